@@ -1,23 +1,144 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::config::Config;
+use self::authorized_keys::AuthorizedKeys;
+use self::key_usage::KeyUsageRecorder;
+use self::revocation::RevocationList;
+use crate::config::{ClientKey, Config};
+use crate::url_signer::{UrlSigner, SIGNED_URL_TOKEN_PARAM};
 use axum::{
-    http::{self, Request, StatusCode},
+    http::{self, Method, Request, StatusCode},
     middleware::Next,
     response::Response,
 };
 use eyre::eyre;
 use eyre::Result;
 use jwt_simple::prelude::*;
+use tracing::warn;
+
+pub mod authorized_keys;
+pub mod key_usage;
+pub mod revocation;
+
+/// Tokens may expire at most this long after they are used unless `max_token_lifetime_mins` is
+/// set in the config
+const DEFAULT_MAX_TOKEN_LIFETIME_MINS: u64 = 60;
+
+/// Allows for client clocks that are slightly ahead of the server's when checking token lifetimes
+const CLOCK_SKEW_SECS: u64 = 60;
 
 #[derive(Serialize, Deserialize)]
-struct CustomClaims {
-    slicing: bool,
+pub struct CustomClaims {
+    pub slicing: bool,
+    /// Limits the token to some of it's key's scopes. Tokens without scopes have every scope of
+    /// their key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Scope>>,
+}
+
+/// The actions that a client key can be authorized for
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum Scope {
+    /// Create jobs, move them in the queue and use engines, profiles and model analysis to do so
+    SubmitJobs,
+    /// Read the jobs created with the same key
+    ReadOwnJobs,
+    /// Read every job
+    ReadAllJobs,
+    /// Manage slicing engines. Engines are currently only managed from the command line so this
+    /// only grants listing them.
+    ManageEngines,
+    /// Create and delete stored slicing profiles
+    ManageProfiles,
+    /// Every other scope, and moving and cancelling the jobs of every client key
+    Admin,
+}
+
+impl Scope {
+    pub fn all() -> Vec<Scope> {
+        vec![
+            Scope::SubmitJobs,
+            Scope::ReadOwnJobs,
+            Scope::ReadAllJobs,
+            Scope::ManageEngines,
+            Scope::ManageProfiles,
+            Scope::Admin,
+        ]
+    }
+
+    /// The scopes of keys that are added without any. Keys only have access to their own jobs
+    /// and cannot change the server's stored profiles or engines unless they are granted the
+    /// other scopes explicitly.
+    pub fn defaults() -> Vec<Scope> {
+        vec![Scope::SubmitJobs, Scope::ReadOwnJobs]
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Scope::SubmitJobs => "submit_jobs",
+            Scope::ReadOwnJobs => "read_own_jobs",
+            Scope::ReadAllJobs => "read_all_jobs",
+            Scope::ManageEngines => "manage_engines",
+            Scope::ManageProfiles => "manage_profiles",
+            Scope::Admin => "admin",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// The scopes of the request: the client key's scopes, limited to those listed in the JWT
+#[derive(Clone, Debug)]
+pub struct Scopes(pub Vec<Scope>);
+
+impl Scopes {
+    /// Whether the request has the scope. Admins have every scope.
+    pub fn contains(&self, scope: Scope) -> bool {
+        self.0.contains(&scope) || self.0.contains(&Scope::Admin)
+    }
+}
+
+/// Only allows a GraphQL field to be resolved if the request has at least one of the scopes
+pub struct ScopeGuard(pub &'static [Scope]);
+
+#[async_trait::async_trait]
+impl async_graphql::Guard for ScopeGuard {
+    async fn check(&self, ctx: &async_graphql::Context<'_>) -> async_graphql::Result<()> {
+        let is_allowed = ctx.data_opt::<Scopes>().map_or(false, |scopes| {
+            self.0.iter().any(|scope| scopes.contains(*scope))
+        });
+
+        if !is_allowed {
+            let scopes = self
+                .0
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" or ");
+
+            return Err(format!("Forbidden: requires the {scopes} scope").into());
+        }
+
+        Ok(())
+    }
 }
 
+/// The id (JWT kid) of the client key that authorized the request. Requests authorized by a
+/// signed URL do not have one.
+#[derive(Clone, Debug)]
+pub struct ClientKeyId(pub String);
+
 pub async fn auth<B>(
     config: Arc<Config>,
-    req: Request<B>,
+    authorized_keys: AuthorizedKeys,
+    url_signer: UrlSigner,
+    revocations: RevocationList,
+    key_usage: KeyUsageRecorder,
+    mut req: Request<B>,
     next: Next<B>,
 ) -> Result<Response, StatusCode> {
     let auth_header = req
@@ -25,13 +146,88 @@ pub async fn auth<B>(
         .get(http::header::AUTHORIZATION)
         .and_then(|header| header.to_str().ok());
 
-    match auth_header {
-        Some(auth_header) if token_is_valid(config, auth_header).is_ok() => Ok(next.run(req).await),
-        _ => Err(StatusCode::UNAUTHORIZED),
+    let token = match auth_header {
+        Some(auth_header) => {
+            let authorized_keys = authorized_keys.current().await.map_err(|err| {
+                warn!("Unable to read the authorized keys: {:?}", err);
+                StatusCode::UNAUTHORIZED
+            })?;
+
+            Some(
+                token_is_valid(config, &authorized_keys, auth_header)
+                    .map_err(|_| StatusCode::UNAUTHORIZED)?,
+            )
+        }
+        // Requests without a JWT are only authorized by a valid signed URL
+        None => {
+            signed_url_is_valid(&url_signer, &req).map_err(|_| StatusCode::UNAUTHORIZED)?;
+            None
+        }
+    };
+
+    if let Some(token) = token {
+        let is_revoked = revocations
+            .is_revoked(
+                &token.key_id,
+                &token.public_key_pem,
+                token.token_id.as_deref(),
+            )
+            .await
+            .map_err(|err| {
+                warn!("Unable to read the revocation list: {:?}", err);
+                StatusCode::UNAUTHORIZED
+            })?;
+
+        if is_revoked {
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+
+        key_usage.record(&token.key_id);
+
+        req.extensions_mut().insert(ClientKeyId(token.key_id));
+        req.extensions_mut().insert(token.scopes);
+    }
+
+    Ok(next.run(req).await)
+}
+
+fn signed_url_is_valid<B>(url_signer: &UrlSigner, req: &Request<B>) -> Result<()> {
+    // Signed URLs are read-only
+    if req.method() != Method::GET {
+        return Err(eyre!("Signed URLs only authorize GET requests"));
     }
+
+    let token = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| {
+            param
+                .strip_prefix(SIGNED_URL_TOKEN_PARAM)?
+                .strip_prefix('=')
+        })
+        .ok_or_else(|| eyre!("Missing signed URL token"))?;
+
+    url_signer.verify(req.uri().path(), token)
+}
+
+/// A bearer token that is signed by an authorized client key
+struct VerifiedToken {
+    key_id: String,
+    /// The public key that the token's signature was verified with
+    public_key_pem: String,
+    /// The JWT's id (jti), if any
+    token_id: Option<String>,
+    scopes: Scopes,
 }
 
-fn token_is_valid(config: Arc<Config>, auth_header: &str) -> Result<()> {
+/// Verifies the bearer token's signature, expiry and claims. Revocations are checked separately.
+fn token_is_valid(
+    config: Arc<Config>,
+    authorized_keys: &HashMap<String, ClientKey>,
+    auth_header: &str,
+) -> Result<VerifiedToken> {
     // Verify that the authorization header contains a bearer token
     const BEARER: &'static str = "Bearer ";
 
@@ -49,25 +245,71 @@ fn token_is_valid(config: Arc<Config>, auth_header: &str) -> Result<()> {
         .key_id()
         .ok_or_else(|| eyre!("Missing JWT key id (kid)"))?;
 
-    let public_key_pem = &config
-        .authorized_keys
+    let client_key = authorized_keys
         .get(key_id)
-        .ok_or_else(|| eyre!("Unauthorized JWT key id"))?
-        .public_key_pem;
+        .ok_or_else(|| eyre!("Unauthorized JWT key id"))?;
+
+    if let Some(expires_at) = client_key.expires_at {
+        if expires_at <= chrono::Utc::now() {
+            return Err(eyre!("Client key expired"));
+        }
+    }
 
-    let public_key = ES256KeyPair::from_pem(&public_key_pem)
+    let public_key = ES256KeyPair::from_pem(&client_key.public_key_pem)
         .map_err(|_| eyre!("Invalid public key configured"))?
         .public_key();
 
+    // The expiry (exp) and not before (nbf) times are checked if they are present
+    let options = VerificationOptions {
+        required_key_id: Some(key_id.to_owned()),
+        ..Default::default()
+    };
+
     let claims = public_key
-        .verify_token::<CustomClaims>(&bearer_token, None)
+        .verify_token::<CustomClaims>(&bearer_token, Some(options))
         .map_err(|_| eyre!("Invalid JWT"))?;
 
+    // Long lived tokens cannot be cut short other than by revoking them so tokens must expire
+    // soon after they are used
+    let expires_at = claims
+        .expires_at
+        .ok_or_else(|| eyre!("JWTs must have an expiry time (exp)"))?;
+
+    let max_lifetime_mins = config
+        .max_token_lifetime_mins
+        .unwrap_or(DEFAULT_MAX_TOKEN_LIFETIME_MINS);
+    let max_expires_at = Clock::now_since_epoch()
+        + Duration::from_mins(max_lifetime_mins)
+        + Duration::from_secs(CLOCK_SKEW_SECS);
+
+    if expires_at > max_expires_at {
+        return Err(eyre!("JWTs must expire within {max_lifetime_mins} minutes"));
+    }
+
     if claims.custom.slicing != true {
         return Err(eyre!(
             "slicing must be set to true in the JWT to authorize slicing server access"
         ));
     }
 
-    Ok(())
+    // Tokens can narrow their key's scopes but not widen them
+    let scopes = client_key
+        .scopes
+        .iter()
+        .copied()
+        .filter(|scope| {
+            claims
+                .custom
+                .scopes
+                .as_ref()
+                .map_or(true, |token_scopes| token_scopes.contains(scope))
+        })
+        .collect();
+
+    Ok(VerifiedToken {
+        key_id: key_id.to_owned(),
+        public_key_pem: client_key.public_key_pem.clone(),
+        token_id: claims.jwt_id,
+        scopes: Scopes(scopes),
+    })
 }
@@ -0,0 +1,76 @@
+use eyre::Result;
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::fs;
+
+use crate::config::{ClientKey, Config};
+
+/// The server's view of the client keys in config.toml. The file is re-read whenever it is
+/// modified so that rotated, expired and removed keys take effect without restarting the server.
+///
+/// Keys that were added after the server started are ignored until it is restarted because the
+/// job queue and quotas read each key's limits from the config that the server started with.
+#[derive(Clone)]
+pub struct AuthorizedKeys {
+    path: PathBuf,
+    /// The ids of the keys that the server was started with
+    started_with: Arc<HashSet<String>>,
+    cache: Arc<Mutex<CachedKeys>>,
+}
+
+struct CachedKeys {
+    /// The modification time of the file when it was read, or None if it has not been read
+    modified: Option<SystemTime>,
+    keys: Arc<HashMap<String, ClientKey>>,
+}
+
+impl AuthorizedKeys {
+    /// Starts from the keys of the config that the server was started with
+    pub fn new(path: PathBuf, config: &Config) -> Self {
+        let keys = config.authorized_keys.clone();
+
+        Self {
+            path,
+            started_with: Arc::new(keys.keys().cloned().collect()),
+            cache: Arc::new(Mutex::new(CachedKeys {
+                modified: None,
+                keys: Arc::new(keys),
+            })),
+        }
+    }
+
+    /// The currently authorized client keys by id
+    pub async fn current(&self) -> Result<Arc<HashMap<String, ClientKey>>> {
+        let modified = match fs::metadata(&self.path).await {
+            Ok(metadata) => Some(metadata.modified()?),
+            // Without a config file the keys that the server was started with are kept
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        let is_stale = modified.is_some() && self.cache().modified != modified;
+        if is_stale {
+            let config: Config = toml::from_str(&fs::read_to_string(&self.path).await?)?;
+
+            let keys = config
+                .authorized_keys
+                .into_iter()
+                .filter(|(id, _)| self.started_with.contains(id))
+                .collect();
+
+            *self.cache() = CachedKeys {
+                modified,
+                keys: Arc::new(keys),
+            };
+        }
+
+        Ok(Arc::clone(&self.cache().keys))
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, CachedKeys> {
+        self.cache.lock().expect("Authorized keys lock poisoned")
+    }
+}
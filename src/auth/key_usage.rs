@@ -0,0 +1,129 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+use crate::config::directories;
+
+/// How often the recorded usage is saved while the server is running
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When each client key was last used to authorize a request
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct KeyUsage {
+    #[serde(default)]
+    pub last_used: HashMap<String, DateTime<Utc>>,
+}
+
+impl KeyUsage {
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(directories()?.config_dir().join("key_usage.toml"))
+    }
+
+    /// Reads the key usage, returning no usage if the file does not exist
+    pub async fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path).await {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?).await?;
+
+        Ok(())
+    }
+}
+
+/// Records when each client key is used by the running server. Usage is merged into
+/// key_usage.toml by the flush task and when the server shuts down.
+#[derive(Clone)]
+pub struct KeyUsageRecorder {
+    path: PathBuf,
+    last_used: Arc<DashMap<String, DateTime<Utc>>>,
+    /// True if usage has been recorded since it was last saved
+    is_dirty: Arc<AtomicBool>,
+}
+
+impl KeyUsageRecorder {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            last_used: Default::default(),
+            is_dirty: Default::default(),
+        }
+    }
+
+    /// Records that the client key authorized a request just now
+    pub fn record(&self, key_id: &str) {
+        self.last_used.insert(key_id.to_owned(), Utc::now());
+        self.is_dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// When the client key was last used since the server started
+    pub fn last_used(&self, key_id: &str) -> Option<DateTime<Utc>> {
+        self.last_used.get(key_id).map(|last_used| *last_used)
+    }
+
+    /// Saves the recorded usage every SAVE_INTERVAL until the task is aborted. Requests only
+    /// update the in-memory usage so they are not slowed down by writing the file.
+    pub fn spawn_flush_task(&self) -> JoinHandle<()> {
+        let recorder = self.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAVE_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(err) = recorder.flush().await {
+                    warn!("Unable to save client key usage: {:?}", err);
+                }
+            }
+        })
+    }
+
+    /// Saves the usage recorded since the last flush, if any
+    pub async fn flush(&self) -> Result<()> {
+        if !self.is_dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let result = self.save().await;
+        if result.is_err() {
+            // Retry on the next flush
+            self.is_dirty.store(true, Ordering::SeqCst);
+        }
+
+        result
+    }
+
+    /// Merges the recorded usage into the key usage file, keeping the usage of keys that have not
+    /// been used since the server started
+    async fn save(&self) -> Result<()> {
+        let mut usage = KeyUsage::load(&self.path).await?;
+
+        for entry in self.last_used.iter() {
+            let last_used = usage
+                .last_used
+                .entry(entry.key().clone())
+                .or_insert(*entry.value());
+
+            *last_used = (*last_used).max(*entry.value());
+        }
+
+        usage.save(&self.path).await
+    }
+}
@@ -0,0 +1,114 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::fs;
+
+use crate::config::directories;
+
+/// Client keys and individual tokens that the server no longer accepts
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Revocations {
+    /// Revoked client key ids. Every token signed by these keys is rejected.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+    /// The public keys of key pairs that were replaced by rotating a client key. Tokens signed by
+    /// a retired key pair are rejected even though the key's id is still authorized.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub public_keys: Vec<String>,
+    /// Revoked token ids, ie. the JWT `jti` claim, by the id of the client key that signed them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tokens: BTreeMap<String, Vec<String>>,
+}
+
+impl Revocations {
+    pub fn default_path() -> Result<PathBuf> {
+        Ok(directories()?.config_dir().join("revocations.toml"))
+    }
+
+    /// Reads the revocations, returning an empty list if the file does not exist
+    pub async fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path).await {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?).await?;
+
+        Ok(())
+    }
+}
+
+/// The server's view of revocations.toml. The file is re-read whenever it is modified so that
+/// revocations take effect without restarting the server.
+#[derive(Clone)]
+pub struct RevocationList {
+    path: PathBuf,
+    cache: Arc<Mutex<CachedRevocations>>,
+}
+
+#[derive(Default)]
+struct CachedRevocations {
+    /// The modification time of the file when it was read, or None if it did not exist
+    modified: Option<SystemTime>,
+    revocations: Revocations,
+}
+
+impl RevocationList {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            cache: Default::default(),
+        }
+    }
+
+    /// Returns true if the client key, it's key pair or the token has been revoked
+    pub async fn is_revoked(
+        &self,
+        key_id: &str,
+        public_key_pem: &str,
+        token_id: Option<&str>,
+    ) -> Result<bool> {
+        let modified = match fs::metadata(&self.path).await {
+            Ok(metadata) => Some(metadata.modified()?),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        let is_stale = self.cache().modified != modified;
+        if is_stale {
+            let revocations = Revocations::load(&self.path).await?;
+
+            *self.cache() = CachedRevocations {
+                modified,
+                revocations,
+            };
+        }
+
+        let cache = self.cache();
+        let revocations = &cache.revocations;
+
+        let is_revoked = revocations.keys.iter().any(|id| id == key_id)
+            || revocations
+                .public_keys
+                .iter()
+                .any(|pem| pem.trim() == public_key_pem.trim())
+            || token_id
+                .zip(revocations.tokens.get(key_id))
+                .map_or(false, |(token_id, revoked)| {
+                    revoked.iter().any(|id| id == token_id)
+                });
+
+        Ok(is_revoked)
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, CachedRevocations> {
+        self.cache.lock().expect("Revocation list lock poisoned")
+    }
+}
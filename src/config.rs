@@ -1,3 +1,8 @@
+use crate::auth::Scope;
+use crate::engine::InvertRotation;
+use crate::gcode::output_format::OutputFormat;
+use crate::profile::settings::ProfileFormat;
+use chrono::{DateTime, Utc};
 use directories::ProjectDirs;
 use eyre::eyre;
 use eyre::Result;
@@ -6,24 +11,227 @@ use self_host_space::KeyManager;
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use tokio::fs;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ClientKey {
     pub id: String,
     pub label: String,
     pub public_key_pem: String,
+    /// When the key's current key pair was created, ie. when it was added or last rotated. Not
+    /// known for keys created before this was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// The key is no longer authorized after this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// What the key is authorized to do. Keys created before scopes were introduced have the
+    /// default scopes.
+    #[serde(default = "Scope::defaults")]
+    pub scopes: Vec<Scope>,
+    #[serde(default, skip_serializing_if = "ClientLimits::is_unlimited")]
+    pub limits: ClientLimits,
+}
+
+/// Limits on the jobs of a single client key. Each limit is unlimited if it is not set.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ClientLimits {
+    /// The maximum number of the key's jobs that can be waiting in the queue
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_queued_jobs: Option<usize>,
+    /// The maximum number of the key's jobs that can be sliced at the same time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_jobs: Option<usize>,
+    /// The maximum combined size of a job's uploaded models and config, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_upload_bytes: Option<u64>,
+    /// The maximum number of jobs the key can create in any 24 hour period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_jobs_per_day: Option<usize>,
+}
+
+impl ClientLimits {
+    fn is_unlimited(&self) -> bool {
+        self.max_queued_jobs.is_none()
+            && self.max_concurrent_jobs.is_none()
+            && self.max_upload_bytes.is_none()
+            && self.max_jobs_per_day.is_none()
+    }
 }
 
 #[derive(Serialize, Deserialize, Default)]
 pub struct Config {
+    /// The number of jobs that are sliced at the same time. Defaults to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_jobs: Option<usize>,
+    /// The furthest in the future that a JWT's expiry time (exp) can be, in minutes. Defaults to
+    /// 60.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_token_lifetime_mins: Option<u64>,
     /// A mapping of JWT key ids to public key PEMs which are authorized to access the slicing server
     pub authorized_keys: HashMap<String, ClientKey>,
+    /// The transports the slicing server accepts connections on
+    #[serde(default)]
+    pub listeners: ListenerConfig,
+    /// Additional slicing engines which are made available alongside the built-in engines
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub engines: Vec<CustomEngineConfig>,
+    /// Post-processors that are run in order on the GCode of every job once it has been sliced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_processors: Vec<PostProcessorConfig>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListenerConfig {
+    /// Accept connections via the self-host.space WebTransport server
+    pub self_host_space: bool,
+    /// Accept plain HTTP connections on this address, eg. "0.0.0.0:8080"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<SocketAddr>,
+    /// Accept HTTPS connections using a configured certificate and private key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub https: Option<HttpsListenerConfig>,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            self_host_space: true,
+            http: None,
+            https: None,
+        }
+    }
+}
+
+impl ListenerConfig {
+    pub fn is_empty(&self) -> bool {
+        !self.self_host_space && self.http.is_none() && self.https.is_none()
+    }
+
+    /// Returns an error if no listeners are enabled, in which case clients could not connect
+    pub fn ensure_enabled(&self) -> Result<()> {
+        if self.is_empty() {
+            return Err(eyre!(
+                "No listeners enabled. Enable at least one listener in the [listeners] section of config.toml"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HttpsListenerConfig {
+    /// The address to listen on, eg. "0.0.0.0:8443"
+    pub bind: SocketAddr,
+    /// Path to the PEM encoded certificate chain
+    pub cert_path: PathBuf,
+    /// Path to the PEM encoded private key
+    pub key_path: PathBuf,
+}
+
+/// A slicing engine declared in config.toml
+///
+/// Exactly one of `bin_path` (a manually installed engine) or `github_repo` (an engine installed
+/// from Github releases via `slicing-server engines install`) must be set.
+#[derive(Serialize, Deserialize)]
+pub struct CustomEngineConfig {
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub home_page: Option<String>,
+    /// The path to a manually installed engine binary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bin_path: Option<PathBuf>,
+    /// The engine URL that jobs use to select a manually installed engine. Defaults to
+    /// "local://{id}".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_url: Option<String>,
+    /// The Github repo to install releases from, eg. "prusa3d/PrusaSlicer"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub github_repo: Option<String>,
+    /// A regex matching the name of the release asset to install, eg. '-x64-GTK3.*\.AppImage$'
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset_pattern: Option<String>,
+    /// The engine's arguments. `{src}`, `{config}` and `{gcode}` are replaced with the paths of
+    /// the model, slicing profile and GCode output respectively. A `{src}` argument on it's own is
+    /// expanded to every model of a multi-object job. `{output_format}` is replaced with the
+    /// extension of the format to write, eg. "bgcode".
+    pub args: Vec<String>,
+    /// Output formats other than plain GCode that the engine can write itself, eg. ["bgcode"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output_formats: Vec<OutputFormat>,
+    /// The file formats accepted by the engine, eg. [".stl", ".obj"]
+    pub accepted_file_formats: Vec<String>,
+    #[serde(default = "default_allows_positioning")]
+    pub allows_positioning: bool,
+    /// Engine-specific transform applied to meshes before slicing, in the same column-major
+    /// layout as the GraphQL `transformMat4` field. Defaults to the identity matrix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform_mat4: Option<[[f32; 4]; 4]>,
+    /// A regex matched against each line of the engine's output. The first capture group is
+    /// parsed as the percent complete, eg. '^(\d+) => '.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress_regex: Option<String>,
+    /// The format of the engine's slicing profiles, either "ini" (the default) or "json"
+    #[serde(default)]
+    pub profile_format: ProfileFormat,
+    #[serde(default)]
+    pub invert_rotation: InvertRotation,
+}
+
+fn default_allows_positioning() -> bool {
+    true
+}
+
+/// A GCode post-processor declared in config.toml
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PostProcessorConfig {
+    /// Identifies the post-processor in job errors. Defaults to the post-processor's type.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub kind: PostProcessorKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PostProcessorKind {
+    /// Inserts an M73 progress update at the start of each layer
+    Progress,
+    /// Inserts GCode before each of the given layers, counting from 1
+    PauseAtLayer {
+        layers: Vec<usize>,
+        #[serde(default = "default_pause_gcode")]
+        gcode: String,
+    },
+    /// Replaces each match of a regex in every line, eg. to rewrite M600 for other firmware.
+    /// Capture groups can be referenced in the replacement as `$1`.
+    Replace {
+        pattern: String,
+        replacement: String,
+    },
+    /// Adds comment lines to the start of the GCode
+    Header { comments: Vec<String> },
+    /// Runs a command as the slicing worker, in the same sandbox as the slicer. `{gcode}` is
+    /// replaced with the path of the GCode file, which the command should modify in place. The
+    /// path is appended to the arguments if `{gcode}` is not used.
+    Command {
+        command: PathBuf,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+fn default_pause_gcode() -> String {
+    "M600".to_owned()
 }
 
 impl Config {
-    fn config_path() -> Result<PathBuf> {
+    pub fn config_path() -> Result<PathBuf> {
         let dirs = directories()?;
         let config_path = dirs.config_dir().join("config.toml");
         Ok(config_path)
@@ -53,19 +261,12 @@ impl Config {
         &mut self,
         server_keys: &KeyManager,
         label: String,
+        scopes: Vec<Scope>,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<(String, &ClientKey)> {
         let id = nanoid::nanoid!();
 
-        let key_pair = ES256KeyPair::generate();
-
-        let signing_key_pem = key_pair
-            .to_pem()
-            .map_err(|_| eyre!("Failed to generate PEM for client signing key"))?;
-
-        let public_key_pem = key_pair
-            .public_key()
-            .to_pem()
-            .map_err(|_| eyre!("Failed to generate PEM for client public key"))?;
+        let (signing_key_pem, public_key_pem) = generate_key_pair()?;
 
         if self.authorized_keys.contains_key(&id) {
             return Err(eyre!("authorized_keys hash collision"))?;
@@ -75,18 +276,95 @@ impl Config {
             id,
             label,
             public_key_pem,
+            created_at: Some(Utc::now()),
+            expires_at,
+            scopes,
+            limits: ClientLimits::default(),
         });
 
-        let invite_json = serde_json::json!({
-            "id": client_key.id,
-            "sk": signing_key_pem,
-            "server_pk": server_keys.server_identity.identity_public_key,
-        });
-
-        let invite_token = bs58::encode(serde_json::to_string(&invite_json)?).into_string();
+        let invite_token = invite_token(server_keys, &client_key.id, &signing_key_pem)?;
 
         Ok((invite_token, client_key))
     }
+
+    /// Replaces a key's key pair, keeping it's id, label, scopes and limits. The key's expiry is
+    /// only changed if a new one is given. Returns the new invite token and the old key pair's
+    /// public key so that it can be revoked.
+    pub fn rotate_key(
+        &mut self,
+        server_keys: &KeyManager,
+        id: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(String, String, &ClientKey)> {
+        let client_key = self
+            .authorized_keys
+            .get_mut(id)
+            .ok_or_else(|| eyre!("Key (id: \"{}\") not found", id))?;
+
+        let (signing_key_pem, public_key_pem) = generate_key_pair()?;
+
+        let retired_public_key_pem =
+            std::mem::replace(&mut client_key.public_key_pem, public_key_pem);
+        client_key.created_at = Some(Utc::now());
+        if expires_at.is_some() {
+            client_key.expires_at = expires_at;
+        }
+
+        let invite_token = invite_token(server_keys, &client_key.id, &signing_key_pem)?;
+
+        Ok((invite_token, retired_public_key_pem, client_key))
+    }
+}
+
+/// Generates a client key pair, returning the PEMs of it's signing key and public key
+fn generate_key_pair() -> Result<(String, String)> {
+    let key_pair = ES256KeyPair::generate();
+
+    let signing_key_pem = key_pair
+        .to_pem()
+        .map_err(|_| eyre!("Failed to generate PEM for client signing key"))?;
+
+    let public_key_pem = key_pair
+        .public_key()
+        .to_pem()
+        .map_err(|_| eyre!("Failed to generate PEM for client public key"))?;
+
+    Ok((signing_key_pem, public_key_pem))
+}
+
+/// The token that clients are given to connect with a key: bs58 encoded JSON
+#[derive(Serialize, Deserialize)]
+pub struct InviteToken {
+    /// The client key's id
+    pub id: String,
+    /// The PEM of the client key's signing key
+    pub sk: String,
+    /// The server's self-host.space identity public key
+    pub server_pk: serde_json::Value,
+}
+
+impl InviteToken {
+    pub fn decode(token: &str) -> Result<Self> {
+        let json = bs58::decode(token.trim())
+            .into_vec()
+            .map_err(|_| eyre!("Invite tokens must be bs58 encoded"))?;
+
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    pub fn encode(&self) -> Result<String> {
+        Ok(bs58::encode(serde_json::to_string(self)?).into_string())
+    }
+}
+
+/// Encodes the token that clients are given to connect with a key
+fn invite_token(server_keys: &KeyManager, id: &str, signing_key_pem: &str) -> Result<String> {
+    InviteToken {
+        id: id.to_owned(),
+        sk: signing_key_pem.to_owned(),
+        server_pk: serde_json::to_value(&server_keys.server_identity.identity_public_key)?,
+    }
+    .encode()
 }
 
 pub fn directories() -> Result<ProjectDirs> {
@@ -95,3 +373,77 @@ pub fn directories() -> Result<ProjectDirs> {
 
     Ok(dirs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn listeners_are_parsed() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+                [authorized_keys]
+
+                [listeners]
+                self_host_space = false
+                http = "0.0.0.0:8080"
+
+                [listeners.https]
+                bind = "0.0.0.0:8443"
+                cert_path = "/etc/slicing-server/cert.pem"
+                key_path = "/etc/slicing-server/key.pem"
+            "#,
+        )?;
+
+        let listeners = &config.listeners;
+        assert!(!listeners.self_host_space);
+        assert_eq!(listeners.http, Some("0.0.0.0:8080".parse()?));
+
+        let https = listeners.https.as_ref().expect("Missing HTTPS listener");
+        assert_eq!(https.bind, "0.0.0.0:8443".parse()?);
+        assert_eq!(
+            https.cert_path,
+            PathBuf::from("/etc/slicing-server/cert.pem")
+        );
+        assert_eq!(https.key_path, PathBuf::from("/etc/slicing-server/key.pem"));
+        assert!(listeners.ensure_enabled().is_ok());
+
+        // Only self-host.space is enabled by default
+        let config: Config = toml::from_str("[authorized_keys]\n")?;
+        assert!(config.listeners.self_host_space);
+        assert!(config.listeners.http.is_none());
+        assert!(config.listeners.https.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn configs_without_listeners_are_rejected() -> Result<()> {
+        let config: Config =
+            toml::from_str("[authorized_keys]\n\n[listeners]\nself_host_space = false\n")?;
+
+        assert!(config.listeners.is_empty());
+        assert!(config.listeners.ensure_enabled().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn invite_tokens_can_be_decoded() -> Result<()> {
+        let invite = InviteToken {
+            id: "test_key".to_owned(),
+            sk: "signing key".to_owned(),
+            server_pk: json!("server key"),
+        };
+
+        let decoded = InviteToken::decode(&invite.encode()?)?;
+        assert_eq!(decoded.id, "test_key");
+        assert_eq!(decoded.sk, "signing key");
+        assert_eq!(decoded.server_pk, json!("server key"));
+
+        assert!(InviteToken::decode("not an invite token").is_err());
+
+        Ok(())
+    }
+}
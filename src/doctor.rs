@@ -0,0 +1,473 @@
+use axum_server::tls_rustls::RustlsConfig;
+use chrono::Utc;
+use eyre::Result;
+use jwt_simple::prelude::*;
+use self_host_space::KeyManager;
+use std::net::SocketAddr;
+use std::os::unix::prelude::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::auth::revocation::Revocations;
+use crate::config::{directories, ClientKey, Config, InviteToken, ListenerConfig};
+use crate::engine::EngineRegistry;
+
+/// How long to wait when checking whether a listener is already accepting connections
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Prints the outcome of each check, counting the failures
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn ok(&mut self, message: impl AsRef<str>) {
+        println!("  [ok]   {}", message.as_ref());
+    }
+
+    /// A problem that does not stop the server from working
+    fn warn(&mut self, message: impl AsRef<str>) {
+        println!("  [warn] {}", message.as_ref());
+    }
+
+    fn fail(&mut self, message: impl AsRef<str>) {
+        self.failures += 1;
+        println!("  [FAIL] {}", message.as_ref());
+    }
+
+    fn section(&self, name: &str) {
+        println!("\n{name}:");
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.failures == 0
+    }
+}
+
+/// Decodes an invite token and checks it against the config and the server's identity. The
+/// token's signing key is never printed. Returns true if the token can be used to connect.
+pub async fn inspect_invite_token(config: &Config, token: &str) -> Result<bool> {
+    let mut report = Report::default();
+    report.section("Invite token");
+
+    let invite = match InviteToken::decode(token) {
+        Ok(invite) => {
+            report.ok(format!("Decoded the token for key id \"{}\"", invite.id));
+            invite
+        }
+        Err(err) => {
+            report.fail(format!("Unable to decode the token: {err}"));
+            return Ok(false);
+        }
+    };
+
+    let signing_key = match ES256KeyPair::from_pem(&invite.sk) {
+        Ok(signing_key) => {
+            report.ok("The token's signing key is a valid ES256 key");
+            Some(signing_key)
+        }
+        Err(_) => {
+            report.fail("The token's signing key is not a valid ES256 key");
+            None
+        }
+    };
+
+    match config.authorized_keys.get(&invite.id) {
+        Some(client_key) => {
+            report.ok(format!("The key is authorized as \"{}\"", client_key.label));
+
+            if let Some(signing_key) = signing_key {
+                let public_key = ES256PublicKey::from_pem(&client_key.public_key_pem).ok();
+                let is_current = public_key.map_or(false, |public_key| {
+                    public_key.to_bytes() == signing_key.public_key().to_bytes()
+                });
+
+                if is_current {
+                    report.ok("The token matches the key's current key pair");
+                } else {
+                    report.fail("The token does not match the key's current key pair. The key has been rotated since the token was issued.");
+                }
+            }
+
+            let revocations = Revocations::load(&Revocations::default_path()?).await?;
+
+            for problem in key_problems(client_key, &revocations) {
+                report.fail(problem);
+            }
+        }
+        None => report.fail("The key is not in authorized_keys. It has been removed or it belongs to another server."),
+    }
+
+    let server_keys = match load_server_identity().await? {
+        Some(server_keys) => server_keys,
+        None => {
+            report.fail("There is no server identity yet so the token cannot belong to this server. Start the server to create it's identity.");
+            return Ok(false);
+        }
+    };
+    let server_pk = serde_json::to_value(&server_keys.server_identity.identity_public_key)?;
+
+    if invite.server_pk == server_pk {
+        report.ok("The token matches the server's identity");
+    } else {
+        report.fail("The token was issued by a server with a different identity. The server's identity has changed or the token belongs to another server.");
+    }
+
+    Ok(report.is_healthy())
+}
+
+/// Checks the config, client keys, engines, slicing worker and listeners. Returns true if no
+/// problems were found that would stop the server from working.
+pub async fn run() -> Result<bool> {
+    let mut report = Report::default();
+
+    report.section("Config");
+
+    let config = match Config::load().await {
+        Ok(config) => {
+            report.ok(format!("Parsed {}", Config::config_path()?.display()));
+            config
+        }
+        Err(err) => {
+            report.fail(format!(
+                "Unable to parse {}: {err}",
+                Config::config_path()?.display()
+            ));
+            return Ok(false);
+        }
+    };
+
+    report.section("Client keys");
+    check_keys(&mut report, &config).await?;
+
+    report.section("Engines");
+    check_engines(&mut report, &config)?;
+
+    report.section("Slicing worker");
+    check_slicing_worker(&mut report).await;
+
+    report.section("Listeners");
+    check_listeners(&mut report, &config.listeners).await?;
+
+    println!();
+    if report.is_healthy() {
+        println!("No problems found");
+    } else {
+        println!("{} problem(s) found", report.failures);
+    }
+
+    Ok(report.is_healthy())
+}
+
+async fn check_keys(report: &mut Report, config: &Config) -> Result<()> {
+    if config.authorized_keys.is_empty() {
+        report.warn("No authorized client keys. Use slicing-server keys add [label] to authorize a slicing client.");
+    }
+
+    let revocations = Revocations::load(&Revocations::default_path()?).await?;
+
+    for (id, client_key) in &config.authorized_keys {
+        // Tokens are verified with the key listed under their key id but invite tokens contain
+        // the key's own id
+        if id != &client_key.id {
+            report.fail(format!(
+                "\"{}\" is listed under the id \"{}\" instead of it's own id (\"{}\")",
+                client_key.label, id, client_key.id,
+            ));
+        }
+
+        if ES256PublicKey::from_pem(&client_key.public_key_pem).is_err() {
+            report.fail(format!(
+                "\"{}\" (id: \"{}\") does not have a valid ES256 public key",
+                client_key.label, client_key.id,
+            ));
+            continue;
+        }
+
+        // Expired and revoked keys do not stop the server from working
+        let problems = key_problems(client_key, &revocations);
+        if problems.is_empty() {
+            report.ok(format!(
+                "\"{}\" (id: \"{}\") is valid",
+                client_key.label, client_key.id,
+            ));
+        }
+        for problem in problems {
+            report.warn(problem);
+        }
+
+        // Revoked tokens do not stop the key from signing new ones
+        let revoked_tokens = revocations.tokens.get(&client_key.id).map_or(0, Vec::len);
+        if revoked_tokens > 0 {
+            report.warn(format!(
+                "\"{}\" (id: \"{}\") has {} revoked token(s)",
+                client_key.label, client_key.id, revoked_tokens,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes why the key cannot be used, if it has expired or been revoked
+fn key_problems(client_key: &ClientKey, revocations: &Revocations) -> Vec<String> {
+    let mut problems = vec![];
+
+    if let Some(expires_at) = client_key.expires_at {
+        if expires_at <= Utc::now() {
+            problems.push(format!(
+                "\"{}\" (id: \"{}\") expired at {}. Use slicing-server keys rotate {} --expires [duration] to renew it.",
+                client_key.label, client_key.id, expires_at, client_key.id,
+            ));
+        }
+    }
+
+    if revocations.keys.contains(&client_key.id) {
+        problems.push(format!(
+            "\"{}\" (id: \"{}\") has been revoked",
+            client_key.label, client_key.id,
+        ));
+    }
+
+    let public_key_pem = client_key.public_key_pem.trim();
+    if revocations
+        .public_keys
+        .iter()
+        .any(|revoked_pem| revoked_pem.trim() == public_key_pem)
+    {
+        problems.push(format!(
+            "\"{}\" (id: \"{}\") uses a key pair that has been revoked. Use slicing-server keys rotate {} to issue a new one.",
+            client_key.label, client_key.id, client_key.id,
+        ));
+    }
+
+    problems
+}
+
+fn check_engines(report: &mut Report, config: &Config) -> Result<()> {
+    match EngineRegistry::from_config(config) {
+        Ok(engines) => report.ok(format!("{} engines are available", engines.all().len())),
+        Err(err) => report.fail(format!("{err:#}")),
+    }
+
+    for engine_config in &config.engines {
+        if let Some(bin_path) = &engine_config.bin_path {
+            check_executable(report, &format!("\"{}\"", engine_config.id), bin_path);
+        }
+    }
+
+    // Engines installed from Github releases are saved to the engines directory as AppImages
+    let engines_dir = directories()?.data_dir().join("engines");
+    let mut installed = 0;
+
+    if let Ok(repo_dirs) = std::fs::read_dir(&engines_dir) {
+        for repo_dir in repo_dirs.flatten() {
+            // Each engine is installed to a directory named after it's Github repo
+            if !repo_dir.path().is_dir() {
+                report.warn(format!(
+                    "Unexpected file in the engines directory: {}",
+                    repo_dir.path().display()
+                ));
+                continue;
+            }
+
+            for entry in std::fs::read_dir(repo_dir.path())?.flatten() {
+                let path = entry.path();
+
+                if path
+                    .extension()
+                    .map_or(false, |extension| extension == "AppImage")
+                {
+                    installed += 1;
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    check_executable(report, &name, &path);
+                }
+            }
+        }
+    }
+
+    if installed == 0
+        && config
+            .engines
+            .iter()
+            .all(|engine| engine.bin_path.is_none())
+    {
+        report.warn(
+            "No engines are installed. Use slicing-server engines install [engine] to install one.",
+        );
+    }
+
+    Ok(())
+}
+
+fn check_executable(report: &mut Report, name: &str, path: &Path) {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.permissions().mode() & 0o111 != 0 => {
+            report.ok(format!("{name} is installed at {}", path.display()));
+        }
+        Ok(_) => report.fail(format!("{name} is not executable ({})", path.display())),
+        Err(err) => report.fail(format!(
+            "{name} is not installed at {}: {err}",
+            path.display()
+        )),
+    }
+}
+
+/// Engines and post-processors are run as the slicing-worker user
+async fn check_slicing_worker(report: &mut Report) {
+    let output = tokio::process::Command::new("id")
+        .arg("-u")
+        .arg("slicing-worker")
+        .output()
+        .await;
+
+    match output {
+        Ok(output) if output.status.success() => {
+            report.ok("The slicing-worker user exists");
+        }
+        _ => report.fail("The slicing-worker user does not exist. Engines are run as this user so that they are sandboxed from the server."),
+    }
+}
+
+async fn check_listeners(report: &mut Report, listeners: &ListenerConfig) -> Result<()> {
+    if listeners.self_host_space {
+        match load_server_identity().await {
+            Ok(Some(_)) => report.ok("self-host.space: the server's identity is loaded"),
+            Ok(None) => report.warn(
+                "self-host.space: there is no server identity yet. It will be created when the server starts.",
+            ),
+            Err(err) => report.fail(format!(
+                "self-host.space: unable to load the server's identity: {err}"
+            )),
+        }
+    }
+
+    if let Some(addr) = listeners.http {
+        check_address(report, "HTTP", addr).await;
+    }
+
+    if let Some(https) = &listeners.https {
+        match RustlsConfig::from_pem_file(&https.cert_path, &https.key_path).await {
+            Ok(_) => report.ok("HTTPS: the certificate and private key are valid"),
+            Err(err) => report.fail(format!(
+                "HTTPS: unable to load the certificate ({:?}) or private key ({:?}): {err}",
+                https.cert_path, https.key_path,
+            )),
+        }
+
+        check_address(report, "HTTPS", https.bind).await;
+    }
+
+    if listeners.is_empty() {
+        report.fail("No listeners are enabled so clients cannot connect");
+    }
+
+    Ok(())
+}
+
+/// Checks that the address is either already served (eg. by a running slicing server) or free to
+/// listen on
+async fn check_address(report: &mut Report, name: &str, addr: SocketAddr) {
+    let connection = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await;
+
+    if let Ok(Ok(_)) = connection {
+        report.ok(format!("{name}: {addr} is accepting connections"));
+        return;
+    }
+
+    match TcpListener::bind(addr).await {
+        Ok(_) => report.ok(format!("{name}: {addr} is free to listen on")),
+        Err(err) => report.fail(format!("{name}: unable to listen on {addr}: {err}")),
+    }
+}
+
+/// Loads the server's self-host.space identity without creating one if it does not exist yet.
+///
+/// KeyManager creates any missing keys when it loads them so it is run against a copy of the
+/// config directory. If that creates new files then the server does not have an identity yet.
+async fn load_server_identity() -> Result<Option<KeyManager>> {
+    let config_dir = directories()?.config_dir().to_owned();
+    let copy = tempfile::tempdir()?;
+
+    if config_dir.is_dir() {
+        copy_dir(&config_dir, copy.path())?;
+    }
+
+    let files_before = count_files(copy.path())?;
+    let server_keys = KeyManager::load_or_create(copy.path()).await?;
+
+    if count_files(copy.path())? != files_before {
+        return Ok(None);
+    }
+
+    Ok(Some(server_keys))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let to = to.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir_all(&to)?;
+            copy_dir(&entry.path(), &to)?;
+        } else {
+            std::fs::copy(entry.path(), to)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn count_files(dir: &Path) -> Result<usize> {
+    let mut count = 0;
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+
+        count += if entry.file_type()?.is_dir() {
+            count_files(&entry.path())?
+        } else {
+            1
+        };
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::config::ClientLimits;
+
+    #[test]
+    fn revoked_key_pairs_are_reported() {
+        let client_key = ClientKey {
+            id: "a".to_owned(),
+            label: "Printer A".to_owned(),
+            public_key_pem: "-----BEGIN PUBLIC KEY-----\nabc\n-----END PUBLIC KEY-----\n"
+                .to_owned(),
+            created_at: None,
+            expires_at: None,
+            scopes: Scope::all(),
+            limits: ClientLimits::default(),
+        };
+
+        let mut revocations = Revocations::default();
+        assert!(key_problems(&client_key, &revocations).is_empty());
+
+        // Revoking one of the key's tokens does not stop the key from being used
+        revocations
+            .tokens
+            .insert("a".to_owned(), vec!["token_id".to_owned()]);
+        assert!(key_problems(&client_key, &revocations).is_empty());
+
+        revocations
+            .public_keys
+            .push("-----BEGIN PUBLIC KEY-----\nabc\n-----END PUBLIC KEY-----".to_owned());
+        assert_eq!(key_problems(&client_key, &revocations).len(), 1);
+    }
+}
@@ -1,18 +1,39 @@
-use async_graphql::FieldResult;
+use async_graphql::{Context, FieldResult, ID};
+use async_trait::async_trait;
 use cgmath::Matrix4;
-use lazy_static::lazy_static;
-use std::{collections::HashMap, pin::Pin};
-use tracing::instrument;
+use dashmap::DashMap;
+use eyre::{eyre, Context as _, Result};
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
+use tokio::fs;
+use tokio_util::codec::{FramedRead, LinesCodec};
+use tracing::{info, instrument};
 
-use crate::release::ReleaseConfig;
+use crate::auth::{Scope, ScopeGuard};
+use crate::config::Config;
+use crate::execution_context::ExecutionContext;
+use crate::gcode::output_format::OutputFormat;
+use crate::profile::conversion::ProfileConversion;
+use crate::profile::settings::{ProfileFormat, ProfileSettings};
+use crate::profile::validation::ProfileError;
+use crate::release::Release;
 
 pub mod belt_engine;
+pub mod custom_engine;
 pub mod slic3r;
 
 /// A Slicer or other engine that converts various file formats into GCode
+#[derive(Clone)]
 pub struct Engine {
-    pub id: async_graphql::ID,
-    pub name: &'static str,
+    pub id: ID,
+    pub name: String,
     /// Engine-specific transforms to be applied before any model-specific transforms when
     /// exporting a mesh for slicing.
     pub transform_mat4: Matrix4<f32>,
@@ -21,36 +42,304 @@ pub struct Engine {
     /// True for each axis about which the rotation direction should be visually reversed
     pub invert_rotation: InvertRotation,
     /// The file formats accepted by the engine
-    pub accepted_file_formats: Vec<&'static str>,
+    pub accepted_file_formats: Vec<String>,
     /// The Github releases page (if applicable)
-    pub release_url: Option<&'static str>,
-    pub home_page: &'static str,
-    pub release_config: Pin<Box<dyn ReleaseConfig + Sync + Send + Unpin>>,
-    // pub generate_gcode: &'static (dyn Fn(PathBuf, PathBuf, PathBuf) -> Pin<Box<dyn Stream<Item = Result<f32>>>>
-    //               + Sync),
+    pub release_url: Option<String>,
+    pub home_page: String,
 }
 
 /// True for each axis about which the rotation direction should be visually reversed
-#[derive(Default, async_graphql::SimpleObject)]
+#[derive(Default, Clone, Serialize, Deserialize, async_graphql::SimpleObject)]
+#[serde(default)]
 pub struct InvertRotation {
-    x: bool,
-    y: bool,
-    z: bool,
+    pub x: bool,
+    pub y: bool,
+    pub z: bool,
 }
 
-lazy_static! {
-    pub static ref ENGINES: HashMap<async_graphql::ID, Engine> = {
-        let mut map = HashMap::new();
+/// The largest model that a printer can fit, in millimeters. Axes without a limit are None.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct PrintVolume {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+    pub z: Option<f32>,
+}
+
+/// The interface between the job queue and a slicing engine.
+///
+/// Most engines only need to describe how their releases are resolved and how their binary is
+/// invoked - the default `generate_gcode` runs the binary as the slicing worker user and reports
+/// progress parsed from it's output.
+#[async_trait]
+pub trait SlicingEngine: Send + Sync {
+    /// The engine's metadata, as exposed via GraphQL
+    fn metadata(&self) -> &Engine;
+
+    /// Resolves a release URL (eg. a Github release page) to a release of this engine
+    fn parse_release(&self, url: &str) -> Result<Release>;
 
-        let mut engines = vec![belt_engine::engine()];
-        engines.append(&mut slic3r::engines());
+    /// Resolves the most recent release of this engine
+    async fn latest_release(&self) -> Result<Release>;
 
-        for engine in engines {
-            map.insert(engine.id.clone(), engine);
+    /// True if the engine can slice several model files into a single GCode file
+    fn accepts_multiple_models(&self) -> bool {
+        false
+    }
+
+    /// Output formats other than plain GCode that the release of the engine can write itself.
+    /// Other formats are converted by the server once the engine has generated plain GCode.
+    fn native_output_formats(&self, _release: &Release) -> Vec<OutputFormat> {
+        vec![]
+    }
+
+    /// The command line arguments to invoke the engine's binary with
+    fn args(&self, exec_ctx: &ExecutionContext) -> Vec<OsString>;
+
+    /// The format of the engine's slicing profiles, used to apply per-job setting overrides
+    fn profile_format(&self) -> ProfileFormat {
+        ProfileFormat::Ini
+    }
+
+    /// The keys of every setting the engine supports, used to reject unknown setting overrides.
+    /// Returns None if the engine does not list it's settings, in which case overrides are
+    /// checked against the keys in the job's profile instead.
+    fn setting_keys(&self) -> Option<HashSet<&'static str>> {
+        None
+    }
+
+    /// Checks a profile's settings before it is used to slice a job, normalizing values where the
+    /// engine accepts several equivalent forms.
+    fn validate_profile(&self, _settings: &mut ProfileSettings) -> Vec<ProfileError> {
+        vec![]
+    }
+
+    /// The printer's print volume as described by the profile. Returns None if the profile does
+    /// not describe the printer's size, in which case models are not checked before slicing.
+    fn print_volume(&self, _settings: &ProfileSettings) -> Option<PrintVolume> {
+        None
+    }
+
+    /// The number of extruders described by the profile. Returns None if the profile does not
+    /// describe it's extruders, in which case extruder assignments are not checked before slicing.
+    fn extruder_count(&self, _settings: &ProfileSettings) -> Option<usize> {
+        None
+    }
+
+    /// Converts a profile created for another engine into this engine's settings. Returns None if
+    /// the other engine's profiles cannot be converted.
+    fn convert_profile(
+        &self,
+        _from_engine_id: &str,
+        _settings: &mut ProfileSettings,
+    ) -> Option<ProfileConversion> {
+        None
+    }
+
+    /// Parses a line of the engine's output into a percent complete, if it reports progress
+    fn parse_progress(&self, _line: &str) -> Option<f32> {
+        None
+    }
+
+    /// Locates the GCode generated by the engine
+    async fn find_output(&self, exec_ctx: &ExecutionContext) -> Result<PathBuf> {
+        if exec_ctx.gcode_path.exists() {
+            Ok(exec_ctx.gcode_path.clone())
+        } else {
+            Err(eyre!(
+                "{} did not generate a GCode file",
+                self.metadata().name
+            ))
         }
+    }
+
+    /// Generates the GCode, yielding progress updates via the execution context
+    async fn generate_gcode(&self, exec_ctx: ExecutionContext) -> Result<()> {
+        run_engine_bin(self, exec_ctx).await
+    }
+}
 
-        map
-    };
+/// A command that runs the binary as the slicing worker user. Engines and post-processors are
+/// both sandboxed this way.
+pub fn slicing_worker_command<I, S>(bin_path: &Path, args: I) -> tokio::process::Command
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = tokio::process::Command::new("su");
+
+    cmd.arg("-")
+        .arg("slicing-worker")
+        .arg("-c")
+        .arg(bin_path)
+        .args(args)
+        // Cancelled jobs stop slicing by dropping the command's child process
+        .kill_on_drop(true);
+
+    cmd
+}
+
+/// Runs the engine's binary as the slicing worker and moves it's output to the job's GCode path
+async fn run_engine_bin<E>(engine: &E, exec_ctx: ExecutionContext) -> Result<()>
+where
+    E: SlicingEngine + ?Sized,
+{
+    let bin_path = exec_ctx.release.bin_path_if_downloaded()?;
+
+    let mut cmd = slicing_worker_command(&bin_path, engine.args(&exec_ctx));
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    info!("Slicer command: {:?}", cmd);
+
+    let mut child = cmd.spawn().wrap_err("Slicer error")?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| eyre!("Missing slicer stdout"))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| eyre!("Missing slicer stderr"))?;
+
+    let mut lines = stream::select(
+        FramedRead::new(stdout, LinesCodec::new()),
+        FramedRead::new(stderr, LinesCodec::new()),
+    );
+
+    let mut output = String::new();
+
+    while let Some(line) = lines.next().await {
+        let line = line.wrap_err("Error reading slicer output")?;
+
+        if let Some(percent_complete) = engine.parse_progress(&line) {
+            exec_ctx.co.yield_(Ok(percent_complete)).await;
+        }
+
+        output.push_str(&line);
+        output.push('\n');
+    }
+
+    let status = child.wait().await.wrap_err("Slicer error")?;
+
+    if !status.success() {
+        return Err(eyre!(output));
+    }
+    info!("{}", output);
+
+    let output_path = engine.find_output(&exec_ctx).await?;
+    if output_path != exec_ctx.gcode_path {
+        fs::rename(&output_path, &exec_ctx.gcode_path).await?;
+    }
+
+    exec_ctx.co.yield_(Ok(100.0)).await;
+
+    Ok(())
+}
+
+/// Runs the engine against a job's files, streaming it's percent complete
+pub fn generate_gcode(
+    engine: Arc<dyn SlicingEngine>,
+    release: Release,
+    src_paths: Vec<PathBuf>,
+    config_path: PathBuf,
+    gcode_path: PathBuf,
+    output_format: OutputFormat,
+) -> impl Stream<Item = Result<f32>> {
+    genawaiter::sync::Gen::new(move |co| async move {
+        let co = Arc::new(co);
+
+        let result = engine
+            .generate_gcode(ExecutionContext {
+                release,
+                co: Arc::clone(&co),
+                src_paths,
+                config_path,
+                gcode_path,
+                output_format,
+            })
+            .await;
+
+        if let Err(err) = result {
+            co.yield_(Err(err)).await;
+        }
+    })
+}
+
+pub type Engines = Arc<EngineRegistry>;
+
+/// The slicing engines available to the server. Additional engines can be registered at runtime.
+#[derive(Default)]
+pub struct EngineRegistry {
+    engines: DashMap<ID, Arc<dyn SlicingEngine>>,
+}
+
+impl EngineRegistry {
+    pub fn with_builtin_engines() -> Self {
+        let registry = Self::default();
+
+        registry.register(Arc::new(belt_engine::engine()));
+
+        for engine in slic3r::engines() {
+            registry.register(Arc::new(engine));
+        }
+
+        registry
+    }
+
+    /// The built-in engines along with any custom engines declared in the config
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let registry = Self::with_builtin_engines();
+
+        for engine_config in &config.engines {
+            if registry.get(&engine_config.id.as_str().into()).is_some() {
+                return Err(eyre!(
+                    "Custom engine id {:?} is already in use by another engine",
+                    engine_config.id,
+                ));
+            }
+
+            let engine = custom_engine::CustomEngine::new(engine_config)
+                .wrap_err_with(|| format!("Invalid custom engine: {:?}", engine_config.id))?;
+
+            registry.register(Arc::new(engine));
+        }
+
+        Ok(registry)
+    }
+
+    /// Adds an engine to the registry, replacing any existing engine with the same id
+    pub fn register(&self, engine: Arc<dyn SlicingEngine>) {
+        self.engines.insert(engine.metadata().id.clone(), engine);
+    }
+
+    pub fn get(&self, id: &ID) -> Option<Arc<dyn SlicingEngine>> {
+        self.engines
+            .get(id)
+            .map(|engine| Arc::clone(engine.value()))
+    }
+
+    /// All registered engines, sorted by id
+    pub fn all(&self) -> Vec<Arc<dyn SlicingEngine>> {
+        let mut engines = self
+            .engines
+            .iter()
+            .map(|engine| Arc::clone(engine.value()))
+            .collect::<Vec<_>>();
+
+        engines.sort_by(|a, b| a.metadata().id.0.cmp(&b.metadata().id.0));
+        engines
+    }
+
+    /// Finds the engine that the release URL belongs to
+    pub fn find_release(&self, release_url: &str) -> Result<(Arc<dyn SlicingEngine>, Release)> {
+        self.all()
+            .into_iter()
+            .find_map(|engine| {
+                let release = engine.parse_release(release_url).ok()?;
+                Some((engine, release))
+            })
+            .ok_or_else(|| eyre!("Engine not found for release url: {:?}", release_url))
+    }
 }
 
 #[derive(Default)]
@@ -58,19 +347,26 @@ pub struct EnginesQuery;
 
 #[async_graphql::Object]
 impl EnginesQuery {
-    #[instrument(skip(self))]
-    async fn engines(&self) -> FieldResult<Vec<&'static Engine>> {
-        Ok(ENGINES.values().collect())
+    #[instrument(skip(self, ctx))]
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs, Scope::ManageEngines])")]
+    async fn engines<'ctx>(&self, ctx: &'ctx Context<'_>) -> FieldResult<Vec<Engine>> {
+        let engines: &Engines = ctx.data()?;
+
+        Ok(engines
+            .all()
+            .into_iter()
+            .map(|engine| engine.metadata().clone())
+            .collect())
     }
 }
 
 #[async_graphql::Object]
 impl Engine {
-    async fn id(&self) -> &async_graphql::ID {
+    async fn id(&self) -> &ID {
         &self.id
     }
-    async fn name(&self) -> &'static str {
-        self.name
+    async fn name(&self) -> &str {
+        &self.name
     }
     async fn allows_positioning(&self) -> bool {
         self.allows_positioning
@@ -78,7 +374,7 @@ impl Engine {
     async fn invert_rotation(&self) -> &InvertRotation {
         &self.invert_rotation
     }
-    async fn accepted_file_formats(&self) -> &Vec<&'static str> {
+    async fn accepted_file_formats(&self) -> &Vec<String> {
         &self.accepted_file_formats
     }
     async fn home_page(&self) -> &str {
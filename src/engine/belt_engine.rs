@@ -1,83 +1,170 @@
+use super::{Engine, PrintVolume, SlicingEngine};
 use crate::{
     engine::InvertRotation,
     execution_context::ExecutionContext,
-    release::{LocalRelease, LocalReleaseConfig},
+    profile::{settings::ProfileSettings, validation::ProfileError},
+    release::{LocalReleaseConfig, Release, ReleaseConfig},
 };
+use async_trait::async_trait;
 use cgmath::Matrix4;
-use eyre::{eyre, Context, Result};
-use futures_util::FutureExt;
-use tracing::info;
+use eyre::Result;
+use std::ffi::OsString;
 
 // beltEngine configs were previously stored in crate::paths::etc().join("CR30.cfg.ini")
 
 pub const BELT_ENGINE_URL: &'static str = "https://github.com/Autodrop3d/BeltEngine";
 
-pub fn engine() -> super::Engine {
-    super::Engine {
-        id: "belt_engine".into(),
-        name: "Belt Engine",
-        transform_mat4: Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0),
-        allows_positioning: false,
-        invert_rotation: InvertRotation {
-            x: false,
-            y: false,
-            z: true,
+pub struct BeltEngine {
+    metadata: Engine,
+    release_config: LocalReleaseConfig,
+}
+
+pub fn engine() -> BeltEngine {
+    BeltEngine {
+        metadata: Engine {
+            id: "belt_engine".into(),
+            name: "Belt Engine".to_owned(),
+            transform_mat4: Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0),
+            allows_positioning: false,
+            invert_rotation: InvertRotation {
+                x: false,
+                y: false,
+                z: true,
+            },
+            accepted_file_formats: [".stl", ".obj"].map(String::from).to_vec(),
+            release_url: None,
+            home_page: BELT_ENGINE_URL.to_owned(),
         },
-        accepted_file_formats: vec![".stl", ".obj"],
-        release_url: None,
-        home_page: BELT_ENGINE_URL,
-        release_config: Box::pin(LocalReleaseConfig {
+        release_config: LocalReleaseConfig {
             bin_path: std::env::var("BELT_ENGINE")
                 .unwrap_or("beltengine".to_owned())
                 .into(),
             release_url: BELT_ENGINE_URL.to_owned(),
-            generate_gcode_inner: &|exec_ctx| generate_gcode(exec_ctx).boxed(),
-        }),
+        },
     }
 }
 
-pub async fn generate_gcode(exec_ctx: ExecutionContext<LocalRelease>) -> Result<()> {
-    let ExecutionContext {
-        release,
-        co,
-        src_path,
-        config_path,
-        gcode_path,
-    } = exec_ctx;
-
-    let belt_engine_path = &release.bin_path_if_downloaded()?;
-
-    // Run as slicing worker
-    let mut cmd = tokio::process::Command::new("su");
-
-    cmd.arg("-")
-        .arg("slicing-worker")
-        .arg("-c")
-        // slicer
-        .arg(&belt_engine_path)
-        // slicing profile
-        .arg("-c")
-        .arg(&config_path)
-        // gcode output
-        .arg("-o")
-        .arg(&gcode_path)
-        // load model
-        .arg(&src_path);
-
-    info!("Slicer command: {:?}", cmd);
-
-    let output = cmd.output().await.wrap_err("Slicer error")?;
-
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-
-    let result = if output.status.success() {
-        info!("{}", stderr);
-        Ok(100.0)
-    } else {
-        Err(eyre!(stderr))
-    };
-
-    co.yield_(result).await;
-
-    Ok(())
+#[async_trait]
+impl SlicingEngine for BeltEngine {
+    fn metadata(&self) -> &Engine {
+        &self.metadata
+    }
+
+    fn parse_release(&self, url: &str) -> Result<Release> {
+        self.release_config.parse(url)
+    }
+
+    async fn latest_release(&self) -> Result<Release> {
+        self.release_config.latest_release().await
+    }
+
+    fn accepts_multiple_models(&self) -> bool {
+        true
+    }
+
+    /// Belt Engine profiles are CuraEngine settings. The printer's size and nozzle are optional
+    /// but must be valid when they are set.
+    fn validate_profile(&self, settings: &mut ProfileSettings) -> Vec<ProfileError> {
+        let mut errors = vec![];
+
+        let mut positive = |key: &str| {
+            let value = settings.get(key)?;
+
+            match value.trim().parse::<f32>() {
+                Ok(value) if value > 0.0 => Some(value),
+                Ok(_) => {
+                    errors.push(ProfileError::new(key, "must be greater than 0"));
+                    None
+                }
+                Err(_) => {
+                    errors.push(ProfileError::new(key, format!("invalid value {value:?}")));
+                    None
+                }
+            }
+        };
+
+        positive("machine_width");
+        positive("machine_height");
+        let nozzle_size = positive("machine_nozzle_size");
+        let layer_height = positive("layer_height");
+
+        if let (Some(layer_height), Some(nozzle_size)) = (layer_height, nozzle_size) {
+            if layer_height > nozzle_size {
+                errors.push(ProfileError::new(
+                    "layer_height",
+                    format!("must not exceed the nozzle size ({nozzle_size}mm)"),
+                ));
+            }
+        }
+
+        errors
+    }
+
+    /// Belt printers print along the belt so the Y axis is unbounded. The width and height of the
+    /// gantry limit the X and Z axes.
+    fn print_volume(&self, settings: &ProfileSettings) -> Option<PrintVolume> {
+        let size = |key: &str| {
+            settings
+                .get(key)
+                .and_then(|value| value.parse::<f32>().ok())
+                .filter(|value| *value > 0.0)
+        };
+
+        let volume = PrintVolume {
+            x: size("machine_width"),
+            y: None,
+            z: size("machine_height"),
+        };
+
+        (volume.x.is_some() || volume.z.is_some()).then_some(volume)
+    }
+
+    fn args(&self, exec_ctx: &ExecutionContext) -> Vec<OsString> {
+        vec![
+            // slicing profile
+            "-c".into(),
+            exec_ctx.config_path.clone().into(),
+            // gcode output
+            "-o".into(),
+            exec_ctx.gcode_path.clone().into(),
+        ]
+        .into_iter()
+        // load models
+        .chain(exec_ctx.src_paths.iter().map(|src_path| src_path.into()))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::settings::ProfileFormat;
+
+    fn errors(config: &str) -> Vec<Option<String>> {
+        let mut settings = ProfileSettings::parse(ProfileFormat::Ini, config).unwrap();
+
+        engine()
+            .validate_profile(&mut settings)
+            .into_iter()
+            .map(|error| error.key)
+            .collect()
+    }
+
+    #[test]
+    fn profiles_are_validated() {
+        assert!(errors("machine_width = 100\nmachine_height = 100\n").is_empty());
+        assert!(errors("layer_height = 0.2\nmachine_nozzle_size = 0.4\n").is_empty());
+
+        assert_eq!(
+            errors("machine_width = 0\nmachine_height = tall\n"),
+            vec![
+                Some("machine_width".to_owned()),
+                Some("machine_height".to_owned())
+            ],
+        );
+        assert_eq!(
+            errors("layer_height = 0.6\nmachine_nozzle_size = 0.4\n"),
+            vec![Some("layer_height".to_owned())],
+        );
+    }
 }
@@ -0,0 +1,162 @@
+use super::{Engine, SlicingEngine};
+use crate::{
+    config::CustomEngineConfig,
+    execution_context::ExecutionContext,
+    gcode::output_format::OutputFormat,
+    profile::settings::ProfileFormat,
+    release::{GithubReleaseConfig, LocalReleaseConfig, Release, ReleaseConfig},
+};
+use async_trait::async_trait;
+use cgmath::{Matrix4, SquareMatrix};
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::{ffi::OsString, sync::Arc};
+
+/// An engine declared in config.toml
+pub struct CustomEngine {
+    metadata: Engine,
+    release_config: Box<dyn ReleaseConfig + Send + Sync>,
+    args: Vec<String>,
+    output_formats: Vec<OutputFormat>,
+    progress_regex: Option<Regex>,
+    profile_format: ProfileFormat,
+}
+
+impl CustomEngine {
+    pub fn new(config: &CustomEngineConfig) -> Result<Self> {
+        let release_config: Box<dyn ReleaseConfig + Send + Sync> =
+            match (&config.bin_path, &config.github_repo) {
+                (Some(bin_path), None) => Box::new(LocalReleaseConfig {
+                    bin_path: bin_path.clone(),
+                    release_url: config
+                        .release_url
+                        .clone()
+                        .unwrap_or_else(|| format!("local://{}", config.id)),
+                }),
+                (None, Some(repo)) => {
+                    let asset_pattern = config
+                        .asset_pattern
+                        .as_ref()
+                        .ok_or_else(|| eyre!("asset_pattern is required for Github engines"))?;
+                    let asset_pattern = Regex::new(asset_pattern)?;
+
+                    Box::new(GithubReleaseConfig {
+                        repo: repo.clone(),
+                        asset_filter: Arc::new(move |asset: &str| asset_pattern.is_match(asset)),
+                    })
+                }
+                _ => return Err(eyre!("Exactly one of bin_path or github_repo must be set")),
+            };
+
+        let progress_regex = config
+            .progress_regex
+            .as_ref()
+            .map(|progress_regex| Regex::new(progress_regex))
+            .transpose()?;
+
+        let release_url = config
+            .github_repo
+            .as_ref()
+            .map(|repo| format!("https://github.com/{repo}/releases"));
+
+        let home_page = config
+            .home_page
+            .clone()
+            .or_else(|| {
+                config
+                    .github_repo
+                    .as_ref()
+                    .map(|repo| format!("https://github.com/{repo}"))
+            })
+            .unwrap_or_default();
+
+        let metadata = Engine {
+            id: config.id.as_str().into(),
+            name: config.name.clone(),
+            transform_mat4: config
+                .transform_mat4
+                .map(Matrix4::from)
+                .unwrap_or_else(Matrix4::identity),
+            allows_positioning: config.allows_positioning,
+            invert_rotation: config.invert_rotation.clone(),
+            accepted_file_formats: config.accepted_file_formats.clone(),
+            release_url,
+            home_page,
+        };
+
+        Ok(Self {
+            metadata,
+            release_config,
+            args: config.args.clone(),
+            output_formats: config.output_formats.clone(),
+            progress_regex,
+            profile_format: config.profile_format,
+        })
+    }
+}
+
+#[async_trait]
+impl SlicingEngine for CustomEngine {
+    fn metadata(&self) -> &Engine {
+        &self.metadata
+    }
+
+    fn parse_release(&self, url: &str) -> Result<Release> {
+        self.release_config.parse(url)
+    }
+
+    async fn latest_release(&self) -> Result<Release> {
+        self.release_config.latest_release().await
+    }
+
+    /// Engines can accept several models if `{src}` is an argument on it's own
+    fn accepts_multiple_models(&self) -> bool {
+        self.args.iter().any(|arg| arg == "{src}")
+    }
+
+    fn native_output_formats(&self, _release: &Release) -> Vec<OutputFormat> {
+        self.output_formats.clone()
+    }
+
+    fn args(&self, exec_ctx: &ExecutionContext) -> Vec<OsString> {
+        let src_path = exec_ctx
+            .src_paths
+            .first()
+            .map(|src_path| src_path.to_string_lossy())
+            .unwrap_or_default();
+
+        self.args
+            .iter()
+            .flat_map(|arg| -> Vec<OsString> {
+                if arg == "{src}" {
+                    return exec_ctx
+                        .src_paths
+                        .iter()
+                        .map(|src_path| src_path.into())
+                        .collect();
+                }
+
+                vec![arg
+                    .replace("{src}", &src_path)
+                    .replace("{config}", &exec_ctx.config_path.to_string_lossy())
+                    .replace("{gcode}", &exec_ctx.gcode_path.to_string_lossy())
+                    .replace("{output_format}", exec_ctx.output_format.extension())
+                    .into()]
+            })
+            .collect()
+    }
+
+    fn profile_format(&self) -> ProfileFormat {
+        self.profile_format
+    }
+
+    fn parse_progress(&self, line: &str) -> Option<f32> {
+        self.progress_regex
+            .as_ref()?
+            .captures(line)?
+            .get(1)?
+            .as_str()
+            .parse()
+            .ok()
+    }
+}
@@ -1,115 +1,337 @@
-use super::Engine;
+use super::{Engine, PrintVolume, SlicingEngine};
 use crate::{
     execution_context::ExecutionContext,
-    release::{GithubRelease, GithubReleaseConfig},
+    gcode::output_format::OutputFormat,
+    mesh::arrange::BedShape,
+    profile::{
+        conversion::ProfileConversion,
+        settings::ProfileSettings,
+        validation::{parse_list, require, ProfileError},
+    },
+    release::{GithubReleaseConfig, Release, ReleaseConfig},
 };
+use async_trait::async_trait;
 use cgmath::Matrix4;
-use eyre::{eyre, Context, Result};
-use futures_util::FutureExt;
-use tracing::info;
+use eyre::Result;
+use std::{collections::HashSet, ffi::OsString, sync::Arc};
 
-pub fn engines() -> Vec<Engine> {
+mod key_mappings;
+mod setting_keys;
+
+/// The first PrusaSlicer release that can write binary GCode
+const BINARY_GCODE_VERSION: (u32, u32) = (2, 7);
+
+/// Slic3r and it's forks, which share a command line interface
+pub struct Slic3rEngine {
+    metadata: Engine,
+    variant: Slic3rVariant,
+    release_config: GithubReleaseConfig,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Slic3rVariant {
+    Slic3r,
+    PrusaSlicer,
+    SuperSlicer,
+}
+
+impl Slic3rVariant {
+    fn from_engine_id(engine_id: &str) -> Option<Self> {
+        match engine_id {
+            "slic3r" => Some(Self::Slic3r),
+            "prusa_slicer" => Some(Self::PrusaSlicer),
+            "super_slicer" => Some(Self::SuperSlicer),
+            _ => None,
+        }
+    }
+}
+
+/// The major and minor version of a Github release, eg. 2.7 for "version_2.7.0-rc1"
+fn release_version(release: &Release) -> Option<(u32, u32)> {
+    let tag = match release {
+        Release::Github(github_release) => &github_release.tag,
+        Release::Local(_) => return None,
+    };
+
+    let mut numbers = tag
+        .trim_start_matches(|c: char| !c.is_ascii_digit())
+        .split(|c: char| !c.is_ascii_digit());
+
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next()?.parse().ok()?;
+
+    Some((major, minor))
+}
+
+pub fn engines() -> Vec<Slic3rEngine> {
     vec![
-        Engine {
-            id: "slic3r".into(),
-            name: "Slic3r".into(),
-            transform_mat4: Matrix4::from_scale(1.0),
-            allows_positioning: true,
-            invert_rotation: Default::default(),
-            accepted_file_formats: vec![".stl", ".obj", ".amf", ".3mf"],
-            release_url: Some("https://github.com/slic3r/Slic3r/releases"),
-            home_page: "https://github.com/slic3r/Slic3r",
-            release_config: Box::pin(GithubReleaseConfig {
+        Slic3rEngine {
+            metadata: Engine {
+                id: "slic3r".into(),
+                name: "Slic3r".to_owned(),
+                transform_mat4: Matrix4::from_scale(1.0),
+                allows_positioning: true,
+                invert_rotation: Default::default(),
+                accepted_file_formats: [".stl", ".obj", ".amf", ".3mf"].map(String::from).to_vec(),
+                release_url: Some("https://github.com/slic3r/Slic3r/releases".to_owned()),
+                home_page: "https://github.com/slic3r/Slic3r".to_owned(),
+            },
+            variant: Slic3rVariant::Slic3r,
+            release_config: GithubReleaseConfig {
                 repo: "slic3r/Slic3r".to_owned(),
-                asset_filter: &|asset: &str| {
+                asset_filter: Arc::new(|asset: &str| {
                     // Only X64 support for now
                     asset.contains("-x86_64") && asset.ends_with(".AppImage")
-                },
-                generate_gcode_inner: &|exec_ctx| generate_gcode(exec_ctx).boxed(),
-            }),
+                }),
+            },
         },
-        Engine {
-            id: "prusa_slicer".into(),
-            name: "Prusa Slicer".into(),
-            transform_mat4: Matrix4::from_scale(1.0),
-            allows_positioning: true,
-            invert_rotation: Default::default(),
-            accepted_file_formats: vec![".stl", ".obj", ".amf", ".3mf"],
-            release_url: Some("https://github.com/prusa3d/PrusaSlicer/releases"),
-            home_page: "https://github.com/prusa3d/PrusaSlicer",
-            release_config: Box::pin(GithubReleaseConfig {
+        Slic3rEngine {
+            metadata: Engine {
+                id: "prusa_slicer".into(),
+                name: "Prusa Slicer".to_owned(),
+                transform_mat4: Matrix4::from_scale(1.0),
+                allows_positioning: true,
+                invert_rotation: Default::default(),
+                accepted_file_formats: [".stl", ".obj", ".amf", ".3mf"].map(String::from).to_vec(),
+                release_url: Some("https://github.com/prusa3d/PrusaSlicer/releases".to_owned()),
+                home_page: "https://github.com/prusa3d/PrusaSlicer".to_owned(),
+            },
+            variant: Slic3rVariant::PrusaSlicer,
+            release_config: GithubReleaseConfig {
                 repo: "prusa3d/PrusaSlicer".to_owned(),
-                asset_filter: &|asset: &str| {
+                asset_filter: Arc::new(|asset: &str| {
                     // Only X64 support for now
                     asset.contains("-x64-GTK3") && asset.ends_with(".AppImage")
-                },
-                generate_gcode_inner: &|exec_ctx| generate_gcode(exec_ctx).boxed(),
-            }),
+                }),
+            },
         },
-        Engine {
-            id: "super_slicer".into(),
-            name: "Super Slicer".into(),
-            transform_mat4: Matrix4::from_scale(1.0),
-            allows_positioning: true,
-            invert_rotation: Default::default(),
-            accepted_file_formats: vec![".stl", ".obj", ".amf", ".3mf"],
-            release_url: Some("https://github.com/supermerill/SuperSlicer/releases"),
-            home_page: "https://github.com/supermerill/SuperSlicer",
-            release_config: Box::pin(GithubReleaseConfig {
+        Slic3rEngine {
+            metadata: Engine {
+                id: "super_slicer".into(),
+                name: "Super Slicer".to_owned(),
+                transform_mat4: Matrix4::from_scale(1.0),
+                allows_positioning: true,
+                invert_rotation: Default::default(),
+                accepted_file_formats: [".stl", ".obj", ".amf", ".3mf"].map(String::from).to_vec(),
+                release_url: Some("https://github.com/supermerill/SuperSlicer/releases".to_owned()),
+                home_page: "https://github.com/supermerill/SuperSlicer".to_owned(),
+            },
+            variant: Slic3rVariant::SuperSlicer,
+            release_config: GithubReleaseConfig {
                 repo: "supermerill/SuperSlicer".to_owned(),
-                asset_filter: &|asset: &str| {
+                asset_filter: Arc::new(|asset: &str| {
                     // Only X64 support for now
                     asset.contains("-ubuntu_18.04-") && asset.ends_with(".AppImage")
-                },
-                generate_gcode_inner: &|exec_ctx| generate_gcode(exec_ctx).boxed(),
-            }),
+                }),
+            },
         },
     ]
 }
 
-pub async fn generate_gcode(exec_ctx: ExecutionContext<GithubRelease>) -> Result<()> {
-    let ExecutionContext {
-        release,
-        co,
-        src_path,
-        config_path,
-        gcode_path,
-    } = exec_ctx;
-
-    let belt_engine_path = release.bin_path_if_downloaded()?;
-
-    // Run as slicing worker
-    let mut cmd = tokio::process::Command::new("su");
-
-    cmd.arg("-")
-        .arg("slicing-worker")
-        .arg("-c")
-        // slicer
-        .arg(&belt_engine_path)
-        // Set slicing profile
-        .arg("--load")
-        .arg(&config_path)
-        // Set gcode output
-        .arg("--output")
-        .arg(&gcode_path)
+#[async_trait]
+impl SlicingEngine for Slic3rEngine {
+    fn metadata(&self) -> &Engine {
+        &self.metadata
+    }
+
+    fn parse_release(&self, url: &str) -> Result<Release> {
+        self.release_config.parse(url)
+    }
+
+    async fn latest_release(&self) -> Result<Release> {
+        self.release_config.latest_release().await
+    }
+
+    fn accepts_multiple_models(&self) -> bool {
+        true
+    }
+
+    /// PrusaSlicer writes binary GCode itself from version 2.7. Locally installed releases are
+    /// assumed to be older as their version is unknown.
+    fn native_output_formats(&self, release: &Release) -> Vec<OutputFormat> {
+        let writes_binary_gcode = self.variant == Slic3rVariant::PrusaSlicer
+            && release_version(release).map_or(false, |version| version >= BINARY_GCODE_VERSION);
+
+        if writes_binary_gcode {
+            vec![OutputFormat::Bgcode]
+        } else {
+            vec![]
+        }
+    }
+
+    fn args(&self, exec_ctx: &ExecutionContext) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            // Set slicing profile
+            "--load".into(),
+            exec_ctx.config_path.clone().into(),
+            // Set gcode output
+            "--output".into(),
+            exec_ctx.gcode_path.clone().into(),
+        ];
+
+        if exec_ctx.output_format == OutputFormat::Bgcode {
+            args.push("--binary-gcode".into());
+        }
+
         // Run the slicer
-        .arg("--slice")
-        .arg(&src_path);
+        args.push("--slice".into());
 
-    info!("Slicer command: {:?}", cmd);
+        args.into_iter()
+            .chain(exec_ctx.src_paths.iter().map(|src_path| src_path.into()))
+            .collect()
+    }
 
-    let output = cmd.output().await.wrap_err("Slicer error")?;
+    /// Parses the status lines printed by the CLI while slicing, eg. "20 => Generating perimeters"
+    fn parse_progress(&self, line: &str) -> Option<f32> {
+        let (percent, _) = line.split_once(" => ")?;
+        percent.trim().parse().ok()
+    }
 
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    fn setting_keys(&self) -> Option<HashSet<&'static str>> {
+        Some(setting_keys::setting_keys(self.variant))
+    }
 
-    let result = if output.status.success() {
-        info!("{}", stderr);
-        Ok(100.0)
-    } else {
-        Err(eyre!(stderr))
-    };
+    fn print_volume(&self, settings: &ProfileSettings) -> Option<PrintVolume> {
+        let (min, max) = BedShape::parse(&settings.get("bed_shape")?)?.bounds()?;
+
+        // A max_print_height of 0 means the height is not limited
+        let height = settings
+            .get("max_print_height")
+            .and_then(|height| height.parse::<f32>().ok())
+            .filter(|height| *height > 0.0);
+
+        Some(PrintVolume {
+            x: Some(max.x - min.x),
+            y: Some(max.y - min.y),
+            z: height,
+        })
+    }
+
+    /// Slic3r lists a nozzle diameter for each extruder
+    fn extruder_count(&self, settings: &ProfileSettings) -> Option<usize> {
+        Some(settings.get("nozzle_diameter")?.split(',').count())
+    }
+
+    fn validate_profile(&self, settings: &mut ProfileSettings) -> Vec<ProfileError> {
+        let mut errors = vec![];
+
+        // One diameter per extruder
+        let nozzle_diameters = require(settings, "nozzle_diameter", &mut errors)
+            .and_then(|value| parse_list::<f32>("nozzle_diameter", &value, &mut errors));
+
+        if let Some(nozzle_diameters) = &nozzle_diameters {
+            if nozzle_diameters.iter().any(|diameter| *diameter <= 0.0) {
+                errors.push(ProfileError::new(
+                    "nozzle_diameter",
+                    "must be greater than 0",
+                ));
+            }
+        }
+
+        // The bed outline as a list of XxY points, eg. "0x0,250x0,250x210,0x210"
+        if let Some(bed_shape) = require(settings, "bed_shape", &mut errors) {
+            match BedShape::parse(&bed_shape) {
+                Some(bed) if bed.points.len() >= 3 => {}
+                Some(_) => errors.push(ProfileError::new(
+                    "bed_shape",
+                    "must contain at least 3 points",
+                )),
+                None => errors.push(ProfileError::new(
+                    "bed_shape",
+                    format!("invalid value {bed_shape:?}"),
+                )),
+            }
+        }
+
+        if let Some(layer_height) = require(settings, "layer_height", &mut errors) {
+            match layer_height.parse::<f32>() {
+                Ok(layer_height) if layer_height <= 0.0 => {
+                    errors.push(ProfileError::new("layer_height", "must be greater than 0"))
+                }
+                Ok(layer_height) => {
+                    let smallest_nozzle =
+                        nozzle_diameters.iter().flatten().copied().reduce(f32::min);
+
+                    if let Some(smallest_nozzle) = smallest_nozzle {
+                        if layer_height > smallest_nozzle {
+                            errors.push(ProfileError::new(
+                                "layer_height",
+                                format!(
+                                    "must not exceed the nozzle diameter ({smallest_nozzle}mm)"
+                                ),
+                            ));
+                        }
+                    }
+                }
+                Err(_) => errors.push(ProfileError::new(
+                    "layer_height",
+                    format!("invalid value {layer_height:?}"),
+                )),
+            }
+        }
+
+        // Infill density is a percentage, which may be written without the percent sign
+        if let Some(fill_density) = settings.get("fill_density") {
+            let percent = fill_density
+                .trim_end_matches('%')
+                .trim()
+                .parse::<f32>()
+                .ok();
+
+            match percent {
+                Some(percent) if (0.0..=100.0).contains(&percent) => {
+                    settings.set("fill_density", &format!("{percent}%"));
+                }
+                _ => errors.push(ProfileError::new(
+                    "fill_density",
+                    "must be a percentage between 0% and 100%",
+                )),
+            }
+        }
+
+        errors
+    }
+
+    fn convert_profile(
+        &self,
+        from_engine_id: &str,
+        settings: &mut ProfileSettings,
+    ) -> Option<ProfileConversion> {
+        let from = Slic3rVariant::from_engine_id(from_engine_id)?;
+
+        Some(key_mappings::convert(settings, from, self.variant))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prusa_slicer_formats(tag: &str) -> Vec<OutputFormat> {
+        let engine = engines()
+            .into_iter()
+            .find(|engine| engine.variant == Slic3rVariant::PrusaSlicer)
+            .unwrap();
+        let release = engine
+            .parse_release(&format!(
+                "https://github.com/prusa3d/PrusaSlicer/releases/tag/{tag}"
+            ))
+            .unwrap();
 
-    co.yield_(result).await;
+        engine.native_output_formats(&release)
+    }
 
-    Ok(())
+    #[test]
+    fn prusa_slicer_writes_binary_gcode_from_2_7() {
+        assert_eq!(prusa_slicer_formats("version_2.5.0"), vec![]);
+        assert_eq!(prusa_slicer_formats("version_2.6.1"), vec![]);
+        assert_eq!(
+            prusa_slicer_formats("version_2.7.0-rc1"),
+            vec![OutputFormat::Bgcode]
+        );
+        assert_eq!(
+            prusa_slicer_formats("version_2.8.0"),
+            vec![OutputFormat::Bgcode]
+        );
+    }
 }
@@ -0,0 +1,340 @@
+use super::Slic3rVariant;
+use crate::profile::conversion::{ApproximatedSetting, ProfileConversion};
+use crate::profile::settings::ProfileSettings;
+
+/// A setting which differs between the Slic3r-family engines. Settings not listed here are shared
+/// by all three engines.
+struct KeyMapping {
+    slic3r: Option<&'static str>,
+    prusa_slicer: Option<&'static str>,
+    super_slicer: Option<&'static str>,
+    value: ValueConversion,
+}
+
+impl KeyMapping {
+    fn key(&self, variant: Slic3rVariant) -> Option<&'static str> {
+        match variant {
+            Slic3rVariant::Slic3r => self.slic3r,
+            Slic3rVariant::PrusaSlicer => self.prusa_slicer,
+            Slic3rVariant::SuperSlicer => self.super_slicer,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ValueConversion {
+    /// The value has the same meaning in each engine
+    Same,
+    /// PrusaSlicer's `elefant_foot_compensation` shrinks the first layer by a positive distance
+    /// where as SuperSlicer's `first_layer_size_compensation` uses a negative distance
+    Negated,
+    /// SuperSlicer replaced the "nearest" seam position with the cost-based "cost" position
+    SeamPosition,
+    /// Slic3r and PrusaSlicer enable overhang detection with a boolean where as SuperSlicer sets
+    /// the overhang threshold as a width
+    OverhangsWidth,
+}
+
+const KEY_MAPPINGS: &[KeyMapping] = &[
+    // Renamed settings
+    KeyMapping {
+        slic3r: Some("top_infill_pattern"),
+        prusa_slicer: Some("top_fill_pattern"),
+        super_slicer: Some("top_fill_pattern"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: Some("bottom_infill_pattern"),
+        prusa_slicer: Some("bottom_fill_pattern"),
+        super_slicer: Some("bottom_fill_pattern"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: Some("brim_separation"),
+        super_slicer: Some("brim_offset"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: Some("wipe_tower_brim_width"),
+        super_slicer: Some("wipe_tower_brim"),
+        value: ValueConversion::Same,
+    },
+    // Settings with different units or values
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: Some("elefant_foot_compensation"),
+        super_slicer: Some("first_layer_size_compensation"),
+        value: ValueConversion::Negated,
+    },
+    KeyMapping {
+        slic3r: Some("seam_position"),
+        prusa_slicer: Some("seam_position"),
+        super_slicer: Some("seam_position"),
+        value: ValueConversion::SeamPosition,
+    },
+    KeyMapping {
+        slic3r: Some("overhangs"),
+        prusa_slicer: Some("overhangs"),
+        super_slicer: Some("overhangs_width"),
+        value: ValueConversion::OverhangsWidth,
+    },
+    // Slic3r-only settings
+    KeyMapping {
+        slic3r: Some("adaptive_slicing"),
+        prusa_slicer: None,
+        super_slicer: None,
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: Some("adaptive_slicing_quality"),
+        prusa_slicer: None,
+        super_slicer: None,
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: Some("min_top_bottom_shell_thickness"),
+        prusa_slicer: None,
+        super_slicer: None,
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: Some("regions_overlap"),
+        prusa_slicer: None,
+        super_slicer: None,
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: Some("support_material_pillar_size"),
+        prusa_slicer: None,
+        super_slicer: None,
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: Some("support_material_pillar_spacing"),
+        prusa_slicer: None,
+        super_slicer: None,
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: Some("z_steps_per_mm"),
+        prusa_slicer: None,
+        super_slicer: None,
+        value: ValueConversion::Same,
+    },
+    // PrusaSlicer and SuperSlicer settings that Slic3r does not support
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: Some("ironing"),
+        super_slicer: Some("ironing"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: Some("ironing_type"),
+        super_slicer: Some("ironing_type"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: Some("thumbnails"),
+        super_slicer: Some("thumbnails"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: Some("wipe_tower"),
+        super_slicer: Some("wipe_tower"),
+        value: ValueConversion::Same,
+    },
+    // SuperSlicer-only settings
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: None,
+        super_slicer: Some("infill_dense"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: None,
+        super_slicer: Some("only_one_perimeter_top"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: None,
+        super_slicer: Some("perimeter_loop"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: None,
+        super_slicer: Some("thin_walls_min_width"),
+        value: ValueConversion::Same,
+    },
+    KeyMapping {
+        slic3r: None,
+        prusa_slicer: None,
+        super_slicer: Some("xy_inner_size_compensation"),
+        value: ValueConversion::Same,
+    },
+];
+
+/// The keys of the settings in the key mappings that the engine supports
+pub fn variant_keys(variant: Slic3rVariant) -> impl Iterator<Item = &'static str> {
+    KEY_MAPPINGS
+        .iter()
+        .filter_map(move |mapping| mapping.key(variant))
+}
+
+/// Converts a value between engines, returning a note if the converted value is approximate
+fn convert_value(
+    conversion: ValueConversion,
+    value: &str,
+    from: Slic3rVariant,
+    to: Slic3rVariant,
+) -> (String, Option<String>) {
+    use Slic3rVariant::SuperSlicer;
+
+    match (conversion, from == SuperSlicer, to == SuperSlicer) {
+        (ValueConversion::Negated, true, false) | (ValueConversion::Negated, false, true) => {
+            match value.parse::<f32>() {
+                // Negating 0 would write "-0"
+                Ok(distance) if distance == 0.0 => ("0".to_owned(), None),
+                Ok(distance) => ((-distance).to_string(), None),
+                Err(_) => (
+                    value.to_owned(),
+                    Some(format!("Unable to convert {value:?}")),
+                ),
+            }
+        }
+        (ValueConversion::SeamPosition, false, true) if value == "nearest" => (
+            "cost".to_owned(),
+            Some("\"nearest\" was replaced by SuperSlicer's \"cost\" seam position".to_owned()),
+        ),
+        (ValueConversion::SeamPosition, true, false) if value == "cost" => (
+            "nearest".to_owned(),
+            Some("SuperSlicer's \"cost\" seam position was replaced by \"nearest\"".to_owned()),
+        ),
+        (ValueConversion::OverhangsWidth, false, true) => {
+            let width = if value == "0" { "0" } else { "50%" };
+
+            (
+                width.to_owned(),
+                Some(format!(
+                    "overhangs = {value} was converted to a width of {width}"
+                )),
+            )
+        }
+        (ValueConversion::OverhangsWidth, true, false) => {
+            let is_disabled = value.trim_end_matches('%').parse::<f32>() == Ok(0.0);
+            let enabled = if is_disabled { "0" } else { "1" };
+
+            (
+                enabled.to_owned(),
+                Some(format!(
+                    "overhangs_width = {value} was converted to {enabled}"
+                )),
+            )
+        }
+        _ => (value.to_owned(), None),
+    }
+}
+
+/// Converts a profile's settings from one Slic3r-family engine to another
+pub fn convert(
+    settings: &mut ProfileSettings,
+    from: Slic3rVariant,
+    to: Slic3rVariant,
+) -> ProfileConversion {
+    let mut conversion = ProfileConversion::default();
+
+    if from == to {
+        return conversion;
+    }
+
+    for mapping in KEY_MAPPINGS {
+        let (from_key, value) = match mapping
+            .key(from)
+            .and_then(|key| Some((key, settings.get(key)?)))
+        {
+            Some(setting) => setting,
+            None => continue,
+        };
+
+        let to_key = match mapping.key(to) {
+            Some(to_key) => to_key,
+            None => {
+                settings.remove(from_key);
+                conversion.dropped_keys.push(from_key.to_owned());
+                continue;
+            }
+        };
+
+        // Profiles which already contain the target engine's key keep it's value
+        if from_key != to_key && !settings.rename(from_key, to_key) {
+            settings.remove(from_key);
+            conversion.approximated.push(ApproximatedSetting {
+                key: to_key.to_owned(),
+                note: format!(
+                    "{from_key} = {value} was replaced by the profile's existing {to_key}"
+                ),
+            });
+            continue;
+        }
+
+        let (new_value, note) = convert_value(mapping.value, &value, from, to);
+        settings.set(to_key, &new_value);
+
+        if let Some(note) = note {
+            conversion.approximated.push(ApproximatedSetting {
+                key: to_key.to_owned(),
+                note,
+            });
+        }
+    }
+
+    conversion
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::settings::ProfileFormat;
+
+    fn convert_ini(
+        content: &str,
+        from: Slic3rVariant,
+        to: Slic3rVariant,
+    ) -> (String, ProfileConversion) {
+        let mut settings = ProfileSettings::parse(ProfileFormat::Ini, content).unwrap();
+        let conversion = convert(&mut settings, from, to);
+
+        (settings.to_string(), conversion)
+    }
+
+    #[test]
+    fn negated_zero_is_written_as_zero() {
+        let (content, _) = convert_ini(
+            "elefant_foot_compensation = 0\n",
+            Slic3rVariant::PrusaSlicer,
+            Slic3rVariant::SuperSlicer,
+        );
+
+        assert_eq!(content, "first_layer_size_compensation = 0\n");
+    }
+
+    #[test]
+    fn renames_keep_the_existing_target_setting() {
+        let (content, conversion) = convert_ini(
+            "brim_separation = 0.1\nbrim_offset = 0.3\n",
+            Slic3rVariant::PrusaSlicer,
+            Slic3rVariant::SuperSlicer,
+        );
+
+        assert_eq!(content, "brim_offset = 0.3\n");
+        assert_eq!(conversion.approximated.len(), 1);
+        assert_eq!(conversion.approximated[0].key, "brim_offset");
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+
+use super::{key_mappings, Slic3rVariant};
+
+/// Settings shared by Slic3r, PrusaSlicer and SuperSlicer under the same key. Settings that were
+/// renamed or that only some of the engines support are listed in the key mappings instead.
+const SHARED_KEYS: &[&str] = &[
+    // Print settings
+    "avoid_crossing_perimeters",
+    "bottom_solid_layers",
+    "bridge_acceleration",
+    "bridge_angle",
+    "bridge_flow_ratio",
+    "bridge_speed",
+    "brim_width",
+    "clip_multipart_objects",
+    "complete_objects",
+    "default_acceleration",
+    "dont_support_bridges",
+    "external_fill_pattern",
+    "external_perimeter_extrusion_width",
+    "external_perimeter_speed",
+    "external_perimeters_first",
+    "extra_perimeters",
+    "extruder_clearance_height",
+    "extruder_clearance_radius",
+    "extrusion_width",
+    "fill_angle",
+    "fill_density",
+    "fill_pattern",
+    "first_layer_acceleration",
+    "first_layer_extrusion_width",
+    "first_layer_height",
+    "first_layer_speed",
+    "gap_fill_speed",
+    "gcode_comments",
+    "infill_acceleration",
+    "infill_every_layers",
+    "infill_extruder",
+    "infill_extrusion_width",
+    "infill_first",
+    "infill_only_where_needed",
+    "infill_overlap",
+    "infill_speed",
+    "interface_shells",
+    "layer_height",
+    "max_print_speed",
+    "max_volumetric_speed",
+    "min_skirt_length",
+    "notes",
+    "only_retract_when_crossing_perimeters",
+    "ooze_prevention",
+    "output_filename_format",
+    "perimeter_acceleration",
+    "perimeter_extruder",
+    "perimeter_extrusion_width",
+    "perimeter_speed",
+    "perimeters",
+    "post_process",
+    "raft_layers",
+    "resolution",
+    "skirt_distance",
+    "skirt_height",
+    "skirts",
+    "small_perimeter_speed",
+    "solid_infill_below_area",
+    "solid_infill_every_layers",
+    "solid_infill_extruder",
+    "solid_infill_extrusion_width",
+    "solid_infill_speed",
+    "spiral_vase",
+    "standby_temperature_delta",
+    "support_material",
+    "support_material_angle",
+    "support_material_buildplate_only",
+    "support_material_contact_distance",
+    "support_material_enforce_layers",
+    "support_material_extruder",
+    "support_material_extrusion_width",
+    "support_material_interface_contact_loops",
+    "support_material_interface_extruder",
+    "support_material_interface_layers",
+    "support_material_interface_spacing",
+    "support_material_interface_speed",
+    "support_material_pattern",
+    "support_material_spacing",
+    "support_material_speed",
+    "support_material_synchronize_layers",
+    "support_material_threshold",
+    "support_material_with_sheath",
+    "support_material_xy_spacing",
+    "thin_walls",
+    "top_infill_extrusion_width",
+    "top_solid_infill_speed",
+    "top_solid_layers",
+    "travel_speed",
+    "xy_size_compensation",
+    // Filament settings
+    "bed_temperature",
+    "bridge_fan_speed",
+    "compatible_printers",
+    "compatible_printers_condition",
+    "cooling",
+    "disable_fan_first_layers",
+    "end_filament_gcode",
+    "extrusion_multiplier",
+    "fan_always_on",
+    "fan_below_layer_time",
+    "filament_colour",
+    "filament_cost",
+    "filament_density",
+    "filament_diameter",
+    "filament_max_volumetric_speed",
+    "filament_notes",
+    "filament_settings_id",
+    "filament_soluble",
+    "filament_type",
+    "first_layer_bed_temperature",
+    "first_layer_temperature",
+    "max_fan_speed",
+    "min_fan_speed",
+    "min_print_speed",
+    "slowdown_below_layer_time",
+    "start_filament_gcode",
+    "temperature",
+    // Printer settings
+    "bed_shape",
+    "before_layer_gcode",
+    "between_objects_gcode",
+    "deretract_speed",
+    "end_gcode",
+    "extruder_colour",
+    "extruder_offset",
+    "gcode_flavor",
+    "layer_gcode",
+    "max_layer_height",
+    "max_print_height",
+    "min_layer_height",
+    "nozzle_diameter",
+    "printer_model",
+    "printer_notes",
+    "printer_settings_id",
+    "printer_technology",
+    "retract_before_travel",
+    "retract_before_wipe",
+    "retract_layer_change",
+    "retract_length",
+    "retract_length_toolchange",
+    "retract_lift",
+    "retract_lift_above",
+    "retract_lift_below",
+    "retract_restart_extra",
+    "retract_restart_extra_toolchange",
+    "retract_speed",
+    "start_gcode",
+    "toolchange_gcode",
+    "use_firmware_retraction",
+    "use_relative_e_distances",
+    "use_volumetric_e",
+    "variable_layer_height",
+    "wipe",
+    "z_offset",
+    // Profile names
+    "print_settings_id",
+];
+
+/// The settings supported by the engine
+pub fn setting_keys(variant: Slic3rVariant) -> HashSet<&'static str> {
+    SHARED_KEYS
+        .iter()
+        .copied()
+        .chain(key_mappings::variant_keys(variant))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_only_listed_once() {
+        let mut seen = HashSet::new();
+
+        for key in SHARED_KEYS {
+            assert!(seen.insert(*key), "{key:?} is listed more than once");
+        }
+
+        // Keys that differ between the variants are listed in the key mappings instead
+        for variant in [
+            Slic3rVariant::Slic3r,
+            Slic3rVariant::PrusaSlicer,
+            Slic3rVariant::SuperSlicer,
+        ] {
+            for key in key_mappings::variant_keys(variant) {
+                assert!(
+                    !SHARED_KEYS.contains(&key),
+                    "{key:?} is both a shared and a variant key"
+                );
+            }
+        }
+    }
+}
@@ -2,18 +2,41 @@ use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 
 // Make our own error that wraps `eyre::Error`.
-pub struct AppError(eyre::Error);
+pub struct AppError {
+    status: StatusCode,
+    err: eyre::Error,
+}
 
 pub type AppResult<T> = Result<T, AppError>;
 
+impl AppError {
+    /// Creates an error that is returned to the client with a specific HTTP status code
+    pub fn new(status: StatusCode, err: impl Into<eyre::Error>) -> Self {
+        Self {
+            status,
+            err: err.into(),
+        }
+    }
+
+    pub fn not_found(err: impl Into<eyre::Error>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, err)
+    }
+
+    pub fn conflict(err: impl Into<eyre::Error>) -> Self {
+        Self::new(StatusCode::CONFLICT, err)
+    }
+}
+
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        let message = if self.status == StatusCode::INTERNAL_SERVER_ERROR {
+            format!("Something went wrong: {}", self.err)
+        } else {
+            self.err.to_string()
+        };
+
+        (self.status, message).into_response()
     }
 }
 
@@ -24,6 +47,6 @@ where
     E: Into<eyre::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, err)
     }
 }
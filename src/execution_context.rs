@@ -1,10 +1,16 @@
+use crate::gcode::output_format::OutputFormat;
+use crate::release::Release;
 use eyre::Result;
 use std::{path::PathBuf, sync::Arc};
 
-pub struct ExecutionContext<R> {
-    pub release: R,
+pub struct ExecutionContext {
+    pub release: Release,
     pub co: Arc<genawaiter::sync::Co<Result<f32>, ()>>,
-    pub src_path: PathBuf,
+    /// The models to slice into a single GCode file. Most jobs have a single model.
+    pub src_paths: Vec<PathBuf>,
     pub config_path: PathBuf,
     pub gcode_path: PathBuf,
+    /// The format to write to the GCode path. This is plain GCode unless the engine lists the
+    /// job's output format in it's `native_output_formats`.
+    pub output_format: OutputFormat,
 }
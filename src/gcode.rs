@@ -0,0 +1,199 @@
+use eyre::{eyre, Result};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+pub mod bgcode;
+pub mod layers;
+pub mod output_format;
+pub mod post_processing;
+pub mod statistics;
+pub mod three_mf;
+pub mod thumbnails;
+
+/// Splits a line of GCode into it's command and parameters, dropping any comment, eg.
+/// "G1 X10 E1.5 ; perimeter" is split into ("G1", [('X', 10.0), ('E', 1.5)])
+pub fn parse_line(line: &str) -> Option<(&str, Vec<(char, f32)>)> {
+    let code = line.split(';').next()?.trim();
+    let mut words = code.split_whitespace();
+
+    let command = words.next()?;
+    let params = words
+        .filter_map(|word| {
+            let mut chars = word.chars();
+            let letter = chars.next()?.to_ascii_uppercase();
+            let value = chars.as_str().parse().ok()?;
+
+            Some((letter, value))
+        })
+        .collect();
+
+    Some((command, params))
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub e: f32,
+}
+
+/// A move of the toolhead
+#[derive(Clone, Copy, Debug)]
+pub struct Move {
+    pub from: Position,
+    pub to: Position,
+}
+
+impl Move {
+    pub fn moves_xy(&self) -> bool {
+        self.from.x != self.to.x || self.from.y != self.to.y
+    }
+
+    /// True if filament is extruded while moving across the bed. Retractions and moves that only
+    /// prime the nozzle are not extrusions.
+    pub fn extrudes(&self) -> bool {
+        self.moves_xy() && self.to.e > self.from.e
+    }
+}
+
+/// Follows the toolhead's position through a GCode file
+#[derive(Default)]
+pub struct Motion {
+    pub position: Position,
+    relative_xyz: bool,
+    relative_e: bool,
+}
+
+impl Motion {
+    /// Updates the position from a line of GCode, returning the move if the line moved the
+    /// toolhead. Arcs are treated as straight moves to their end point.
+    pub fn update(&mut self, line: &str) -> Option<Move> {
+        let (command, params) = parse_line(line)?;
+        let param = |letter: char| {
+            params
+                .iter()
+                .find(|(param, _)| *param == letter)
+                .map(|(_, value)| *value)
+        };
+
+        match command {
+            "G0" | "G1" | "G2" | "G3" => {
+                let axis = |value: Option<f32>, current: f32, relative: bool| match value {
+                    Some(value) if relative => current + value,
+                    Some(value) => value,
+                    None => current,
+                };
+
+                let from = self.position;
+                let to = Position {
+                    x: axis(param('X'), from.x, self.relative_xyz),
+                    y: axis(param('Y'), from.y, self.relative_xyz),
+                    z: axis(param('Z'), from.z, self.relative_xyz),
+                    e: axis(param('E'), from.e, self.relative_e),
+                };
+
+                self.position = to;
+                return Some(Move { from, to });
+            }
+            "G90" => {
+                self.relative_xyz = false;
+                self.relative_e = false;
+            }
+            "G91" => {
+                self.relative_xyz = true;
+                self.relative_e = true;
+            }
+            "M82" => self.relative_e = false,
+            "M83" => self.relative_e = true,
+            "G92" => {
+                if let Some(e) = param('E') {
+                    self.position.e = e;
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
+
+/// Finds the line index of the first extrusion of each layer
+pub fn layer_starts(gcode_path: &Path) -> Result<Vec<usize>> {
+    let mut motion = Motion::default();
+    let mut layer_z = None;
+    let mut starts = vec![];
+
+    for (i, line) in BufReader::new(File::open(gcode_path)?).lines().enumerate() {
+        if let Some(next) = motion.update(&line?) {
+            if next.extrudes() && layer_z != Some(next.to.z) {
+                layer_z = Some(next.to.z);
+                starts.push(i);
+            }
+        }
+    }
+
+    Ok(starts)
+}
+
+/// Rewrites the GCode line by line. The GCode is written to a new file which then replaces the
+/// original so that a partially written file is never served.
+pub fn rewrite(
+    gcode_path: &Path,
+    mut rewrite_line: impl FnMut(usize, &str, &mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    let tmp_path = tmp_path(gcode_path)?;
+
+    let reader = BufReader::new(File::open(gcode_path)?);
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+
+    for (i, line) in reader.lines().enumerate() {
+        rewrite_line(i, &line?, &mut writer)?;
+    }
+
+    writer.flush()?;
+    drop(writer);
+
+    std::fs::rename(&tmp_path, gcode_path)?;
+
+    Ok(())
+}
+
+/// Inserts the content at the start of the GCode, after the "; generated by" line if there is
+/// one so that tools which identify the slicer from the first line continue to work.
+pub fn insert_header(gcode_path: &Path, content: &str) -> Result<()> {
+    let tmp_path = tmp_path(gcode_path)?;
+
+    let mut reader = BufReader::new(File::open(gcode_path)?);
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+
+    let is_generated_by = first_line.starts_with("; generated by");
+    if is_generated_by {
+        writer.write_all(first_line.as_bytes())?;
+    }
+    writer.write_all(content.as_bytes())?;
+    if !is_generated_by {
+        writer.write_all(first_line.as_bytes())?;
+    }
+
+    io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    drop(writer);
+
+    std::fs::rename(&tmp_path, gcode_path)?;
+
+    Ok(())
+}
+
+fn tmp_path(gcode_path: &Path) -> Result<std::path::PathBuf> {
+    let file_name = gcode_path
+        .file_name()
+        .ok_or_else(|| eyre!("Invalid GCode path"))?
+        .to_string_lossy();
+
+    Ok(gcode_path.with_file_name(format!("rewritten-{file_name}")))
+}
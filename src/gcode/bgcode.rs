@@ -0,0 +1,211 @@
+use crc32fast::Hasher;
+use eyre::{eyre, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use super::thumbnails;
+
+const MAGIC: &[u8] = b"GCDE";
+const VERSION: u32 = 1;
+const CHECKSUM_CRC32: u16 = 1;
+
+const COMPRESSION_NONE: u16 = 0;
+const METADATA_ENCODING_INI: u16 = 0;
+const GCODE_ENCODING_NONE: u16 = 0;
+const THUMBNAIL_FORMAT_PNG: u16 = 0;
+
+/// GCode is split into blocks of roughly this many bytes, matching PrusaSlicer's block size
+const GCODE_BLOCK_SIZE: usize = 64 * 1024;
+
+const PRODUCER: &str = "PrintSpool Slicing Server";
+
+/// Metadata that printers read from binary GCode, as written by PrusaSlicer. Values are copied
+/// from the matching `; key = value` comments in the plain GCode.
+const PRINTER_METADATA_KEYS: &[&str] = &[
+    "printer_model",
+    "filament_type",
+    "nozzle_diameter",
+    "bed_temperature",
+    "brim_width",
+    "fill_density",
+    "layer_height",
+    "temperature",
+    "ironing",
+    "support_material",
+    "max_layer_z",
+    "extruder_colour",
+    "filament used [mm]",
+    "filament used [g]",
+    "estimated printing time (normal mode)",
+];
+
+const PRINT_METADATA_KEYS: &[&str] = &[
+    "filament used [mm]",
+    "filament used [cm3]",
+    "filament used [g]",
+    "filament cost",
+    "total filament used [g]",
+    "total filament cost",
+    "estimated printing time (normal mode)",
+    "estimated first layer printing time (normal mode)",
+];
+
+#[derive(Clone, Copy)]
+#[repr(u16)]
+enum BlockType {
+    FileMetadata = 0,
+    Gcode = 1,
+    SlicerMetadata = 2,
+    PrinterMetadata = 3,
+    PrintMetadata = 4,
+    Thumbnail = 5,
+}
+
+/// Converts plain GCode to Prusa's binary GCode format.
+///
+/// Blocks are written uncompressed with CRC32 checksums. Thumbnails embedded in the GCode are
+/// moved into thumbnail blocks and `; key = value` comments are copied into the metadata blocks.
+pub fn write(gcode_path: &Path, output_path: &Path) -> Result<()> {
+    let metadata = read_metadata(gcode_path)?;
+    let thumbnails = thumbnails::read(gcode_path)?;
+
+    let mut out = BufWriter::new(File::create(output_path)?);
+
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&CHECKSUM_CRC32.to_le_bytes())?;
+
+    write_metadata(&mut out, BlockType::FileMetadata, &[("Producer", PRODUCER)])?;
+
+    let printer_metadata = select(&metadata, PRINTER_METADATA_KEYS);
+    write_metadata(&mut out, BlockType::PrinterMetadata, &printer_metadata)?;
+
+    for thumbnail in thumbnails {
+        let mut params = THUMBNAIL_FORMAT_PNG.to_le_bytes().to_vec();
+        params.extend(u16::try_from(thumbnail.width)?.to_le_bytes());
+        params.extend(u16::try_from(thumbnail.height)?.to_le_bytes());
+
+        write_block(&mut out, BlockType::Thumbnail, &params, &thumbnail.png)?;
+    }
+
+    let print_metadata = select(&metadata, PRINT_METADATA_KEYS);
+    write_metadata(&mut out, BlockType::PrintMetadata, &print_metadata)?;
+
+    let slicer_metadata = metadata
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
+    write_metadata(&mut out, BlockType::SlicerMetadata, &slicer_metadata)?;
+
+    write_gcode(&mut out, gcode_path)?;
+
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Reads each `; key = value` comment in the GCode
+fn read_metadata(gcode_path: &Path) -> Result<Vec<(String, String)>> {
+    let mut metadata = vec![];
+
+    for line in BufReader::new(File::open(gcode_path)?).lines() {
+        let line = line?;
+
+        let entry = line
+            .strip_prefix(';')
+            .and_then(|comment| comment.split_once(" = "));
+
+        if let Some((key, value)) = entry {
+            metadata.push((key.trim().to_owned(), value.trim().to_owned()));
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// The first value of each of the keys that is present in the metadata
+fn select<'a>(metadata: &'a [(String, String)], keys: &[&str]) -> Vec<(&'a str, &'a str)> {
+    keys.iter()
+        .filter_map(|key| metadata.iter().find(|(k, _)| k == key))
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+fn write_metadata(
+    out: &mut impl Write,
+    block_type: BlockType,
+    entries: &[(&str, &str)],
+) -> Result<()> {
+    let ini = entries
+        .iter()
+        .map(|(key, value)| format!("{key}={value}\n"))
+        .collect::<String>();
+
+    write_block(
+        out,
+        block_type,
+        &METADATA_ENCODING_INI.to_le_bytes(),
+        ini.as_bytes(),
+    )
+}
+
+/// Writes the GCode in blocks split at line endings, leaving out embedded thumbnails
+fn write_gcode(out: &mut impl Write, gcode_path: &Path) -> Result<()> {
+    let params = GCODE_ENCODING_NONE.to_le_bytes();
+    let mut block = Vec::with_capacity(GCODE_BLOCK_SIZE);
+    let mut in_thumbnail = false;
+
+    for line in BufReader::new(File::open(gcode_path)?).lines() {
+        let line = line?;
+
+        if line.starts_with("; thumbnail begin") {
+            in_thumbnail = true;
+        }
+        if in_thumbnail {
+            in_thumbnail = !line.starts_with("; thumbnail end");
+            continue;
+        }
+
+        if !block.is_empty() && block.len() + line.len() + 1 > GCODE_BLOCK_SIZE {
+            write_block(out, BlockType::Gcode, &params, &block)?;
+            block.clear();
+        }
+
+        block.extend(line.as_bytes());
+        block.push(b'\n');
+    }
+
+    if !block.is_empty() {
+        write_block(out, BlockType::Gcode, &params, &block)?;
+    }
+
+    Ok(())
+}
+
+/// Writes an uncompressed block followed by the CRC32 of it's header, parameters and data
+fn write_block(
+    out: &mut impl Write,
+    block_type: BlockType,
+    params: &[u8],
+    data: &[u8],
+) -> Result<()> {
+    let size = u32::try_from(data.len()).map_err(|_| eyre!("Binary GCode block is too large"))?;
+
+    let mut header = vec![];
+    header.extend((block_type as u16).to_le_bytes());
+    header.extend(COMPRESSION_NONE.to_le_bytes());
+    header.extend(size.to_le_bytes());
+
+    let mut hasher = Hasher::new();
+    hasher.update(&header);
+    hasher.update(params);
+    hasher.update(data);
+
+    out.write_all(&header)?;
+    out.write_all(params)?;
+    out.write_all(data)?;
+    out.write_all(&hasher.finalize().to_le_bytes())?;
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use eyre::Result;
+use serde::Serialize;
+use std::io::BufRead;
+
+use super::Motion;
+
+/// Points closer than this to the line between their neighbours are dropped from toolpaths
+const SIMPLIFY_TOLERANCE: f32 = 0.05;
+
+/// The extrusion toolpaths of each layer
+#[derive(Serialize, Default, Debug)]
+pub struct LayerSummary {
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Layer {
+    pub z: f32,
+    /// Each continuous extrusion as a flat list of X and Y coordinates, eg. [x1, y1, x2, y2]
+    pub paths: Vec<Vec<f32>>,
+}
+
+/// Reads the extrusion moves of the GCode into per-layer toolpaths. Arcs are approximated by
+/// straight lines and nearly straight runs of moves are merged to reduce the summary's size.
+pub fn summarize(gcode: impl BufRead) -> Result<LayerSummary> {
+    let mut summary = LayerSummary::default();
+
+    let mut motion = Motion::default();
+    // True if the last move extruded, ie. the next extrusion continues the same path
+    let mut extruding = false;
+
+    for line in gcode.lines() {
+        let next = match motion.update(&line?) {
+            Some(next) => next,
+            None => continue,
+        };
+
+        if next.extrudes() {
+            let is_new_layer = summary
+                .layers
+                .last()
+                .map_or(true, |layer| layer.z != next.to.z);
+
+            if is_new_layer {
+                extruding = false;
+                summary.layers.push(Layer {
+                    z: next.to.z,
+                    paths: vec![],
+                });
+            }
+
+            if let Some(layer) = summary.layers.last_mut() {
+                match layer.paths.last_mut() {
+                    Some(path) if extruding => push_point(path, next.to.x, next.to.y),
+                    _ => layer
+                        .paths
+                        .push(vec![next.from.x, next.from.y, next.to.x, next.to.y]),
+                }
+            }
+        }
+
+        extruding = next.extrudes() || (extruding && !next.moves_xy());
+    }
+
+    Ok(summary)
+}
+
+/// Adds a point to the path, replacing the last point if it lies on the line to the new point
+fn push_point(path: &mut Vec<f32>, x: f32, y: f32) {
+    let len = path.len();
+
+    if len >= 4 {
+        let (ax, ay) = (path[len - 4], path[len - 3]);
+        let (bx, by) = (path[len - 2], path[len - 1]);
+
+        let (dx, dy) = (x - ax, y - ay);
+        let length = (dx * dx + dy * dy).sqrt();
+
+        let is_between =
+            (bx - ax) * dx + (by - ay) * dy >= 0.0 && (x - bx) * dx + (y - by) * dy >= 0.0;
+
+        if length > 0.0 && is_between {
+            let distance = ((bx - ax) * dy - (by - ay) * dx).abs() / length;
+
+            if distance < SIMPLIFY_TOLERANCE {
+                path[len - 2] = x;
+                path[len - 1] = y;
+                return;
+            }
+        }
+    }
+
+    path.extend([x, y]);
+}
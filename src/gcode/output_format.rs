@@ -0,0 +1,90 @@
+use async_compression::tokio::bufread::GzipEncoder;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufReader};
+
+use super::{bgcode, three_mf};
+
+/// The file format that a job's GCode is delivered in
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, async_graphql::Enum,
+)]
+pub enum OutputFormat {
+    /// Plain text GCode
+    #[default]
+    #[serde(rename = "gcode")]
+    Gcode,
+    /// Gzip compressed GCode
+    #[serde(rename = "gcode.gz")]
+    GcodeGz,
+    /// Binary GCode, as supported by PrusaSlicer 2.7+ and Prusa's firmware
+    #[serde(rename = "bgcode")]
+    Bgcode,
+    /// GCode packaged in a 3MF file, as used by Bambu Lab printers
+    #[serde(rename = "gcode.3mf")]
+    #[graphql(name = "GCODE_3MF")]
+    Gcode3mf,
+}
+
+impl OutputFormat {
+    /// The file extension of the format, without a leading dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Gcode => "gcode",
+            OutputFormat::GcodeGz => "gcode.gz",
+            OutputFormat::Bgcode => "bgcode",
+            OutputFormat::Gcode3mf => "gcode.3mf",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Gcode => "text/x.gcode",
+            OutputFormat::GcodeGz => "application/gzip",
+            OutputFormat::Bgcode => "application/x-bgcode",
+            OutputFormat::Gcode3mf => "model/3mf",
+        }
+    }
+
+    /// True if the format is plain text, ie. downloads benefit from content encoding
+    pub fn is_text(&self) -> bool {
+        *self == OutputFormat::Gcode
+    }
+
+    /// The path of the file in this format, eg. "model.gcode.gz" for a stem of "model"
+    pub fn path(&self, dir: &Path, stem: &str) -> PathBuf {
+        dir.join(format!("{stem}.{}", self.extension()))
+    }
+}
+
+/// Converts plain GCode into the output format for engines that cannot write it themselves
+pub async fn convert(gcode_path: &Path, format: OutputFormat, output_path: &Path) -> Result<()> {
+    match format {
+        OutputFormat::Gcode => {
+            if gcode_path != output_path {
+                tokio::fs::copy(gcode_path, output_path).await?;
+            }
+        }
+        OutputFormat::GcodeGz => {
+            let gcode = BufReader::new(File::open(gcode_path).await?);
+            let mut output = File::create(output_path).await?;
+
+            tokio::io::copy(&mut GzipEncoder::new(gcode), &mut output).await?;
+            output.flush().await?;
+        }
+        OutputFormat::Bgcode | OutputFormat::Gcode3mf => {
+            let gcode_path = gcode_path.to_owned();
+            let output_path = output_path.to_owned();
+
+            tokio::task::spawn_blocking(move || match format {
+                OutputFormat::Bgcode => bgcode::write(&gcode_path, &output_path),
+                _ => three_mf::write(&gcode_path, &output_path),
+            })
+            .await??;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{insert_header, layer_starts, rewrite};
+use crate::config::{PostProcessorConfig, PostProcessorKind};
+use crate::engine::slicing_worker_command;
+
+/// The post-processors from the server's config, in the order they are run
+pub type PostProcessors = Arc<Vec<PostProcessorConfig>>;
+
+/// The name that identifies the post-processor in job errors
+fn name(post_processor: &PostProcessorConfig) -> String {
+    if let Some(name) = &post_processor.name {
+        return name.clone();
+    }
+
+    match &post_processor.kind {
+        PostProcessorKind::Progress => "progress".to_owned(),
+        PostProcessorKind::PauseAtLayer { .. } => "pause_at_layer".to_owned(),
+        PostProcessorKind::Replace { .. } => "replace".to_owned(),
+        PostProcessorKind::Header { .. } => "header".to_owned(),
+        PostProcessorKind::Command { command, .. } => command.to_string_lossy().into_owned(),
+    }
+}
+
+/// Runs each post-processor on the GCode in order, stopping at the first failure
+pub async fn run_post_processors(
+    post_processors: &[PostProcessorConfig],
+    gcode_path: &Path,
+) -> Result<()> {
+    for post_processor in post_processors {
+        run(&post_processor.kind, gcode_path)
+            .await
+            .map_err(|err| eyre!("Post-processor {} failed: {err}", name(post_processor)))?;
+    }
+
+    Ok(())
+}
+
+async fn run(kind: &PostProcessorKind, gcode_path: &Path) -> Result<()> {
+    if let PostProcessorKind::Command { command, args } = kind {
+        return run_command(command, args, gcode_path).await;
+    }
+
+    let kind = kind.clone();
+    let gcode_path = gcode_path.to_owned();
+
+    tokio::task::spawn_blocking(move || match kind {
+        PostProcessorKind::Progress => insert_progress(&gcode_path),
+        PostProcessorKind::PauseAtLayer { layers, gcode } => {
+            insert_pauses(&gcode_path, &layers, &gcode)
+        }
+        PostProcessorKind::Replace {
+            pattern,
+            replacement,
+        } => {
+            let regex = Regex::new(&pattern)?;
+
+            rewrite(&gcode_path, |_, line, out| {
+                writeln!(out, "{}", regex.replace_all(line, replacement.as_str()))?;
+                Ok(())
+            })
+        }
+        PostProcessorKind::Header { comments } => {
+            let header = comments
+                .iter()
+                .map(|comment| format!("; {comment}\n"))
+                .collect::<String>();
+
+            insert_header(&gcode_path, &header)
+        }
+        // Commands are run asynchronously above
+        PostProcessorKind::Command { .. } => Ok(()),
+    })
+    .await?
+}
+
+/// Inserts "M73 P{percent}" before each layer, where the percent is the fraction of layers that
+/// have already been printed, and "M73 P100" once the last layer is done
+fn insert_progress(gcode_path: &Path) -> Result<()> {
+    let starts = layer_starts(gcode_path)?;
+    let mut layer = 0;
+
+    rewrite(gcode_path, |i, line, out| {
+        if starts.get(layer) == Some(&i) {
+            writeln!(out, "M73 P{}", layer * 100 / starts.len())?;
+            layer += 1;
+        }
+        writeln!(out, "{line}")?;
+
+        Ok(())
+    })?;
+
+    let mut gcode = std::fs::OpenOptions::new().append(true).open(gcode_path)?;
+    writeln!(gcode, "M73 P100")?;
+
+    Ok(())
+}
+
+/// Inserts the GCode before each of the layers. Layers past the end of the print are ignored so
+/// that short prints are not affected.
+fn insert_pauses(gcode_path: &Path, layers: &[usize], gcode: &str) -> Result<()> {
+    let starts = layer_starts(gcode_path)?;
+
+    let pause_lines = layers
+        .iter()
+        .filter_map(|layer| starts.get(layer.checked_sub(1)?))
+        .copied()
+        .collect::<HashSet<_>>();
+
+    rewrite(gcode_path, |i, line, out| {
+        if pause_lines.contains(&i) {
+            writeln!(out, "{gcode}")?;
+        }
+        writeln!(out, "{line}")?;
+
+        Ok(())
+    })
+}
+
+async fn run_command(command: &Path, args: &[String], gcode_path: &Path) -> Result<()> {
+    let gcode = gcode_path.to_string_lossy();
+
+    let mut cmd_args = args
+        .iter()
+        .map(|arg| OsString::from(arg.replace("{gcode}", &gcode)))
+        .collect::<Vec<_>>();
+
+    if !args.iter().any(|arg| arg.contains("{gcode}")) {
+        cmd_args.push(gcode_path.into());
+    }
+
+    let output = slicing_worker_command(command, cmd_args).output().await?;
+
+    if !output.status.success() {
+        return Err(eyre!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+
+    Ok(())
+}
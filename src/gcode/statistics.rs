@@ -0,0 +1,58 @@
+use eyre::Result;
+use std::io::BufRead;
+
+use super::{parse_line, Motion};
+
+/// Statistics read from a job's GCode
+#[derive(async_graphql::SimpleObject, Clone, Debug, Default)]
+pub struct GcodeStatistics {
+    /// The filament used by each extruder that printed part of the job
+    pub extruders: Vec<ExtruderUsage>,
+}
+
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct ExtruderUsage {
+    /// The extruder, starting at 1 for T0
+    pub extruder: u32,
+    /// The length of filament fed into the extruder, in millimeters. Retractions that are
+    /// followed by an equal unretraction are not counted.
+    pub filament_used_mm: f32,
+}
+
+/// Totals the filament extruded by each tool. Tool changes are read from T commands, eg. "T1".
+pub fn read_statistics(gcode: impl BufRead) -> Result<GcodeStatistics> {
+    let mut motion = Motion::default();
+    let mut tool = 0;
+    let mut filament_used = vec![0.0f32];
+
+    for line in gcode.lines() {
+        let line = line?;
+
+        let tool_change = parse_line(&line)
+            .and_then(|(command, _)| command.strip_prefix('T')?.parse::<u8>().ok());
+
+        if let Some(next_tool) = tool_change {
+            tool = next_tool as usize;
+            if filament_used.len() <= tool {
+                filament_used.resize(tool + 1, 0.0);
+            }
+            continue;
+        }
+
+        if let Some(next) = motion.update(&line) {
+            filament_used[tool] += next.to.e - next.from.e;
+        }
+    }
+
+    let extruders = filament_used
+        .into_iter()
+        .enumerate()
+        .filter(|(_, filament_used_mm)| *filament_used_mm > 0.0)
+        .map(|(tool, filament_used_mm)| ExtruderUsage {
+            extruder: tool as u32 + 1,
+            filament_used_mm,
+        })
+        .collect();
+
+    Ok(GcodeStatistics { extruders })
+}
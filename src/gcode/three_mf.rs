@@ -0,0 +1,95 @@
+use eyre::Result;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::thumbnails;
+
+const GCODE_PATH: &str = "Metadata/plate_1.gcode";
+const THUMBNAIL_PATH: &str = "Metadata/plate_1.png";
+
+/// Packages the GCode as a single plate 3MF in the layout written by Bambu Studio and
+/// OrcaSlicer. The largest thumbnail embedded in the GCode is used as the plate's thumbnail.
+pub fn write(gcode_path: &Path, output_path: &Path) -> Result<()> {
+    let thumbnail = thumbnails::read(gcode_path)?
+        .into_iter()
+        .max_by_key(|thumbnail| thumbnail.width * thumbnail.height);
+
+    let mut zip = ZipWriter::new(File::create(output_path)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("[Content_Types].xml", options)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+  <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+  <Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+  <Default Extension="png" ContentType="image/png"/>
+  <Default Extension="gcode" ContentType="text/x.gcode"/>
+</Types>
+"#,
+    )?;
+
+    zip.start_file("_rels/.rels", options)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#,
+    )?;
+
+    // The models are not needed to print the plate so the model file is left empty
+    zip.start_file("3D/3dmodel.model", options)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8"?>
+<model unit="millimeter" xml:lang="en-US" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">
+  <resources>
+  </resources>
+  <build>
+  </build>
+</model>
+"#,
+    )?;
+
+    zip.start_file("Metadata/slice_info.config", options)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+  <plate>
+    <metadata key="index" value="1"/>
+  </plate>
+</config>
+"#,
+    )?;
+
+    if let Some(thumbnail) = thumbnail {
+        zip.start_file(THUMBNAIL_PATH, options)?;
+        zip.write_all(&thumbnail.png)?;
+    }
+
+    // Printers check the GCode against it's MD5 before printing
+    zip.start_file(GCODE_PATH, options)?;
+
+    let mut gcode = BufReader::new(File::open(gcode_path)?);
+    let mut md5 = md5::Context::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let len = gcode.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+
+        md5.consume(&buf[..len]);
+        zip.write_all(&buf[..len])?;
+    }
+
+    zip.start_file(format!("{GCODE_PATH}.md5"), options)?;
+    zip.write_all(format!("{:X}", md5.compute()).as_bytes())?;
+
+    zip.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use eyre::{eyre, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use super::insert_header;
+
+/// The thumbnail sizes embedded in GCode, matching PrusaSlicer's defaults for printers with screens
+pub const THUMBNAIL_SIZES: [(u32, u32); 2] = [(16, 16), (220, 124)];
+
+/// The length of each line of base64 in a thumbnail block
+const LINE_LEN: usize = 78;
+
+/// Formats a PNG as a GCode thumbnail block in PrusaSlicer's format
+pub fn thumbnail_block(width: u32, height: u32, png: &[u8]) -> String {
+    let encoded = STANDARD.encode(png);
+
+    let mut block = format!("; thumbnail begin {width}x{height} {}\n", encoded.len());
+    for line in encoded.as_bytes().chunks(LINE_LEN) {
+        block.push_str("; ");
+        block.push_str(&String::from_utf8_lossy(line));
+        block.push('\n');
+    }
+    block.push_str("; thumbnail end\n;\n");
+
+    block
+}
+
+/// A thumbnail embedded in GCode
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub png: Vec<u8>,
+}
+
+/// Reads the thumbnails embedded in the GCode's header comments
+pub fn read(gcode_path: &Path) -> Result<Vec<Thumbnail>> {
+    let mut thumbnails = vec![];
+    // The size and base64 content of the thumbnail being read
+    let mut current: Option<(u32, u32, String)> = None;
+
+    for line in BufReader::new(File::open(gcode_path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if let Some(params) = line.strip_prefix("; thumbnail begin ") {
+            let (width, height) = params
+                .split_whitespace()
+                .next()
+                .and_then(|size| size.split_once('x'))
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                .ok_or_else(|| eyre!("Invalid thumbnail size: {params:?}"))?;
+
+            current = Some((width, height, String::new()));
+        } else if line.starts_with("; thumbnail end") {
+            if let Some((width, height, encoded)) = current.take() {
+                thumbnails.push(Thumbnail {
+                    width,
+                    height,
+                    png: STANDARD.decode(encoded)?,
+                });
+            }
+        } else if let Some((_, _, encoded)) = &mut current {
+            encoded.push_str(line.trim_start_matches(';').trim());
+        } else if !line.is_empty() && !line.starts_with(';') {
+            break;
+        }
+    }
+
+    Ok(thumbnails)
+}
+
+/// True if the GCode's header comments already contain a thumbnail, eg. one added by the engine
+fn has_thumbnail(gcode_path: &Path) -> Result<bool> {
+    for line in BufReader::new(File::open(gcode_path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.starts_with("; thumbnail begin") {
+            return Ok(true);
+        }
+        if !line.is_empty() && !line.starts_with(';') {
+            break;
+        }
+    }
+
+    Ok(false)
+}
+
+/// Inserts the thumbnail blocks at the start of the GCode. GCode that already contains thumbnails
+/// is left as it is.
+pub fn embed(gcode_path: &Path, blocks: &[String]) -> Result<()> {
+    if blocks.is_empty() || has_thumbnail(gcode_path)? {
+        return Ok(());
+    }
+
+    insert_header(gcode_path, &format!(";\n{}", blocks.concat()))
+}
@@ -1,5 +1,5 @@
 use async_graphql::futures_util::StreamExt;
-use async_graphql::ID;
+use async_graphql::{Context, FieldResult, ID};
 use chrono::DateTime;
 use chrono::Utc;
 use dashmap::DashMap;
@@ -8,21 +8,66 @@ use eyre::ContextCompat;
 use eyre::Result;
 use std::{path::PathBuf, sync::Arc};
 use tempfile::TempDir;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
 use tracing::info;
 use tracing::warn;
 
-use crate::engine::ENGINES;
+use self::model_analysis::ModelReport;
+use self::plate::ArrangedObject;
+use self::preview::{generate_preview, Preview};
+pub use self::queue::JobQueue;
+use crate::auth::{ClientKeyId, Scope, Scopes};
+use crate::config::Config;
+use crate::engine::{self, Engines};
+use crate::error::{AppError, AppResult};
+use crate::gcode::output_format::{convert, OutputFormat};
+use crate::gcode::post_processing::{run_post_processors, PostProcessors};
+use crate::gcode::statistics::{read_statistics, GcodeStatistics};
+use crate::routes;
+use crate::url_signer::UrlSigner;
 
+pub mod cancel_job_mutation;
 pub mod create_job_mutation;
+pub mod gcode_download;
+pub mod model_analysis;
+pub mod model_transform;
+pub mod plate;
+pub mod preview;
+pub mod queue;
+pub mod queue_mutation;
+pub mod quota;
 
 pub struct Job {
     pub id: ID,
     pub temp_dir: TempDir,
-    pub src: async_graphql::UploadValue,
-    pub src_path: PathBuf,
-    pub config: async_graphql::UploadValue,
+    /// The uploaded models
+    pub src: Vec<async_graphql::UploadValue>,
+    /// The model files passed to the engine. Plates of several objects are usually assembled into
+    /// a single file.
+    pub src_paths: Vec<PathBuf>,
+    /// The uploaded slicing profile, if the job does not use a stored profile
+    pub config: Option<async_graphql::UploadValue>,
     pub config_path: PathBuf,
+    /// The plain GCode generated by the engine, which is post-processed and previewed before
+    /// being converted to the output format
+    pub gcode_path: PathBuf,
+    pub output_format: OutputFormat,
+    /// The file that is downloaded once the job has completed
+    pub output_path: PathBuf,
+    /// The analysis of each uploaded model that the server can read
+    pub model_reports: Vec<ModelReport>,
+    /// Where the server placed each object, if the job's plate was arranged
+    pub arrangement: Option<Vec<ArrangedObject>>,
+    /// Thumbnails and layer previews, generated once the job has been sliced
+    pub preview: Option<Preview>,
+    /// Filament usage read from the GCode once the job has been sliced
+    pub statistics: Option<GcodeStatistics>,
     pub engine_url: String,
+    /// The client key (JWT kid) that created the job, if any
+    pub key_id: Option<String>,
+    /// Jobs with higher priorities are sliced first
+    pub priority: i32,
     pub status: JobStatus,
     pub percent_complete: f32,
     pub created_at: DateTime<Utc>,
@@ -37,17 +82,65 @@ pub enum JobStatus {
 }
 
 pub type JobMap = Arc<DashMap<ID, Job>>;
-pub type JobQueue = tokio::sync::mpsc::UnboundedSender<ID>;
+
+/// The error message of cancelled jobs
+pub const JOB_CANCELLED: &str = "Job cancelled";
 
 #[derive(async_graphql::SimpleObject)]
-#[graphql(name = "Job")]
+#[graphql(name = "Job", complex)]
 pub struct JobGraphQL {
     id: ID,
     is_done: bool,
     error: Option<JobError>,
     engine_url: String,
+    priority: i32,
     percent_complete: f32,
+    /// The GCode download path. Requires the same authorization as the GraphQL API.
     gcode_url: String,
+    output_format: OutputFormat,
+    /// The MIME type of the GCode download
+    content_type: String,
+    /// The analysis of each uploaded model that the server can read
+    model_reports: Vec<ModelReport>,
+    /// Where the server placed each object, if the job's plate was arranged
+    arrangement: Option<Vec<ArrangedObject>>,
+    /// A PNG thumbnail of the sliced models, once the job has completed
+    thumbnail_url: Option<String>,
+    /// A JSON summary of the extrusion toolpaths of each layer, once the job has completed
+    layers_url: Option<String>,
+    /// The filament used by each extruder, once the job has completed. Not available if the
+    /// engine wrote the output format itself.
+    statistics: Option<GcodeStatistics>,
+}
+
+#[async_graphql::ComplexObject]
+impl JobGraphQL {
+    /// A short-lived GCode download path that does not require a JWT, eg. for printers
+    async fn signed_gcode_url<'ctx>(&self, ctx: &'ctx Context<'_>) -> FieldResult<String> {
+        let url_signer: &UrlSigner = ctx.data()?;
+
+        Ok(url_signer.sign(&self.gcode_url)?)
+    }
+
+    /// The job's position in the queue while it is waiting. The next job to start is at 0.
+    async fn queue_position<'ctx>(&self, ctx: &'ctx Context<'_>) -> FieldResult<Option<u32>> {
+        let job_queue: &JobQueue = ctx.data()?;
+
+        Ok(job_queue.position(&self.id).map(|position| position as u32))
+    }
+
+    /// When the job is expected to start slicing, estimated from the duration of recent jobs.
+    /// Only available while the job is waiting and once a job has completed.
+    async fn estimated_start_time<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+    ) -> FieldResult<Option<String>> {
+        let job_queue: &JobQueue = ctx.data()?;
+
+        Ok(job_queue
+            .estimated_start_time(&self.id)
+            .map(|start_time| start_time.to_rfc3339()))
+    }
 }
 
 #[derive(async_graphql::SimpleObject)]
@@ -56,10 +149,6 @@ pub struct JobError {
 }
 
 impl Job {
-    pub fn gcode_path(&self) -> PathBuf {
-        self.src_path.with_extension(".gcode")
-    }
-
     pub fn graphql(&self) -> JobGraphQL {
         let error = if let JobStatus::Errored((message, _)) = &self.status {
             Some(JobError {
@@ -74,37 +163,189 @@ impl Job {
             is_done: matches!(self.status, JobStatus::Completed(_)),
             error,
             engine_url: self.engine_url.clone(),
+            priority: self.priority,
             percent_complete: self.percent_complete,
-            gcode_url: format!("/job/{}/gcode", &self.id.0),
+            gcode_url: routes::job_gcode(&self.id),
+            output_format: self.output_format,
+            content_type: self.output_format.content_type().to_owned(),
+            model_reports: self.model_reports.clone(),
+            arrangement: self.arrangement.clone(),
+            thumbnail_url: self
+                .preview
+                .as_ref()
+                .and_then(|preview| preview.thumbnail_path.as_ref())
+                .map(|_| routes::job_thumbnail(&self.id)),
+            layers_url: self
+                .preview
+                .as_ref()
+                .and_then(|preview| preview.layers_path.as_ref())
+                .map(|_| routes::job_layers(&self.id)),
+            statistics: self.statistics.clone(),
         }
     }
 
-    pub async fn run(jobs: &JobMap, job_id: &ID) -> Result<()> {
-        let mut job = jobs.get_mut(job_id).wrap_err("Unable to find job")?;
-        job.status = JobStatus::Started;
+    /// Whether the job was created by the client key
+    pub fn is_owned_by(&self, key_id: Option<&ClientKeyId>) -> bool {
+        match (&self.key_id, key_id) {
+            (Some(job_key_id), Some(ClientKeyId(key_id))) => job_key_id == key_id,
+            _ => false,
+        }
+    }
+
+    /// Keys can read the jobs that they created with the read_own_jobs scope and every job with
+    /// the read_all_jobs scope
+    pub fn can_read(&self, key_id: Option<&ClientKeyId>, scopes: &Scopes) -> bool {
+        scopes.contains(Scope::ReadAllJobs)
+            || (scopes.contains(Scope::ReadOwnJobs) && self.is_owned_by(key_id))
+    }
+
+    /// Keys can move and cancel the jobs that they created with the submit_jobs scope and every
+    /// job with the admin scope
+    pub fn can_modify(&self, key_id: Option<&ClientKeyId>, scopes: &Scopes) -> bool {
+        scopes.contains(Scope::Admin)
+            || (scopes.contains(Scope::SubmitJobs) && self.is_owned_by(key_id))
+    }
 
-        let job = jobs.get(&job_id).wrap_err("Unable to find job")?;
+    /// Checks that the GraphQL request's client key can read the job. Jobs that cannot be read
+    /// are reported as not found so that other keys' job ids are not revealed.
+    pub fn check_readable(&self, ctx: &Context<'_>) -> FieldResult<()> {
+        let scopes: &Scopes = ctx.data()?;
+
+        if !self.can_read(ctx.data_opt::<ClientKeyId>(), scopes) {
+            return Err(eyre!("Job not found").into());
+        }
 
-        let src_path = job.src_path.clone();
+        Ok(())
+    }
+
+    /// Checks that an HTTP request can download the job's files. Requests authorized by a signed
+    /// URL have no client key and are limited to the file that the URL was signed for instead.
+    pub fn check_downloadable(
+        &self,
+        key_id: Option<&ClientKeyId>,
+        scopes: Option<&Scopes>,
+    ) -> AppResult<()> {
+        match scopes {
+            Some(scopes) if !self.can_read(key_id, scopes) => {
+                Err(AppError::not_found(eyre!("Job not found")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the GraphQL request's client key can move or cancel the job
+    pub fn check_modifiable(&self, ctx: &Context<'_>) -> FieldResult<()> {
+        let scopes: &Scopes = ctx.data()?;
+        let key_id = ctx.data_opt::<ClientKeyId>();
+
+        if self.can_modify(key_id, scopes) {
+            Ok(())
+        } else if self.can_read(key_id, scopes) {
+            Err(eyre!(
+                "Forbidden: only the client key that created the job or an admin can modify it"
+            )
+            .into())
+        } else {
+            Err(eyre!("Job not found").into())
+        }
+    }
+
+    pub async fn run(
+        jobs: &JobMap,
+        engines: &Engines,
+        post_processors: &PostProcessors,
+        job_id: &ID,
+    ) -> Result<()> {
+        let job = {
+            let mut job = jobs.get_mut(job_id).wrap_err("Unable to find job")?;
+            job.status = JobStatus::Started;
+            job.downgrade()
+        };
+
+        let src_paths = job.src_paths.clone();
         let config_path = job.config_path.clone();
-        let gcode_path = job.gcode_path();
+        let gcode_path = job.gcode_path.clone();
+        let output_format = job.output_format;
+        let output_path = job.output_path.clone();
+        let preview_dir = job.temp_dir.path().join("preview");
 
-        let release = ENGINES
-            .values()
-            .filter_map(|engine| engine.release_config.parse(&job.engine_url).ok())
-            .next()
-            .ok_or_else(|| eyre!("Engine not found for release url: {:?}", &job.engine_url))?;
+        let (engine, release) = engines.find_release(&job.engine_url)?;
 
         drop(job);
 
-        let mut job_stream = release.generate_gcode(src_path, config_path, gcode_path);
+        // Engines write the output format themselves if they can, unless plain GCode is needed for
+        // post-processing
+        let is_native = output_format != OutputFormat::Gcode
+            && post_processors.is_empty()
+            && engine
+                .native_output_formats(&release)
+                .contains(&output_format);
+
+        let (engine_output_path, engine_output_format) = if is_native {
+            (output_path.clone(), output_format)
+        } else {
+            (gcode_path.clone(), OutputFormat::Gcode)
+        };
+
+        let mut job_stream = engine::generate_gcode(
+            engine,
+            release,
+            src_paths.clone(),
+            config_path,
+            engine_output_path,
+            engine_output_format,
+        );
 
         while let Some(precent_complete) = job_stream.next().await {
             let mut job = jobs.get_mut(job_id).wrap_err("Unable to find job")?;
             job.percent_complete = precent_complete?;
         }
 
+        let mut statistics = None;
+
+        if !is_native {
+            run_post_processors(post_processors, &gcode_path).await?;
+
+            let statistics_gcode_path = gcode_path.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                read_statistics(std::io::BufReader::new(std::fs::File::open(
+                    statistics_gcode_path,
+                )?))
+            })
+            .await?;
+
+            match result {
+                Ok(result) => statistics = Some(result),
+                Err(err) => warn!("Unable to read GCode statistics: {:?}", err),
+            }
+        }
+
+        // Previews are a convenience so the job still completes if they cannot be generated. The
+        // GCode's layers can only be previewed if the server has the plain GCode.
+        let preview_gcode_path = (!is_native).then(|| gcode_path.clone());
+
+        let preview = match generate_preview(src_paths, preview_gcode_path, preview_dir).await {
+            Ok(preview) => Some(preview),
+            Err(err) => {
+                warn!("Unable to generate preview: {:?}", err);
+                None
+            }
+        };
+
+        if !is_native && output_path != gcode_path {
+            convert(&gcode_path, output_format, &output_path).await?;
+            tokio::fs::remove_file(&gcode_path).await?;
+        }
+
         let mut job = jobs.get_mut(job_id).wrap_err("Unable to find job")?;
+
+        // The job may have been cancelled while it's output was being finished
+        if matches!(job.status, JobStatus::Errored(_)) {
+            return Err(eyre!(JOB_CANCELLED));
+        }
+
+        job.preview = preview;
+        job.statistics = statistics;
         job.status = JobStatus::Completed(Utc::now());
         info!("Slicing... [DONE]");
 
@@ -112,17 +353,72 @@ impl Job {
     }
 }
 
-pub async fn run_job_queue(
+/// Starts processing queued jobs in a background task and returns the queue to submit jobs to.
+///
+/// Up to `max_concurrent_jobs` jobs are sliced at a time, in priority order. The task runs until
+/// it is aborted.
+pub fn spawn_job_queue(
     jobs: JobMap,
-    mut job_queue_rx: tokio::sync::mpsc::UnboundedReceiver<ID>,
-) -> Result<()> {
-    while let Some(job_id) = job_queue_rx.recv().await {
-        if let Err(err) = Job::run(&jobs, &job_id).await {
-            warn!("Slicing Failure: {:?}", err);
-            let mut job = jobs.get_mut(&job_id).wrap_err("Unable to find job")?;
-            job.status = JobStatus::Errored((err.to_string(), Utc::now()));
-        }
-    }
+    engines: Engines,
+    config: Arc<Config>,
+) -> (JobQueue, JoinHandle<()>) {
+    let job_queue = JobQueue::new(config.max_concurrent_jobs.unwrap_or(1));
+    let task = tokio::spawn(run_job_queue(jobs, engines, config, job_queue.clone()));
+
+    (job_queue, task)
+}
+
+async fn run_job_queue(jobs: JobMap, engines: Engines, config: Arc<Config>, job_queue: JobQueue) {
+    let post_processors: PostProcessors = Arc::new(config.post_processors.clone());
+    let slots = Arc::new(Semaphore::new(job_queue.concurrency()));
 
-    Ok(())
+    loop {
+        let slot = Arc::clone(&slots)
+            .acquire_owned()
+            .await
+            .expect("The job queue's semaphore is never closed");
+
+        let job_id = job_queue.pop(&config).await;
+
+        tokio::spawn({
+            let jobs = jobs.clone();
+            let engines = engines.clone();
+            let post_processors = post_processors.clone();
+            let job_queue = job_queue.clone();
+
+            async move {
+                // Each job runs in it's own task so that a panic while slicing only fails that job
+                let mut slicing_task = tokio::spawn({
+                    let jobs = jobs.clone();
+                    let job_id = job_id.clone();
+                    async move { Job::run(&jobs, &engines, &post_processors, &job_id).await }
+                });
+
+                let result = tokio::select! {
+                    result = &mut slicing_task => result
+                        .map_err(|err| eyre!("Slicing task failed: {err}"))
+                        .and_then(|result| result),
+                    _ = job_queue.cancelled(&job_id) => {
+                        // Aborting the task kills the engine or post-processor that is running
+                        slicing_task.abort();
+                        Err(eyre!(JOB_CANCELLED))
+                    }
+                };
+
+                job_queue.finish(&job_id, result.is_ok());
+                drop(slot);
+
+                if let Err(err) = result {
+                    warn!("Slicing Failure: {:?}", err);
+
+                    match jobs.get_mut(&job_id) {
+                        Some(mut job) => {
+                            job.status = JobStatus::Errored((err.to_string(), Utc::now()))
+                        }
+                        None => warn!("Unable to find job {:?} to record its failure", job_id),
+                    }
+                }
+            }
+        });
+    }
 }
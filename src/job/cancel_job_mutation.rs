@@ -0,0 +1,46 @@
+use super::{JobGraphQL, JobMap, JobQueue, JobStatus, JOB_CANCELLED};
+use crate::auth::{Scope, ScopeGuard};
+use async_graphql::{FieldResult, ID};
+use chrono::Utc;
+use eyre::eyre;
+use tracing::instrument;
+
+#[derive(Default)]
+pub struct CancelJobMutation;
+
+#[derive(async_graphql::InputObject)]
+struct CancelJobInput {
+    id: ID,
+}
+
+#[async_graphql::Object]
+impl CancelJobMutation {
+    /// Removes a waiting job from the queue or stops slicing a running job. Cancelled jobs fail
+    /// with a "Job cancelled" error.
+    #[instrument(skip(self, input, ctx))]
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs])")]
+    async fn cancel_job<'ctx>(
+        &self,
+        ctx: &'ctx async_graphql::Context<'_>,
+        input: CancelJobInput,
+    ) -> FieldResult<JobGraphQL> {
+        let jobs: &JobMap = ctx.data()?;
+        let job_queue: &JobQueue = ctx.data()?;
+
+        let mut job = jobs
+            .get_mut(&input.id)
+            .ok_or_else(|| eyre!("Job not found"))?;
+        job.check_modifiable(ctx)?;
+
+        // The status is checked while the job is locked so that a job cannot complete between
+        // the check and it being cancelled
+        if !matches!(job.status, JobStatus::Waiting | JobStatus::Started) {
+            return Err(eyre!("Only waiting or running jobs can be cancelled").into());
+        }
+
+        job_queue.cancel(&input.id)?;
+        job.status = JobStatus::Errored((JOB_CANCELLED.to_owned(), Utc::now()));
+
+        Ok(job.graphql())
+    }
+}
@@ -1,37 +1,136 @@
+use super::model_analysis::analyze_model;
+use super::model_transform::TransformInput;
+use super::plate::{arrange_plate, assemble_plate, check_print_volume, PlateObject};
+use super::quota::{check_quota, queue_within_quota, JobHistory};
 use super::{Job, JobGraphQL, JobMap, JobQueue, JobStatus};
-use async_graphql::{FieldResult, UploadValue};
+use crate::auth::{ClientKeyId, Scope, ScopeGuard};
+use crate::config::Config;
+use crate::engine::{Engines, SlicingEngine};
+use crate::gcode::output_format::OutputFormat;
+use crate::mesh::arrange::BedShape;
+use crate::profile::conversion::convert_profile;
+use crate::profile::settings::ProfileSettings;
+use crate::profile::validation;
+use crate::profile::Profiles;
+use async_graphql::{FieldResult, UploadValue, ID};
 use chrono::{Duration, Utc};
-use eyre::Result;
-use std::{os::unix::prelude::AsRawFd, path::PathBuf};
-use tempfile::TempDir;
-use tracing::instrument;
+use eyre::{eyre, Context as _, Result};
+use std::{
+    os::unix::prelude::AsRawFd,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::fs;
+use tracing::{instrument, warn};
 
 #[derive(Default)]
 pub struct CreateJobMutation;
 
-#[derive(async_graphql::InputObject)]
-struct Vec3Input {
-    x: f64,
-    y: f64,
-    z: f64,
-}
-
 #[derive(async_graphql::InputObject)]
 struct CreateJobInput {
-    src: async_graphql::Upload,
-    config: async_graphql::Upload,
+    /// The model to slice. Either src or objects must be provided.
+    src: Option<async_graphql::Upload>,
+    /// A plate of objects to slice into a single GCode file
+    objects: Option<Vec<JobObjectInput>>,
+    /// The slicing profile to use. Either config or profileId must be provided.
+    config: Option<async_graphql::Upload>,
+    /// The id of a slicing profile stored on the server to use in place of a config upload
+    profile_id: Option<ID>,
     /// The engine to use to generate the GCode
     #[graphql(name = "engineURL")]
     engine_url: String,
+    /// Positions the src model on the bed. When set, the engine's transformMat4 is also applied by
+    /// the server so the model should be uploaded untransformed.
+    transform: Option<TransformInput>,
+    /// Settings to change in the config or profile for this job only. Keys the engine does not
+    /// support are rejected. Supported settings that are missing from the config are added to it.
+    #[graphql(default)]
+    setting_overrides: Vec<SettingOverrideInput>,
+    /// Lays out the objects on the bed described by the profile's bed_shape. Only supported by
+    /// engines that allow positioning.
+    arrange: Option<ArrangeInput>,
+    /// Fixes holes, flipped normals and degenerate triangles in the models before slicing
+    #[graphql(default)]
+    repair_models: bool,
+    /// The file format to deliver the GCode in. Engines that cannot write the format themselves
+    /// generate plain GCode which is then converted by the server.
+    #[graphql(default)]
+    output_format: OutputFormat,
+    /// Selects the extruder that prints each object or volume. Only supported by engines that
+    /// accept 3MF files.
+    #[graphql(default)]
+    extruder_assignments: Vec<ExtruderAssignmentInput>,
+    /// Settings for individual extruders, eg. the filament loaded in each of them
+    #[graphql(default)]
+    extruders: Vec<ExtruderInput>,
+    /// Jobs with higher priorities are sliced first. Jobs that have waited a long time are moved
+    /// ahead of newer jobs with slightly higher priorities so that they are not starved.
+    #[graphql(default)]
+    priority: i32,
+}
+
+#[derive(async_graphql::InputObject)]
+struct JobObjectInput {
+    src: async_graphql::Upload,
+    /// Positions the object on the bed. When set, the engine's transformMat4 is also applied by
+    /// the server so the object should be uploaded untransformed.
+    transform: Option<TransformInput>,
+    /// The number of copies of the object to print
+    #[graphql(default = 1, validator(minimum = 1))]
+    copies: u32,
+    /// Settings to change for this object only. Only supported by engines that accept 3MF files.
+    #[graphql(default)]
+    setting_overrides: Vec<SettingOverrideInput>,
+}
+
+#[derive(async_graphql::InputObject)]
+struct ArrangeInput {
+    /// The gap to leave between objects, in millimeters
+    #[graphql(default = 6.0, validator(minimum = 0))]
+    spacing: f64,
+    /// Allows objects to be turned 90 degrees about the Z axis to fit more of them on the bed
+    #[graphql(default)]
+    allow_rotation: bool,
+}
+
+#[derive(async_graphql::InputObject)]
+struct ExtruderAssignmentInput {
+    /// The object's index in objects. Jobs with a src model have a single object at index 0.
+    #[graphql(default)]
+    object: u32,
+    /// The index of one of the object's volumes, eg. a part of a multi-material 3MF. When not set
+    /// the whole object is assigned to the extruder.
+    volume: Option<u32>,
+    /// The extruder, starting at 1
+    #[graphql(validator(minimum = 1))]
+    extruder: u32,
+}
+
+#[derive(async_graphql::InputObject)]
+struct ExtruderInput {
+    /// The extruder, starting at 1
+    #[graphql(validator(minimum = 1))]
+    extruder: u32,
+    /// Per-extruder settings such as temperature or filament_type. The extruder's value is
+    /// replaced in the setting's list of values. Settings that are missing from the profile are
+    /// added with this value for every extruder, so each key must be a setting of the engine.
+    setting_overrides: Vec<SettingOverrideInput>,
+}
+
+#[derive(async_graphql::InputObject)]
+struct SettingOverrideInput {
+    /// The setting's key. For INI configs with sections use "section.key".
+    key: String,
+    value: String,
 }
 
-fn move_upload_to_dir(upload: &UploadValue, temp_dir: &TempDir) -> Result<PathBuf> {
+pub fn move_upload_to_dir(upload: &UploadValue, dir: &Path) -> Result<PathBuf> {
     // Get a path to the unlinked temp file
     let fd_path =
         Into::<PathBuf>::into("/proc/self/fd/").join(upload.content.as_raw_fd().to_string());
 
     // Create a new path to move the temp file to
-    let named_file_path = temp_dir.path().join(&upload.filename);
+    let named_file_path = dir.join(&upload.filename);
 
     // Create a name in the file system for the temp file - it will automatically be cleaned up
     // by the OS when the File gets dropped.
@@ -46,6 +145,60 @@ fn move_upload_to_dir(upload: &UploadValue, temp_dir: &TempDir) -> Result<PathBu
     Ok(named_file_path)
 }
 
+/// Applies the overrides to the job's copy of the config and validates it for the engine before
+/// the job is queued, rewriting the config in it's normalized form.
+async fn prepare_config(
+    engine: &dyn SlicingEngine,
+    config_path: &Path,
+    overrides: &[SettingOverrideInput],
+    extruders: &[ExtruderInput],
+    object_setting_keys: &[&str],
+) -> FieldResult<ProfileSettings> {
+    let content = fs::read_to_string(config_path)
+        .await
+        .wrap_err("Unable to read config")?;
+
+    let mut settings = ProfileSettings::parse(engine.profile_format(), &content)?;
+
+    let overrides = overrides
+        .iter()
+        .map(|setting| (setting.key.clone(), setting.value.clone()))
+        .collect::<Vec<_>>();
+    let engine_keys = engine.setting_keys();
+    settings.apply_overrides(engine_keys.as_ref(), &overrides)?;
+    settings.check_keys(engine_keys.as_ref(), object_setting_keys.iter().copied())?;
+
+    settings.check_keys(
+        engine_keys.as_ref(),
+        extruders.iter().flat_map(|extruder| {
+            extruder
+                .setting_overrides
+                .iter()
+                .map(|setting| setting.key.as_str())
+        }),
+    )?;
+
+    for extruder in extruders {
+        for setting in &extruder.setting_overrides {
+            let index = extruder.extruder as usize - 1;
+
+            // Settings missing from the profile are added with the override's value for every
+            // extruder before the extruder's own value is set
+            if settings.get(&setting.key).is_none() {
+                settings.set_or_insert(&setting.key, &setting.value);
+            }
+            settings.set_list_item(&setting.key, index, &setting.value);
+        }
+    }
+
+    let settings =
+        validation::validate_settings(engine, settings).map_err(validation::into_graphql_error)?;
+
+    fs::write(config_path, settings.to_string()).await?;
+
+    Ok(settings)
+}
+
 fn cleanup_old_jobs(jobs: &JobMap) -> Result<()> {
     // Delete jobs that errored or completed more than an hour ago
     let deletion_threshold = Utc::now() - Duration::hours(1);
@@ -61,8 +214,10 @@ fn cleanup_old_jobs(jobs: &JobMap) -> Result<()> {
 
 #[async_graphql::Object]
 impl CreateJobMutation {
-    /// Adds a job to the server's internal queue for processing into GCode.
+    /// Adds a job to the server's internal queue for processing into GCode. Jobs are processed in
+    /// order of priority.
     #[instrument(skip(self, input, ctx))]
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs])")]
     async fn create_job<'ctx>(
         &self,
         ctx: &'ctx async_graphql::Context<'_>,
@@ -73,31 +228,309 @@ impl CreateJobMutation {
 
         cleanup_old_jobs(jobs)?;
 
-        let src = input.src.value(&ctx)?;
-        let config = input.config.value(&ctx)?;
-
         let temp_dir = tempfile::tempdir()?;
 
-        let src_path = move_upload_to_dir(&src, &temp_dir)?;
-        let config_path = move_upload_to_dir(&config, &temp_dir)?;
+        let engines: &Engines = ctx.data()?;
+        // Unknown engines are rejected before any uploads are processed or the job is queued
+        let (engine, _) = engines.find_release(&input.engine_url)?;
+
+        let object_inputs = match (input.src, input.objects) {
+            (Some(src), None) => vec![JobObjectInput {
+                src,
+                transform: input.transform,
+                copies: 1,
+                setting_overrides: vec![],
+            }],
+            (None, Some(objects)) if !objects.is_empty() => {
+                if input.transform.is_some() {
+                    return Err(eyre!("Set each object's transform instead of transform").into());
+                }
+                objects
+            }
+            _ => return Err(eyre!("Exactly one of src or objects must be provided").into()),
+        };
+
+        let key_id = ctx
+            .data_opt::<ClientKeyId>()
+            .map(|ClientKeyId(key_id)| key_id.clone());
+
+        if let Some(key_id) = &key_id {
+            let config: &Arc<Config> = ctx.data()?;
+            let job_history: &JobHistory = ctx.data()?;
+
+            if let Some(client_key) = config.authorized_keys.get(key_id) {
+                let mut upload_bytes = 0;
+                for object in &object_inputs {
+                    upload_bytes += object.src.value(&ctx)?.content.metadata()?.len();
+                }
+                if let Some(config_upload) = &input.config {
+                    upload_bytes += config_upload.value(&ctx)?.content.metadata()?.len();
+                }
+
+                check_quota(key_id, &client_key.limits, jobs, job_history, upload_bytes)?;
+            }
+        }
+
+        if input.arrange.is_some() {
+            let metadata = engine.metadata();
+
+            if !metadata.allows_positioning {
+                return Err(eyre!(
+                    "{} positions models itself and cannot arrange plates",
+                    metadata.name,
+                )
+                .into());
+            }
+        }
+
+        let mut src = vec![];
+        let mut objects = vec![];
+        let mut model_reports = vec![];
+
+        for (i, object) in object_inputs.into_iter().enumerate() {
+            let upload = object.src.value(&ctx)?;
+
+            // Each object gets it's own directory in case several uploads share a file name
+            let dir = temp_dir.path().join("objects").join(i.to_string());
+            fs::create_dir_all(&dir).await?;
+            let src_path = move_upload_to_dir(&upload, &dir)?;
+
+            if let Some(report) =
+                analyze_model(&upload.filename, &src_path, input.repair_models).await?
+            {
+                model_reports.push(report);
+            }
+
+            let transform = match object.transform {
+                Some(transform) => Some(transform),
+                // Arranged objects are always transformed by the server
+                None if input.arrange.is_some() => Some(TransformInput::default()),
+                None => None,
+            };
+
+            let transform = match transform {
+                Some(transform) => {
+                    let metadata = engine.metadata();
+
+                    if !metadata.allows_positioning && transform.is_positioned() {
+                        return Err(eyre!(
+                            "{} positions models itself and does not accept positioned plates",
+                            metadata.name,
+                        )
+                        .into());
+                    }
+
+                    if input.arrange.is_some() && transform.is_positioned() {
+                        return Err(eyre!(
+                            "Objects cannot be positioned when the plate is arranged"
+                        )
+                        .into());
+                    }
+
+                    Some(transform.to_engine_matrix(metadata)?)
+                }
+                None => None,
+            };
+
+            objects.push(PlateObject {
+                name: upload.filename.clone(),
+                src_path,
+                transform,
+                copies: object.copies as usize,
+                setting_overrides: object
+                    .setting_overrides
+                    .into_iter()
+                    .map(|setting| (setting.key, setting.value))
+                    .collect(),
+                extruder: None,
+                volume_extruders: vec![],
+            });
+            src.push(upload);
+        }
+
+        for assignment in &input.extruder_assignments {
+            let objects_len = objects.len();
+            let object = objects.get_mut(assignment.object as usize).ok_or_else(|| {
+                eyre!(
+                    "Extruder assigned to object {} but the job has {} objects",
+                    assignment.object,
+                    objects_len,
+                )
+            })?;
+
+            match assignment.volume {
+                Some(volume) => object
+                    .volume_extruders
+                    .push((volume as usize, assignment.extruder)),
+                None => object.extruder = Some(assignment.extruder),
+            }
+        }
+
+        let (config, config_path) = match (input.config, input.profile_id) {
+            (Some(config), None) => {
+                let config = config.value(&ctx)?;
+                let config_path = move_upload_to_dir(&config, temp_dir.path())?;
+
+                (Some(config), config_path)
+            }
+            (None, Some(profile_id)) => {
+                let profiles: &Profiles = ctx.data()?;
+
+                let profile = profiles
+                    .get(&profile_id)
+                    .ok_or_else(|| eyre!("Profile not found"))?;
+
+                let config_path = profiles.copy_to_dir(&profile, temp_dir.path()).await?;
+
+                // Profiles for other engines are used if they can be converted, eg. a
+                // PrusaSlicer profile used with SuperSlicer
+                if engine.metadata().id.0 != profile.engine_id {
+                    let content = fs::read_to_string(&config_path).await?;
+
+                    let (settings, conversion) =
+                        convert_profile(engine.as_ref(), &profile.engine_id, &content).map_err(
+                            |_| {
+                                eyre!(
+                                    "Profile {:?} is for {} and cannot be used with {}",
+                                    profile.name,
+                                    profile.engine_id,
+                                    engine.metadata().id.0,
+                                )
+                            },
+                        )?;
+
+                    if !conversion.dropped_keys.is_empty() || !conversion.approximated.is_empty() {
+                        warn!(
+                            "Profile {:?} converted for {} with differences: {:?}",
+                            profile.name,
+                            engine.metadata().id.0,
+                            conversion,
+                        );
+                    }
+
+                    fs::write(&config_path, settings.to_string()).await?;
+                }
+
+                (None, config_path)
+            }
+            _ => {
+                return Err(eyre!("Exactly one of config or profileId must be provided").into());
+            }
+        };
+
+        let object_setting_keys = objects
+            .iter()
+            .flat_map(|object| object.setting_overrides.iter().map(|(key, _)| key.as_str()))
+            .collect::<Vec<_>>();
+
+        let mut arrangement = None;
+
+        let settings = prepare_config(
+            engine.as_ref(),
+            &config_path,
+            &input.setting_overrides,
+            &input.extruders,
+            &object_setting_keys,
+        )
+        .await?;
+
+        if let Some(extruder_count) = engine.extruder_count(&settings) {
+            let max_extruder = input
+                .extruder_assignments
+                .iter()
+                .map(|assignment| assignment.extruder)
+                .chain(input.extruders.iter().map(|extruder| extruder.extruder))
+                .max();
+
+            if let Some(max_extruder) = max_extruder {
+                if max_extruder as usize > extruder_count {
+                    return Err(
+                        eyre!("The profile does not have an extruder {max_extruder}",).into(),
+                    );
+                }
+            }
+        }
+
+        // Models that do not fit the printer are rejected before they are queued rather than
+        // failing once they reach the slicer
+        if let Some(volume) = engine.print_volume(&settings) {
+            check_print_volume(volume, &objects).await?;
+        }
+
+        if let Some(arrange) = &input.arrange {
+            let bed = settings
+                .get("bed_shape")
+                .and_then(|bed_shape| BedShape::parse(&bed_shape))
+                .filter(|bed| bed.points.len() >= 3)
+                .ok_or_else(|| {
+                    eyre!("A valid bed_shape is required in the profile to arrange plates")
+                })?;
+
+            let (arranged_objects, placements) =
+                arrange_plate(bed, objects, arrange.spacing as f32, arrange.allow_rotation).await?;
+
+            objects = arranged_objects;
+            arrangement = Some(placements);
+        }
+
+        let src_paths = assemble_plate(engine.as_ref(), temp_dir.path(), objects).await?;
+
+        let id: ID = nanoid::nanoid!().into();
+
+        let output_dir = temp_dir.path().join("output");
+        fs::create_dir_all(&output_dir).await?;
+
+        let stem = src_paths[0]
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| id.0.clone());
 
         let job = Job {
-            id: nanoid::nanoid!().into(),
+            id,
+            gcode_path: OutputFormat::Gcode.path(&output_dir, &stem),
+            output_format: input.output_format,
+            output_path: input.output_format.path(&output_dir, &stem),
             temp_dir,
             src,
-            src_path,
+            src_paths,
             config,
             config_path,
+            model_reports,
+            arrangement,
+            preview: None,
+            statistics: None,
             engine_url: input.engine_url,
+            key_id,
+            priority: input.priority,
             status: JobStatus::Waiting,
             percent_complete: 0.0,
             created_at: Utc::now(),
         };
 
-        // Insert the job and return a reference to it
-        let entry_ref = jobs.entry(job.id.clone()).or_insert(job);
-        let job = entry_ref.value();
-        job_queue.send(job.id.clone())?;
+        let config: &Arc<Config> = ctx.data()?;
+        let job_id = job.id.clone();
+        let key_id = job.key_id.clone();
+        let limits = key_id
+            .as_ref()
+            .and_then(|key_id| config.authorized_keys.get(key_id))
+            .map(|client_key| &client_key.limits);
+
+        let queue_job = || {
+            let (key_id, priority) = (job.key_id.clone(), job.priority);
+            jobs.insert(job_id.clone(), job);
+            job_queue.push(job_id.clone(), key_id, priority);
+        };
+
+        // The job is only counted against the client key's limits once it is queued
+        match (&key_id, limits) {
+            (Some(key_id), Some(limits)) => {
+                let job_history: &JobHistory = ctx.data()?;
+                queue_within_quota(key_id, limits, jobs, job_history, queue_job)?;
+            }
+            _ => queue_job(),
+        }
+
+        let job = jobs.get(&job_id).ok_or_else(|| eyre!("Job not found"))?;
 
         Ok(job.graphql())
     }
@@ -0,0 +1,381 @@
+use super::JobStatus;
+use crate::auth::{ClientKeyId, Scopes};
+use crate::error::{AppError, AppResult};
+use crate::server::SharedState;
+use async_compression::tokio::bufread::{GzipEncoder, ZstdEncoder};
+use axum::body::{boxed, BoxBody, Empty, StreamBody};
+use axum::extract::{Extension, Path};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Context};
+use std::io::SeekFrom;
+use std::sync::Arc;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio_util::io::ReaderStream;
+
+const HTTP_DATE_FORMAT: &'static str = "%a, %d %b %Y %H:%M:%S GMT";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Encoding {
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some("gzip"),
+            Encoding::Zstd => Some("zstd"),
+        }
+    }
+}
+
+/// Streams a completed job's GCode to the client.
+///
+/// Supports single byte range requests (for resuming downloads), conditional requests via
+/// ETag/Last-Modified and gzip or zstd content encoding of full responses.
+pub async fn get_job_gcode(
+    Path(job_id): Path<String>,
+    headers: HeaderMap,
+    key_id: Option<Extension<ClientKeyId>>,
+    scopes: Option<Extension<Scopes>>,
+    shared_state: Arc<SharedState>,
+) -> AppResult<Response<BoxBody>> {
+    // Copy what we need out of the job so that the job map is not locked while streaming
+    let (gcode_path, output_format, filename) = {
+        let job = shared_state
+            .jobs
+            .get(&job_id.into())
+            .ok_or_else(|| AppError::not_found(eyre!("Job not found")))?;
+
+        job.check_downloadable(key_id.as_deref(), scopes.as_deref())?;
+
+        match &job.status {
+            JobStatus::Completed(_) => {}
+            JobStatus::Errored((message, _)) => {
+                return Err(AppError::conflict(eyre!("Job failed: {}", message)));
+            }
+            JobStatus::Waiting | JobStatus::Started => {
+                return Err(AppError::conflict(eyre!("Job has not finished slicing")));
+            }
+        }
+
+        let filename = job
+            .output_path
+            .file_name()
+            .map(|filename| filename.to_string_lossy().into_owned())
+            .unwrap_or_else(|| format!("{}.{}", job.id.0, job.output_format.extension()));
+
+        (job.output_path.clone(), job.output_format, filename)
+    };
+
+    let metadata = fs::metadata(&gcode_path)
+        .await
+        .wrap_err("Error reading GCode file")?;
+
+    let len = metadata.len();
+    let last_modified: DateTime<Utc> = metadata.modified()?.into();
+    let http_last_modified = last_modified.format(HTTP_DATE_FORMAT).to_string();
+
+    let identity_etag = etag(len, &last_modified, None);
+
+    let range = match header_str(&headers, header::RANGE) {
+        Some(range) if if_range_matches(&headers, &identity_etag, &http_last_modified) => {
+            parse_range(range, len)
+        }
+        _ => None,
+    };
+
+    // Partial responses are never compressed so that byte offsets refer to the GCode file itself.
+    // Binary and already compressed formats gain little from compression.
+    let encoding = if range.is_some() || !output_format.is_text() {
+        Encoding::Identity
+    } else {
+        negotiate_encoding(&headers)
+    };
+
+    let etag = etag(len, &last_modified, encoding.name());
+
+    let mut res = Response::builder()
+        .header(header::CONTENT_TYPE, output_format.content_type())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::VARY, "Accept-Encoding")
+        .header(header::ETAG, &etag)
+        .header(header::LAST_MODIFIED, &http_last_modified)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", sanitize_filename(&filename)),
+        );
+
+    if is_not_modified(&headers, &etag, &last_modified) {
+        return Ok(res
+            .status(StatusCode::NOT_MODIFIED)
+            .body(boxed(Empty::new()))?);
+    }
+
+    let mut file = File::open(&gcode_path)
+        .await
+        .wrap_err("Error reading GCode file")?;
+
+    let res = match range {
+        Some(Ok((start, end))) => {
+            file.seek(SeekFrom::Start(start)).await?;
+            let body = ReaderStream::new(file.take(end - start + 1));
+
+            res.status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}"))
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .body(boxed(StreamBody::new(body)))?
+        }
+        Some(Err(())) => res
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+            .body(boxed(Empty::new()))?,
+        None => {
+            if let Some(encoding_name) = encoding.name() {
+                res = res.header(header::CONTENT_ENCODING, encoding_name);
+            }
+
+            let reader = BufReader::new(file);
+
+            let body = match encoding {
+                Encoding::Identity => {
+                    res = res.header(header::CONTENT_LENGTH, len);
+                    boxed(StreamBody::new(ReaderStream::new(reader)))
+                }
+                Encoding::Gzip => {
+                    boxed(StreamBody::new(ReaderStream::new(GzipEncoder::new(reader))))
+                }
+                Encoding::Zstd => {
+                    boxed(StreamBody::new(ReaderStream::new(ZstdEncoder::new(reader))))
+                }
+            };
+
+            res.status(StatusCode::OK).body(body)?
+        }
+    };
+
+    Ok(res)
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn etag(len: u64, last_modified: &DateTime<Utc>, encoding: Option<&str>) -> String {
+    let mut etag = format!(
+        "{:x}-{:x}.{:x}",
+        len,
+        last_modified.timestamp(),
+        last_modified.timestamp_subsec_nanos(),
+    );
+
+    if let Some(encoding) = encoding {
+        etag = format!("{etag}-{encoding}");
+    }
+
+    format!("\"{etag}\"")
+}
+
+fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: &DateTime<Utc>) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since
+    if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    header_str(headers, header::IF_MODIFIED_SINCE)
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+        .map(|since| last_modified.timestamp() <= since.timestamp())
+        .unwrap_or(false)
+}
+
+/// Returns false if the client's If-Range validator is stale, in which case the full file
+/// should be sent instead of the requested range.
+fn if_range_matches(headers: &HeaderMap, etag: &str, http_last_modified: &str) -> bool {
+    match header_str(headers, header::IF_RANGE) {
+        Some(if_range) => if_range == etag || if_range == http_last_modified,
+        None => true,
+    }
+}
+
+/// Parses a single `bytes=` range into inclusive start and end offsets.
+///
+/// Returns `None` for ranges that should be ignored (multiple ranges or unparsable headers) and
+/// `Some(Err(()))` for ranges that cannot be satisfied.
+fn parse_range(range: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // Suffix range, eg. the last 500 bytes: "bytes=-500"
+        let suffix_len: u64 = end.parse().ok()?;
+        if suffix_len == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        (len.saturating_sub(suffix_len), len - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end: u64 = if end.is_empty() {
+            len.saturating_sub(1)
+        } else {
+            end.parse::<u64>().ok()?.min(len.saturating_sub(1))
+        };
+
+        if start >= len || end < start {
+            return Some(Err(()));
+        }
+        (start, end)
+    };
+
+    Some(Ok(range))
+}
+
+/// Picks the content encoding with the highest quality (q-value) that is supported by both the
+/// client and the server, preferring zstd when they are equally preferred.
+fn negotiate_encoding(headers: &HeaderMap) -> Encoding {
+    let accept_encoding = match header_str(headers, header::ACCEPT_ENCODING) {
+        Some(accept_encoding) => accept_encoding,
+        None => return Encoding::Identity,
+    };
+
+    // Encodings that are not listed or that have an invalid q-value are not accepted
+    let quality = |name: &str| {
+        accept_encoding
+            .split(',')
+            .find_map(|entry| {
+                let mut parts = entry.split(';').map(str::trim);
+                if parts.next() != Some(name) {
+                    return None;
+                }
+
+                match parts.find_map(|param| param.strip_prefix("q=")) {
+                    Some(q) => q.parse::<f32>().ok(),
+                    None => Some(1.0),
+                }
+            })
+            .unwrap_or(0.0)
+    };
+
+    let (zstd, gzip) = (quality("zstd"), quality("gzip"));
+
+    if zstd > 0.0 && zstd >= gzip {
+        Encoding::Zstd
+    } else if gzip > 0.0 {
+        Encoding::Gzip
+    } else {
+        Encoding::Identity
+    }
+}
+
+fn sanitize_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | '/' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn byte_ranges_are_parsed() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=100-", 1000), Some(Ok((100, 999))));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Some(Ok((900, 999))));
+
+        // Suffix ranges
+        assert_eq!(parse_range("bytes=-500", 1000), Some(Ok((500, 999))));
+        assert_eq!(parse_range("bytes=-2000", 1000), Some(Ok((0, 999))));
+
+        // Multiple and unparsable ranges are ignored
+        assert_eq!(parse_range("bytes=0-99,200-299", 1000), None);
+        assert_eq!(parse_range("bytes=a-b", 1000), None);
+        assert_eq!(parse_range("items=0-99", 1000), None);
+    }
+
+    #[test]
+    fn unsatisfiable_byte_ranges_are_rejected() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=500-100", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=-500", 0), Some(Err(())));
+    }
+
+    #[test]
+    fn encodings_are_negotiated_by_quality() {
+        let negotiate = |value| negotiate_encoding(&headers(header::ACCEPT_ENCODING, value));
+
+        assert_eq!(negotiate_encoding(&HeaderMap::new()), Encoding::Identity);
+        assert_eq!(negotiate("gzip, zstd"), Encoding::Zstd);
+        assert_eq!(negotiate("gzip, deflate"), Encoding::Gzip);
+        assert_eq!(negotiate("gzip;q=1.0, zstd;q=0.5"), Encoding::Gzip);
+        assert_eq!(negotiate("zstd;q=0, gzip;q=0.1"), Encoding::Gzip);
+        assert_eq!(negotiate("zstd;q=0, gzip;q=0"), Encoding::Identity);
+        assert_eq!(negotiate("br, deflate"), Encoding::Identity);
+    }
+
+    #[test]
+    fn conditional_requests_match_the_etag_or_last_modified_time() {
+        let last_modified = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        let etag = "\"abc\"";
+
+        let not_modified = |headers| is_not_modified(&headers, etag, &last_modified);
+
+        assert!(!not_modified(HeaderMap::new()));
+        assert!(not_modified(headers(header::IF_NONE_MATCH, "\"abc\"")));
+        assert!(not_modified(headers(
+            header::IF_NONE_MATCH,
+            "\"x\", W/\"abc\""
+        )));
+        assert!(not_modified(headers(header::IF_NONE_MATCH, "*")));
+        assert!(!not_modified(headers(header::IF_NONE_MATCH, "\"x\"")));
+        assert!(not_modified(headers(
+            header::IF_MODIFIED_SINCE,
+            "Wed, 21 Oct 2015 07:28:00 GMT"
+        )));
+        assert!(!not_modified(headers(
+            header::IF_MODIFIED_SINCE,
+            "Tue, 20 Oct 2015 07:28:00 GMT"
+        )));
+
+        let http_last_modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let if_range =
+            |value| if_range_matches(&headers(header::IF_RANGE, value), etag, http_last_modified);
+
+        assert!(if_range_matches(
+            &HeaderMap::new(),
+            etag,
+            http_last_modified
+        ));
+        assert!(if_range("\"abc\""));
+        assert!(if_range(http_last_modified));
+        assert!(!if_range("\"stale\""));
+    }
+}
@@ -0,0 +1,97 @@
+use async_graphql::{Context, FieldResult, Object, Upload};
+use eyre::{eyre, Result};
+use std::path::Path;
+
+use super::create_job_mutation::move_upload_to_dir;
+use super::model_transform::replace_model;
+use crate::auth::{Scope, ScopeGuard};
+use crate::mesh::analysis::{self, MeshReport};
+use crate::mesh::{Mesh, MeshFormat};
+
+/// The analysis of an uploaded model
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct ModelReport {
+    /// The model's file name
+    pub name: String,
+    /// The model as it was uploaded, or None if the server was unable to read it
+    pub analysis: Option<MeshReport>,
+    /// The model after it was repaired, if repairs were requested and the model needed them
+    pub repaired: Option<MeshReport>,
+    /// Why the model could not be analyzed. The model is still sliced as the engine may be able
+    /// to read it.
+    pub error: Option<String>,
+}
+
+/// Analyzes the model and, if requested, repairs it in place. Models in formats that the server
+/// does not support are not analyzed. Models that cannot be read are reported without an
+/// analysis, unless they needed to be repaired in which case an error is returned.
+pub async fn analyze_model(name: &str, path: &Path, repair: bool) -> Result<Option<ModelReport>> {
+    if MeshFormat::from_path(path).is_err() {
+        return Ok(None);
+    }
+
+    let name = name.to_owned();
+    let path = path.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        let mesh = match Mesh::read(&path) {
+            Ok(mesh) => mesh,
+            Err(err) if repair => return Err(eyre!("Unable to read {name}: {err}")),
+            Err(err) => {
+                return Ok(Some(ModelReport {
+                    error: Some(format!("Unable to read {name}: {err}")),
+                    name,
+                    analysis: None,
+                    repaired: None,
+                }))
+            }
+        };
+        let report = analysis::analyze(&mesh);
+
+        let repaired = if repair && report.has_problems() {
+            let mesh = analysis::repair(&mesh);
+            replace_model(&path, &mesh)?;
+
+            Some(analysis::analyze(&mesh))
+        } else {
+            None
+        };
+
+        Ok(Some(ModelReport {
+            name,
+            analysis: Some(report),
+            repaired,
+            error: None,
+        }))
+    })
+    .await?
+}
+
+#[derive(Default)]
+pub struct ModelQuery;
+
+#[Object]
+impl ModelQuery {
+    /// Checks a model for problems without slicing it
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs])")]
+    async fn analyze_model<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        upload: Upload,
+    ) -> FieldResult<MeshReport> {
+        let upload = upload.value(ctx)?;
+
+        let dir = tempfile::tempdir()?;
+        let path = move_upload_to_dir(&upload, dir.path())?;
+
+        let report = analyze_model(&upload.filename, &path, false)
+            .await?
+            .ok_or_else(|| eyre!("Unsupported model format: {}", upload.filename))?;
+
+        let analysis = report
+            .analysis
+            .ok_or_else(|| eyre!(report.error.unwrap_or_default()))?;
+
+        Ok(analysis)
+    }
+}
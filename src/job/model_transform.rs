@@ -0,0 +1,136 @@
+use cgmath::{Deg, Matrix4, SquareMatrix, Vector3};
+use eyre::{eyre, Result};
+use std::path::Path;
+
+use crate::engine::{Engine, InvertRotation};
+use crate::mesh::Mesh;
+
+#[derive(async_graphql::InputObject, Clone, Copy)]
+pub struct Vec3Input {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl From<Vec3Input> for Vector3<f32> {
+    fn from(vec3: Vec3Input) -> Self {
+        Vector3::new(vec3.x as f32, vec3.y as f32, vec3.z as f32)
+    }
+}
+
+/// The placement of a model on the bed. Either a 4x4 matrix or any combination of position,
+/// rotation and scale may be provided.
+#[derive(async_graphql::InputObject, Clone, Default)]
+pub struct TransformInput {
+    /// The position of the model in millimeters
+    position: Option<Vec3Input>,
+    /// The rotation of the model in degrees, applied in intrinsic XYZ order. Axes flagged in the
+    /// engine's `invertRotation` are reversed in the same way as they are for display.
+    rotation: Option<Vec3Input>,
+    scale: Option<Vec3Input>,
+    /// A column-major 4x4 matrix, in the same layout as the engine's `transformMat4`
+    mat4: Option<Vec<Vec<f64>>>,
+}
+
+impl TransformInput {
+    /// The model's transform, excluding the engine's transform
+    pub fn to_matrix(&self, invert_rotation: &InvertRotation) -> Result<Matrix4<f32>> {
+        if let Some(mat4) = &self.mat4 {
+            if self.position.is_some() || self.rotation.is_some() || self.scale.is_some() {
+                return Err(eyre!(
+                    "mat4 cannot be combined with position, rotation or scale"
+                ));
+            }
+
+            return mat4_from_columns(mat4);
+        }
+
+        let translation = self
+            .position
+            .map(|position| Matrix4::from_translation(position.into()))
+            .unwrap_or_else(Matrix4::identity);
+
+        let rotation = self
+            .rotation
+            .map(|rotation| {
+                let direction = |inverted: bool| if inverted { -1.0 } else { 1.0 };
+
+                Matrix4::from_angle_x(Deg(rotation.x as f32 * direction(invert_rotation.x)))
+                    * Matrix4::from_angle_y(Deg(rotation.y as f32 * direction(invert_rotation.y)))
+                    * Matrix4::from_angle_z(Deg(rotation.z as f32 * direction(invert_rotation.z)))
+            })
+            .unwrap_or_else(Matrix4::identity);
+
+        let scale = self
+            .scale
+            .map(|scale| {
+                Matrix4::from_nonuniform_scale(scale.x as f32, scale.y as f32, scale.z as f32)
+            })
+            .unwrap_or_else(Matrix4::identity);
+
+        Ok(translation * rotation * scale)
+    }
+
+    /// True if the transform moves the model on the bed
+    pub fn is_positioned(&self) -> bool {
+        let mat4_translation = self
+            .mat4
+            .as_ref()
+            .and_then(|mat4| mat4.get(3))
+            .map_or(false, |column| {
+                column.iter().take(3).any(|value| *value != 0.0)
+            });
+
+        self.position.is_some() || mat4_translation
+    }
+
+    /// The transform to apply to the uploaded mesh. The engine's transform is applied first,
+    /// followed by the model's transform.
+    pub fn to_engine_matrix(&self, engine: &Engine) -> Result<Matrix4<f32>> {
+        Ok(self.to_matrix(&engine.invert_rotation)? * engine.transform_mat4)
+    }
+}
+
+fn mat4_from_columns(mat4: &[Vec<f64>]) -> Result<Matrix4<f32>> {
+    if mat4.len() != 4 || mat4.iter().any(|column| column.len() != 4) {
+        return Err(eyre!("mat4 must be a 4x4 matrix"));
+    }
+
+    let mut columns = [[0f32; 4]; 4];
+    for (column, values) in columns.iter_mut().zip(mat4) {
+        for (value, input) in column.iter_mut().zip(values) {
+            *value = *input as f32;
+        }
+    }
+
+    Ok(Matrix4::from(columns))
+}
+
+/// Transforms the model file in place. The file keeps it's original format.
+pub async fn transform_model(path: &Path, mat4: Matrix4<f32>) -> Result<()> {
+    let path = path.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        let mut mesh = Mesh::read(&path)?;
+        mesh.transform(&mat4);
+
+        replace_model(&path, &mesh)
+    })
+    .await?
+}
+
+/// Overwrites the model file with the mesh, keeping the file's format
+pub fn replace_model(path: &Path, mesh: &Mesh) -> Result<()> {
+    // Write to a new file and then replace the original so that a partially written model is
+    // never sliced
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| eyre!("Invalid model path"))?
+        .to_string_lossy();
+    let tmp_path = path.with_file_name(format!("replacement-{file_name}"));
+
+    mesh.write(&tmp_path)?;
+    std::fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
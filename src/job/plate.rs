@@ -0,0 +1,372 @@
+use cgmath::{Deg, Matrix4, Vector3};
+use eyre::{eyre, Result};
+use std::path::{Path, PathBuf};
+
+use super::model_transform::transform_model;
+use crate::engine::{PrintVolume, SlicingEngine};
+use crate::mesh::arrange::{arrange, BedShape};
+use crate::mesh::three_mf::{self, SceneObject, SceneVolume};
+use crate::mesh::{Mesh, MeshFormat};
+
+/// The gap left between copies of an object, in millimeters
+const COPY_SPACING: f32 = 10.0;
+
+const PLATE_FILENAME: &'static str = "plate.3mf";
+
+/// An object on a job's plate
+pub struct PlateObject {
+    pub name: String,
+    pub src_path: PathBuf,
+    /// The transform to apply to the uploaded mesh (including the engine's transform), if the
+    /// object is positioned by the server
+    pub transform: Option<Matrix4<f32>>,
+    pub copies: usize,
+    pub setting_overrides: Vec<(String, String)>,
+    /// The extruder that prints the object, starting at 1
+    pub extruder: Option<u32>,
+    /// The extruders of individual volumes of the object, as (volume index, extruder) pairs
+    pub volume_extruders: Vec<(usize, u32)>,
+}
+
+impl PlateObject {
+    fn has_extruder_assignments(&self) -> bool {
+        self.extruder.is_some() || !self.volume_extruders.is_empty()
+    }
+}
+
+/// Checks that each object fits the printer once it's transformed. Objects uploaded without a
+/// transform are checked as they were uploaded.
+pub async fn check_print_volume(volume: PrintVolume, objects: &[PlateObject]) -> Result<()> {
+    let objects = objects
+        .iter()
+        .map(|object| {
+            (
+                object.name.clone(),
+                object.src_path.clone(),
+                object.transform,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    tokio::task::spawn_blocking(move || {
+        for (name, src_path, transform) in objects {
+            let mut mesh = Mesh::read(&src_path)?;
+            if let Some(mat4) = &transform {
+                mesh.transform(mat4);
+            }
+
+            let (min, max) = match mesh.bounds() {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+            let size = max - min;
+
+            let overflows = [
+                ("X", size.x, volume.x),
+                ("Y", size.y, volume.y),
+                ("Z", size.z, volume.z),
+            ]
+            .into_iter()
+            .filter_map(|(axis, size, limit)| {
+                let overflow = size - limit?;
+                (overflow > 0.0).then(|| format!("{axis} by {overflow:.2}mm"))
+            })
+            .collect::<Vec<_>>();
+
+            if !overflows.is_empty() {
+                return Err(eyre!(
+                    "{name} is too large for the printer: {}",
+                    overflows.join(", "),
+                ));
+            }
+        }
+
+        Ok(())
+    })
+    .await?
+}
+
+/// Where the server placed a copy of an object when arranging the plate
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct ArrangedObject {
+    /// The object's index in the job's objects
+    pub object: u32,
+    /// Which copy of the object this is, starting at 0
+    pub copy: u32,
+    /// The X coordinate of the center of the object's footprint on the bed, in millimeters
+    pub x: f32,
+    /// The Y coordinate of the center of the object's footprint on the bed, in millimeters
+    pub y: f32,
+    /// The rotation about the Z axis added by the server, in degrees
+    pub rotation: f32,
+}
+
+/// Packs the plate's objects onto the bed. Each copy of an object is split out into it's own
+/// object so that it can be placed separately.
+///
+/// Every object must have a transform, which is replaced by the arranged transform.
+pub async fn arrange_plate(
+    bed: BedShape,
+    objects: Vec<PlateObject>,
+    spacing: f32,
+    allow_rotation: bool,
+) -> Result<(Vec<PlateObject>, Vec<ArrangedObject>)> {
+    let footprints = tokio::task::spawn_blocking({
+        let objects = objects
+            .iter()
+            .map(|object| (object.src_path.clone(), object.transform))
+            .collect::<Vec<_>>();
+
+        move || {
+            objects
+                .into_iter()
+                .map(|(src_path, transform)| {
+                    let mut mesh = Mesh::read(&src_path)?;
+                    if let Some(mat4) = &transform {
+                        mesh.transform(mat4);
+                    }
+
+                    mesh.bounds()
+                        .ok_or_else(|| eyre!("{:?} does not contain any vertices", src_path))
+                })
+                .collect::<Result<Vec<_>>>()
+        }
+    })
+    .await??;
+
+    let copies = objects
+        .iter()
+        .zip(&footprints)
+        .flat_map(|(object, (min, max))| {
+            std::iter::repeat((max.x - min.x, max.y - min.y)).take(object.copies)
+        })
+        .collect::<Vec<_>>();
+
+    let mut placements = arrange(&bed, &copies, spacing, allow_rotation)?.into_iter();
+
+    let mut arranged_objects = vec![];
+    let mut arrangement = vec![];
+
+    for (i, (object, (min, max))) in objects.into_iter().zip(footprints).enumerate() {
+        let transform = object
+            .transform
+            .ok_or_else(|| eyre!("Objects must be transformed to be arranged"))?;
+
+        let center = Vector3::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0, 0.0);
+
+        for copy in 0..object.copies {
+            let placement = placements
+                .next()
+                .ok_or_else(|| eyre!("Missing placement for {}", object.name))?;
+
+            let rotation = if placement.rotated { 90.0 } else { 0.0 };
+
+            let placement_mat4 = Matrix4::from_translation(Vector3::new(
+                placement.center.x,
+                placement.center.y,
+                0.0,
+            )) * Matrix4::from_angle_z(Deg(rotation))
+                * Matrix4::from_translation(-center);
+
+            // Copies get their own model file so that each one can be transformed in place
+            let src_path = if copy == 0 {
+                object.src_path.clone()
+            } else {
+                let file_name = object
+                    .src_path
+                    .file_name()
+                    .ok_or_else(|| eyre!("Invalid model path"))?
+                    .to_string_lossy();
+                let copy_path = object
+                    .src_path
+                    .with_file_name(format!("copy-{copy}-{file_name}"));
+
+                tokio::fs::copy(&object.src_path, &copy_path).await?;
+                copy_path
+            };
+
+            arranged_objects.push(PlateObject {
+                name: copy_name(&object.name, copy, object.copies),
+                src_path,
+                transform: Some(placement_mat4 * transform),
+                copies: 1,
+                setting_overrides: object.setting_overrides.clone(),
+                extruder: object.extruder,
+                volume_extruders: object.volume_extruders.clone(),
+            });
+            arrangement.push(ArrangedObject {
+                object: i as u32,
+                copy: copy as u32,
+                x: placement.center.x,
+                y: placement.center.y,
+                rotation,
+            });
+        }
+    }
+
+    Ok((arranged_objects, arrangement))
+}
+
+/// Prepares the plate's objects for slicing, returning the model files to pass to the engine.
+///
+/// Engines that accept 3MF files receive the plate as a single 3MF scene. Other engines receive
+/// each object as a separate model file if they support multiple models.
+pub async fn assemble_plate(
+    engine: &dyn SlicingEngine,
+    dir: &Path,
+    mut objects: Vec<PlateObject>,
+) -> Result<Vec<PathBuf>> {
+    let metadata = engine.metadata().clone();
+
+    // Transformed 3MFs are rewritten as plates so that their volumes are kept
+    let is_single_object = objects.len() == 1
+        && objects[0].copies == 1
+        && objects[0].setting_overrides.is_empty()
+        && !objects[0].has_extruder_assignments()
+        && !(objects[0].transform.is_some()
+            && MeshFormat::from_path(&objects[0].src_path).ok() == Some(MeshFormat::ThreeMf));
+
+    if is_single_object {
+        let object = objects.remove(0);
+
+        if let Some(mat4) = object.transform {
+            transform_model(&object.src_path, mat4).await?;
+        }
+
+        return Ok(vec![object.src_path]);
+    }
+
+    if metadata
+        .accepted_file_formats
+        .iter()
+        .any(|format| format == ".3mf")
+    {
+        let plate_path = dir.join(PLATE_FILENAME);
+
+        tokio::task::spawn_blocking({
+            let plate_path = plate_path.clone();
+            move || write_plate(&plate_path, &objects, metadata.allows_positioning)
+        })
+        .await??;
+
+        return Ok(vec![plate_path]);
+    }
+
+    if !engine.accepts_multiple_models() {
+        return Err(eyre!(
+            "{} does not support multi-object plates",
+            metadata.name
+        ));
+    }
+
+    if objects
+        .iter()
+        .any(|object| !object.setting_overrides.is_empty())
+    {
+        return Err(eyre!(
+            "{} does not support per-object settings",
+            metadata.name
+        ));
+    }
+
+    if objects.iter().any(PlateObject::has_extruder_assignments) {
+        return Err(eyre!(
+            "{} does not support extruder assignments",
+            metadata.name
+        ));
+    }
+
+    let mut src_paths = vec![];
+
+    for object in objects {
+        if let Some(mat4) = object.transform {
+            transform_model(&object.src_path, mat4).await?;
+        }
+
+        // Engines that accept multiple models lay out each copy themselves
+        src_paths.extend(std::iter::repeat(object.src_path).take(object.copies));
+    }
+
+    Ok(src_paths)
+}
+
+/// Writes the plate as a 3MF scene. Copies of positioned objects are placed in a row along the X
+/// axis.
+fn write_plate(path: &Path, objects: &[PlateObject], allows_positioning: bool) -> Result<()> {
+    let mut copies = vec![];
+
+    for object in objects {
+        let mut volumes = Mesh::read_volumes(&object.src_path)?;
+        if let Some(mat4) = &object.transform {
+            for volume in volumes.iter_mut() {
+                volume.transform(mat4);
+            }
+        }
+
+        let mut extruders = vec![None; volumes.len()];
+        for (volume, extruder) in &object.volume_extruders {
+            *extruders.get_mut(*volume).ok_or_else(|| {
+                eyre!(
+                    "{} has {} volumes so volume {} cannot be assigned an extruder",
+                    object.name,
+                    volumes.len(),
+                    volume,
+                )
+            })? = Some(*extruder);
+        }
+
+        // PrusaSlicer reads the object's extruder from it's metadata alongside it's settings
+        let mut settings = object.setting_overrides.clone();
+        if let Some(extruder) = object.extruder {
+            settings.push(("extruder".to_owned(), extruder.to_string()));
+        }
+
+        let width = Mesh::merge(volumes.clone())
+            .bounds()
+            .map(|(min, max)| max.x - min.x)
+            .unwrap_or_default();
+
+        for copy in 0..object.copies {
+            let mut volumes = volumes.clone();
+
+            if allows_positioning && copy > 0 {
+                let offset = copy as f32 * (width + COPY_SPACING);
+                let mat4 = Matrix4::from_translation(Vector3::new(offset, 0.0, 0.0));
+
+                for volume in volumes.iter_mut() {
+                    volume.transform(&mat4);
+                }
+            }
+
+            let name = copy_name(&object.name, copy, object.copies);
+
+            copies.push((name, volumes, extruders.clone(), settings.clone()));
+        }
+    }
+
+    let scene = copies
+        .iter()
+        .map(|(name, volumes, extruders, settings)| SceneObject {
+            name,
+            volumes: volumes
+                .iter()
+                .zip(extruders)
+                .map(|(mesh, extruder)| SceneVolume {
+                    mesh,
+                    extruder: *extruder,
+                })
+                .collect(),
+            settings,
+        })
+        .collect::<Vec<_>>();
+
+    three_mf::write_scene(path, &scene)
+}
+
+fn copy_name(name: &str, copy: usize, copies: usize) -> String {
+    if copies > 1 {
+        format!("{} ({})", name, copy + 1)
+    } else {
+        name.to_owned()
+    }
+}
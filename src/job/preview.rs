@@ -0,0 +1,176 @@
+use axum::extract::{Extension, Path};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use eyre::{eyre, Context, Result};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::warn;
+
+use super::JobStatus;
+use crate::auth::{ClientKeyId, Scopes};
+use crate::error::{AppError, AppResult};
+use crate::gcode::{layers, thumbnails};
+use crate::mesh::render::render_png;
+use crate::mesh::Mesh;
+use crate::server::SharedState;
+
+const THUMBNAIL_FILENAME: &'static str = "thumbnail.png";
+const LAYERS_FILENAME: &'static str = "layers.json";
+
+/// Preview files generated for a completed job
+#[derive(Clone, Debug)]
+pub struct Preview {
+    /// A PNG of the sliced models, if the server can read their format
+    pub thumbnail_path: Option<PathBuf>,
+    /// A JSON summary of the extrusion toolpaths of each layer, if the engine generated plain
+    /// GCode
+    pub layers_path: Option<PathBuf>,
+}
+
+/// Renders thumbnails of the models, embedding them in the GCode, and summarizes the GCode's
+/// layers. Rendering is done on the CPU so that no GPU is required.
+///
+/// The GCode path is None if the engine wrote the job's output format itself, in which case only
+/// the thumbnail is generated.
+pub async fn generate_preview(
+    src_paths: Vec<PathBuf>,
+    gcode_path: Option<PathBuf>,
+    preview_dir: PathBuf,
+) -> Result<Preview> {
+    tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&preview_dir)?;
+
+        let thumbnail_path =
+            match render_thumbnails(&src_paths, gcode_path.as_deref(), &preview_dir) {
+                Ok(thumbnail_path) => thumbnail_path,
+                Err(err) => {
+                    warn!("Unable to render thumbnails: {:?}", err);
+                    None
+                }
+            };
+
+        let layers_path = match gcode_path {
+            Some(gcode_path) => {
+                let gcode = BufReader::new(std::fs::File::open(&gcode_path)?);
+                let summary = layers::summarize(gcode)?;
+
+                let layers_path = preview_dir.join(LAYERS_FILENAME);
+                std::fs::write(&layers_path, serde_json::to_vec(&summary)?)?;
+
+                Some(layers_path)
+            }
+            None => None,
+        };
+
+        Ok(Preview {
+            thumbnail_path,
+            layers_path,
+        })
+    })
+    .await?
+}
+
+/// Renders each thumbnail size, saving the largest to the preview directory
+fn render_thumbnails(
+    src_paths: &[PathBuf],
+    gcode_path: Option<&std::path::Path>,
+    preview_dir: &std::path::Path,
+) -> Result<Option<PathBuf>> {
+    let meshes = src_paths
+        .iter()
+        .map(|src_path| Mesh::read(src_path))
+        .collect::<Result<Vec<_>>>()?;
+    let mesh = Mesh::merge(meshes);
+
+    let mut blocks = vec![];
+    let mut largest = None;
+
+    for (width, height) in thumbnails::THUMBNAIL_SIZES {
+        let png = match render_png(&mesh, width, height)? {
+            Some(png) => png,
+            None => return Ok(None),
+        };
+
+        blocks.push(thumbnails::thumbnail_block(width, height, &png));
+        largest = Some(png);
+    }
+
+    if let Some(gcode_path) = gcode_path {
+        thumbnails::embed(gcode_path, &blocks)?;
+    }
+
+    match largest {
+        Some(png) => {
+            let thumbnail_path = preview_dir.join(THUMBNAIL_FILENAME);
+            std::fs::write(&thumbnail_path, png)?;
+
+            Ok(Some(thumbnail_path))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Finds a completed job's preview file
+fn preview_file(
+    shared_state: &SharedState,
+    job_id: String,
+    key_id: Option<Extension<ClientKeyId>>,
+    scopes: Option<Extension<Scopes>>,
+    get_path: impl Fn(&Preview) -> Option<PathBuf>,
+) -> AppResult<PathBuf> {
+    let job = shared_state
+        .jobs
+        .get(&job_id.into())
+        .ok_or_else(|| AppError::not_found(eyre!("Job not found")))?;
+
+    job.check_downloadable(key_id.as_deref(), scopes.as_deref())?;
+
+    if !matches!(job.status, JobStatus::Completed(_)) {
+        return Err(AppError::conflict(eyre!("Job has not finished slicing")));
+    }
+
+    job.preview
+        .as_ref()
+        .and_then(get_path)
+        .ok_or_else(|| AppError::not_found(eyre!("No preview available for this job")))
+}
+
+/// Serves a completed job's thumbnail as a PNG
+pub async fn get_job_thumbnail(
+    Path(job_id): Path<String>,
+    key_id: Option<Extension<ClientKeyId>>,
+    scopes: Option<Extension<Scopes>>,
+    shared_state: Arc<SharedState>,
+) -> AppResult<Response> {
+    let path = preview_file(&shared_state, job_id, key_id, scopes, |preview| {
+        preview.thumbnail_path.clone()
+    })?;
+    let png = tokio::fs::read(path)
+        .await
+        .wrap_err("Error reading thumbnail")?;
+
+    Ok((StatusCode::OK, [(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// Serves a completed job's per-layer toolpath summary as JSON
+pub async fn get_job_layers(
+    Path(job_id): Path<String>,
+    key_id: Option<Extension<ClientKeyId>>,
+    scopes: Option<Extension<Scopes>>,
+    shared_state: Arc<SharedState>,
+) -> AppResult<Response> {
+    let path = preview_file(&shared_state, job_id, key_id, scopes, |preview| {
+        preview.layers_path.clone()
+    })?;
+    let json = tokio::fs::read(path)
+        .await
+        .wrap_err("Error reading layer summary")?;
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        json,
+    )
+        .into_response())
+}
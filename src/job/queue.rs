@@ -0,0 +1,540 @@
+use async_graphql::ID;
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Result};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+
+/// Waiting jobs gain one priority level for each interval they wait so that a steady stream of
+/// higher priority jobs cannot starve them
+const AGING_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// The number of recently completed jobs that start times are estimated from
+const RECENT_JOBS: usize = 10;
+
+/// The jobs waiting to be sliced, ordered by priority.
+///
+/// Jobs are placed ahead of every waiting job with a lower priority and behind the rest. A
+/// waiting job's priority is raised by it's time in the queue (see `AGING_INTERVAL`) when jobs are
+/// placed, so new jobs only overtake it if their priority is higher than that.
+///
+/// Client keys take turns: the next job is the first waiting job of the client key with the
+/// highest priority job, and keys with jobs of the same priority are served round-robin.
+///
+/// Jobs can also be moved to a position in the queue directly. The moved job and the jobs ahead
+/// of it are pinned in that order at the front of the queue, ahead of priorities and client keys
+/// taking turns, until they start or their priority is changed.
+#[derive(Clone)]
+pub struct JobQueue {
+    state: Arc<Mutex<QueueState>>,
+    notify: Arc<Notify>,
+    /// The number of jobs that are sliced at the same time
+    concurrency: usize,
+}
+
+#[derive(Default)]
+struct QueueState {
+    waiting: Vec<QueuedJob>,
+    running: Vec<RunningJob>,
+    /// The number of jobs that have been started
+    starts: u64,
+    /// The value of `starts` when each client key's most recent job was started
+    last_started: HashMap<Option<String>, u64>,
+    /// How long each recently completed job took, most recent last
+    recent_durations: VecDeque<Duration>,
+}
+
+struct QueuedJob {
+    id: ID,
+    /// The client key that created the job, if any
+    key_id: Option<String>,
+    priority: i32,
+    queued_at: Instant,
+    /// True if the job was moved to it's position by hand. Pinned jobs are kept at the front of
+    /// the queue in the order they were placed.
+    pinned: bool,
+}
+
+struct RunningJob {
+    id: ID,
+    key_id: Option<String>,
+    started_at: Instant,
+    /// Cancelled to stop slicing the job
+    cancellation: CancellationToken,
+}
+
+impl QueuedJob {
+    fn effective_priority(&self, now: Instant) -> i64 {
+        let waited = now.saturating_duration_since(self.queued_at);
+        let levels = waited.as_secs() / AGING_INTERVAL.as_secs();
+
+        self.priority as i64 + levels as i64
+    }
+}
+
+impl QueueState {
+    fn insert(&mut self, job: QueuedJob) {
+        let now = Instant::now();
+        let priority = job.effective_priority(now);
+
+        let index = self
+            .waiting
+            .iter()
+            .position(|other| !other.pinned && other.effective_priority(now) < priority)
+            .unwrap_or(self.waiting.len());
+
+        self.waiting.insert(index, job);
+    }
+
+    fn remove(&mut self, job_id: &ID) -> Result<QueuedJob> {
+        let index = self
+            .waiting
+            .iter()
+            .position(|job| &job.id == job_id)
+            .ok_or_else(|| eyre!("Only waiting jobs can be moved in the queue"))?;
+
+        Ok(self.waiting.remove(index))
+    }
+
+    /// Picks the index of the next waiting job to start, ignoring skipped jobs. Pinned jobs are
+    /// picked first, in order. Otherwise only the first job of each client key is considered. The
+    /// highest priority of those is picked, preferring the key that least recently had a job
+    /// started and then the job nearest the front.
+    fn next_job(
+        &self,
+        now: Instant,
+        last_started: &HashMap<Option<String>, u64>,
+        skip: impl Fn(usize, &QueuedJob) -> bool,
+    ) -> Option<usize> {
+        let candidates = self
+            .waiting
+            .iter()
+            .enumerate()
+            .filter(|&(i, job)| !skip(i, job));
+
+        if let Some((i, _)) = candidates.clone().find(|(_, job)| job.pinned) {
+            return Some(i);
+        }
+
+        let mut seen_keys = HashSet::new();
+
+        candidates
+            .filter(|&(_, job)| seen_keys.insert(&job.key_id))
+            .max_by_key(|(i, job)| {
+                (
+                    job.effective_priority(now),
+                    Reverse(last_started.get(&job.key_id).copied()),
+                    Reverse(*i),
+                )
+            })
+            .map(|(i, _)| i)
+    }
+
+    /// The indices of the waiting jobs in the order that they are expected to start. Client key
+    /// limits are not taken into account.
+    fn schedule(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let mut last_started = self.last_started.clone();
+        let mut starts = self.starts;
+        let mut schedule: Vec<usize> = vec![];
+
+        while let Some(i) = self.next_job(now, &last_started, |i, _| schedule.contains(&i)) {
+            starts += 1;
+            last_started.insert(self.waiting[i].key_id.clone(), starts);
+            schedule.push(i);
+        }
+
+        schedule
+    }
+
+    fn average_duration(&self) -> Option<Duration> {
+        if self.recent_durations.is_empty() {
+            return None;
+        }
+
+        let total: Duration = self.recent_durations.iter().sum();
+        Some(total / self.recent_durations.len() as u32)
+    }
+}
+
+impl JobQueue {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            state: Default::default(),
+            notify: Default::default(),
+            concurrency: concurrency.max(1),
+        }
+    }
+
+    /// The number of jobs that are sliced at the same time
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().expect("Job queue lock poisoned")
+    }
+
+    /// Adds a job to the queue
+    pub fn push(&self, job_id: ID, key_id: Option<String>, priority: i32) {
+        self.state().insert(QueuedJob {
+            id: job_id,
+            key_id,
+            priority,
+            queued_at: Instant::now(),
+            pinned: false,
+        });
+
+        self.notify.notify_one();
+    }
+
+    /// Waits for the next job whose client key is below it's `max_concurrent_jobs` limit,
+    /// marking it as running until `finish` is called
+    pub async fn pop(&self, config: &Config) -> ID {
+        loop {
+            {
+                let mut state = self.state();
+
+                let is_at_limit = |job: &QueuedJob| {
+                    let limit = job
+                        .key_id
+                        .as_ref()
+                        .and_then(|key_id| config.authorized_keys.get(key_id))
+                        .and_then(|key| key.limits.max_concurrent_jobs);
+
+                    limit.map_or(false, |limit| {
+                        let running = state
+                            .running
+                            .iter()
+                            .filter(|running| running.key_id == job.key_id)
+                            .count();
+
+                        running >= limit
+                    })
+                };
+
+                let next = state.next_job(Instant::now(), &state.last_started, |_, job| {
+                    is_at_limit(job)
+                });
+
+                if let Some(i) = next {
+                    let job = state.waiting.remove(i);
+
+                    state.starts += 1;
+                    let starts = state.starts;
+                    state.last_started.insert(job.key_id.clone(), starts);
+
+                    state.running.push(RunningJob {
+                        id: job.id.clone(),
+                        key_id: job.key_id,
+                        started_at: Instant::now(),
+                        cancellation: CancellationToken::new(),
+                    });
+
+                    return job.id;
+                }
+            }
+
+            self.notify.notified().await;
+        }
+    }
+
+    /// Records that a running job has finished. Only successful jobs are used to estimate start
+    /// times as failed jobs often end early.
+    pub fn finish(&self, job_id: &ID, succeeded: bool) {
+        let mut state = self.state();
+
+        if let Some(index) = state.running.iter().position(|job| &job.id == job_id) {
+            let job = state.running.remove(index);
+
+            if succeeded {
+                if state.recent_durations.len() == RECENT_JOBS {
+                    state.recent_durations.pop_front();
+                }
+                state.recent_durations.push_back(job.started_at.elapsed());
+            }
+        }
+
+        // The job's client key may have been waiting for it to finish
+        self.notify.notify_one();
+    }
+
+    /// Removes a waiting job from the queue or stops a running job. Running jobs are stopped by
+    /// the task slicing them (see `cancelled`), which also calls `finish`.
+    pub fn cancel(&self, job_id: &ID) -> Result<()> {
+        let mut state = self.state();
+
+        if let Some(job) = state.running.iter().find(|job| &job.id == job_id) {
+            job.cancellation.cancel();
+            return Ok(());
+        }
+
+        state
+            .remove(job_id)
+            .map_err(|_| eyre!("Only waiting or running jobs can be cancelled"))?;
+
+        Ok(())
+    }
+
+    /// Resolves once a running job is cancelled. Never resolves for jobs that are not running.
+    pub async fn cancelled(&self, job_id: &ID) {
+        let cancellation = self
+            .state()
+            .running
+            .iter()
+            .find(|job| &job.id == job_id)
+            .map(|job| job.cancellation.clone());
+
+        match cancellation {
+            Some(cancellation) => cancellation.cancelled().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Changes a waiting job's priority, moving it ahead of or behind the other waiting jobs. Jobs
+    /// that were moved by hand are unpinned.
+    pub fn set_priority(&self, job_id: &ID, priority: i32) -> Result<()> {
+        let mut state = self.state();
+
+        let mut job = state.remove(job_id)?;
+        job.priority = priority;
+        job.pinned = false;
+        state.insert(job);
+
+        Ok(())
+    }
+
+    /// Moves a waiting job to a position in the order that waiting jobs are expected to start
+    /// (see `position`), where 0 is the next job to start. Positions past the end of the queue
+    /// move the job to the end. The job and every job ahead of it are pinned in their new order.
+    pub fn move_to(&self, job_id: &ID, position: usize) -> Result<()> {
+        let mut state = self.state();
+
+        let mut schedule = state
+            .schedule()
+            .into_iter()
+            .map(|i| state.waiting[i].id.clone())
+            .collect::<Vec<_>>();
+
+        let index = schedule
+            .iter()
+            .position(|id| id == job_id)
+            .ok_or_else(|| eyre!("Only waiting jobs can be moved in the queue"))?;
+        let id = schedule.remove(index);
+
+        let position = position.min(schedule.len());
+        schedule.insert(position, id);
+
+        // Jobs that were already pinned behind the new position stay pinned behind these ones
+        let mut pinned = schedule[..=position]
+            .iter()
+            .map(|id| state.remove(id))
+            .collect::<Result<Vec<_>>>()?;
+        for job in &mut pinned {
+            job.pinned = true;
+        }
+
+        pinned.append(&mut state.waiting);
+        state.waiting = pinned;
+
+        Ok(())
+    }
+
+    /// The number of waiting jobs expected to start before this one, if it is waiting
+    pub fn position(&self, job_id: &ID) -> Option<usize> {
+        let state = self.state();
+
+        state
+            .schedule()
+            .into_iter()
+            .position(|i| &state.waiting[i].id == job_id)
+    }
+
+    /// Estimates when a waiting job will start from the average duration of recent jobs. Returns
+    /// None if the job is not waiting or no jobs have completed yet.
+    pub fn estimated_start_time(&self, job_id: &ID) -> Option<DateTime<Utc>> {
+        let state = self.state();
+
+        let position = state
+            .schedule()
+            .into_iter()
+            .position(|i| &state.waiting[i].id == job_id)?;
+        let average_duration = state.average_duration()?;
+
+        // How long until each slot is free to start another job
+        let mut free_in = state
+            .running
+            .iter()
+            .map(|job| average_duration.saturating_sub(job.started_at.elapsed()))
+            .collect::<Vec<_>>();
+        free_in.resize(self.concurrency.max(free_in.len()), Duration::ZERO);
+
+        // Each job ahead of this one takes the slot that is free soonest
+        for _ in 0..position {
+            if let Some(slot) = free_in.iter_mut().min() {
+                *slot += average_duration;
+            }
+        }
+
+        let wait = free_in.into_iter().min().unwrap_or_default();
+
+        Some(Utc::now() + chrono::Duration::from_std(wait).ok()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::config::{ClientKey, ClientLimits};
+
+    #[tokio::test]
+    async fn reordered_jobs_start_ahead_of_other_client_keys() -> Result<()> {
+        let job_queue = JobQueue::new(1);
+        let config = Config::default();
+        let id = |id: &str| ID::from(id);
+        let key = |key: &str| Some(key.to_owned());
+
+        job_queue.push(id("a1"), key("a"), 0);
+        job_queue.push(id("a2"), key("a"), 0);
+        job_queue.push(id("a3"), key("a"), 0);
+        job_queue.push(id("b1"), key("b"), 5);
+
+        assert_eq!(job_queue.position(&id("b1")), Some(0));
+        assert_eq!(job_queue.position(&id("a3")), Some(3));
+
+        // Round-robin scheduling would otherwise start a1 before a3
+        job_queue.move_to(&id("a3"), 1)?;
+        assert_eq!(job_queue.position(&id("b1")), Some(0));
+        assert_eq!(job_queue.position(&id("a3")), Some(1));
+        assert_eq!(job_queue.position(&id("a1")), Some(2));
+
+        // Pinned jobs stay ahead of new higher priority jobs
+        job_queue.push(id("c1"), key("c"), 10);
+        assert_eq!(job_queue.position(&id("a3")), Some(1));
+        assert_eq!(job_queue.position(&id("c1")), Some(2));
+
+        assert_eq!(job_queue.pop(&config).await, id("b1"));
+        job_queue.finish(&id("b1"), true);
+        assert_eq!(job_queue.pop(&config).await, id("a3"));
+        job_queue.finish(&id("a3"), true);
+        assert_eq!(job_queue.pop(&config).await, id("c1"));
+
+        // Changing the priority of a pinned job returns it to priority order
+        job_queue.move_to(&id("a2"), 0)?;
+        assert_eq!(job_queue.position(&id("a2")), Some(0));
+        job_queue.set_priority(&id("a2"), -1)?;
+        assert_eq!(job_queue.position(&id("a2")), Some(1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn waiting_jobs_are_ordered_by_priority() -> Result<()> {
+        let job_queue = JobQueue::new(1);
+        let config = Config::default();
+        let id = |id: &str| ID::from(id);
+
+        job_queue.push(id("a"), None, 0);
+        job_queue.push(id("b"), None, 0);
+        job_queue.push(id("c"), None, 5);
+
+        assert_eq!(job_queue.position(&id("c")), Some(0));
+        assert_eq!(job_queue.position(&id("a")), Some(1));
+        assert_eq!(job_queue.position(&id("b")), Some(2));
+
+        job_queue.set_priority(&id("b"), 10)?;
+        assert_eq!(job_queue.position(&id("b")), Some(0));
+
+        job_queue.move_to(&id("a"), 0)?;
+        assert_eq!(job_queue.position(&id("a")), Some(0));
+
+        // No jobs have completed so there is nothing to estimate start times from
+        assert_eq!(job_queue.estimated_start_time(&id("a")), None);
+
+        assert_eq!(job_queue.pop(&config).await, id("a"));
+        job_queue.finish(&id("a"), true);
+        assert_eq!(job_queue.pop(&config).await, id("b"));
+        assert_eq!(job_queue.pop(&config).await, id("c"));
+
+        assert!(job_queue.estimated_start_time(&id("c")).is_none());
+        assert!(job_queue.set_priority(&id("c"), 1).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_keys_take_turns() -> Result<()> {
+        let job_queue = JobQueue::new(2);
+        let id = |id: &str| ID::from(id);
+        let key = |key: &str| Some(key.to_owned());
+
+        let mut config = Config::default();
+        config.authorized_keys.insert(
+            "a".to_owned(),
+            ClientKey {
+                id: "a".to_owned(),
+                label: "a".to_owned(),
+                public_key_pem: String::new(),
+                created_at: None,
+                expires_at: None,
+                scopes: Scope::all(),
+                limits: ClientLimits {
+                    max_concurrent_jobs: Some(1),
+                    ..Default::default()
+                },
+            },
+        );
+
+        job_queue.push(id("a1"), key("a"), 0);
+        job_queue.push(id("a2"), key("a"), 0);
+        job_queue.push(id("a3"), key("a"), 0);
+        job_queue.push(id("b1"), key("b"), 0);
+
+        assert_eq!(job_queue.position(&id("a1")), Some(0));
+        assert_eq!(job_queue.position(&id("b1")), Some(1));
+        assert_eq!(job_queue.position(&id("a2")), Some(2));
+
+        assert_eq!(job_queue.pop(&config).await, id("a1"));
+        assert_eq!(job_queue.pop(&config).await, id("b1"));
+
+        // Key "a" can only slice one job at a time
+        let next = tokio::time::timeout(Duration::from_millis(50), job_queue.pop(&config));
+        assert!(next.await.is_err());
+
+        job_queue.finish(&id("a1"), true);
+        assert_eq!(job_queue.pop(&config).await, id("a2"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn waiting_and_running_jobs_can_be_cancelled() -> Result<()> {
+        let job_queue = JobQueue::new(1);
+        let config = Config::default();
+        let id = |id: &str| ID::from(id);
+
+        job_queue.push(id("a"), None, 0);
+        job_queue.push(id("b"), None, 0);
+
+        job_queue.cancel(&id("b"))?;
+        assert_eq!(job_queue.position(&id("b")), None);
+
+        assert_eq!(job_queue.pop(&config).await, id("a"));
+
+        let cancelled =
+            tokio::time::timeout(Duration::from_millis(50), job_queue.cancelled(&id("a")));
+        job_queue.cancel(&id("a"))?;
+        cancelled.await?;
+
+        job_queue.finish(&id("a"), false);
+        assert!(job_queue.cancel(&id("a")).is_err());
+
+        Ok(())
+    }
+}
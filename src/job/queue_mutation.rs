@@ -0,0 +1,70 @@
+use super::{JobGraphQL, JobMap, JobQueue};
+use crate::auth::{Scope, ScopeGuard};
+use async_graphql::{FieldResult, ID};
+use eyre::eyre;
+use tracing::instrument;
+
+#[derive(Default)]
+pub struct QueueMutation;
+
+#[derive(async_graphql::InputObject)]
+struct SetJobPriorityInput {
+    id: ID,
+    /// Jobs with higher priorities are sliced first. The default priority is 0.
+    priority: i32,
+}
+
+#[derive(async_graphql::InputObject)]
+struct ReorderJobInput {
+    id: ID,
+    /// The job's new queuePosition, ie. the number of waiting jobs that start before it. 0 moves
+    /// the job to the front of the queue.
+    position: u32,
+}
+
+#[async_graphql::Object]
+impl QueueMutation {
+    /// Changes the priority of a waiting job, moving it ahead of any waiting jobs with a lower
+    /// priority
+    #[instrument(skip(self, input, ctx))]
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs])")]
+    async fn set_job_priority<'ctx>(
+        &self,
+        ctx: &'ctx async_graphql::Context<'_>,
+        input: SetJobPriorityInput,
+    ) -> FieldResult<JobGraphQL> {
+        let jobs: &JobMap = ctx.data()?;
+        let job_queue: &JobQueue = ctx.data()?;
+
+        let mut job = jobs
+            .get_mut(&input.id)
+            .ok_or_else(|| eyre!("Job not found"))?;
+        job.check_modifiable(ctx)?;
+
+        job_queue.set_priority(&input.id, input.priority)?;
+        job.priority = input.priority;
+
+        Ok(job.graphql())
+    }
+
+    /// Moves a waiting job to a position in the queue. The job and the jobs ahead of it keep their
+    /// new order, ahead of priorities and client keys taking turns, until they start or their
+    /// priority is changed.
+    #[instrument(skip(self, input, ctx))]
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs])")]
+    async fn reorder_job<'ctx>(
+        &self,
+        ctx: &'ctx async_graphql::Context<'_>,
+        input: ReorderJobInput,
+    ) -> FieldResult<JobGraphQL> {
+        let jobs: &JobMap = ctx.data()?;
+        let job_queue: &JobQueue = ctx.data()?;
+
+        let job = jobs.get(&input.id).ok_or_else(|| eyre!("Job not found"))?;
+        job.check_modifiable(ctx)?;
+
+        job_queue.move_to(&input.id, input.position as usize)?;
+
+        Ok(job.graphql())
+    }
+}
@@ -0,0 +1,159 @@
+use axum::body::{Body, Bytes};
+use axum::http::Request;
+use axum::BoxError;
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use eyre::eyre;
+use futures_util::StreamExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::{JobMap, JobStatus};
+use crate::config::ClientLimits;
+
+/// The error code set in the `code` extension of GraphQL errors when a client key's limit is hit
+pub const QUOTA_EXCEEDED: &str = "QUOTA_EXCEEDED";
+
+/// The bytes allowed in a request body on top of a client key's `max_upload_bytes`, for the
+/// GraphQL query and the multipart headers around each upload
+const REQUEST_OVERHEAD_BYTES: u64 = 64 * 1024;
+
+/// When each client key created it's jobs over the past day, used to enforce `max_jobs_per_day`.
+/// Unlike the JobMap this is not affected by old jobs being deleted. The history is only kept in
+/// memory so daily limits start over when the server restarts.
+pub type JobHistory = Arc<DashMap<String, Vec<DateTime<Utc>>>>;
+
+/// Checks that a new job of the given upload size is within the client key's limits. This is
+/// checked before a job's uploads are processed so that jobs over the limits fail early, but only
+/// `queue_within_quota` guarantees that concurrent jobs do not exceed them.
+pub fn check_quota(
+    key_id: &str,
+    limits: &ClientLimits,
+    jobs: &JobMap,
+    job_history: &JobHistory,
+    upload_bytes: u64,
+) -> async_graphql::Result<()> {
+    if let Some(max_upload_bytes) = limits.max_upload_bytes {
+        if upload_bytes > max_upload_bytes {
+            return Err(upload_limit_error(max_upload_bytes));
+        }
+    }
+
+    let created = job_history
+        .get(key_id)
+        .map(|created| created.clone())
+        .unwrap_or_default();
+
+    check_job_limits(key_id, limits, jobs, &created)
+}
+
+/// Checks the client key's job limits and, if the new job is within them, records it and queues
+/// it by calling `queue`. The client key's history stays locked until the job is queued so that
+/// concurrent jobs cannot all pass the checks before any of them are counted.
+pub fn queue_within_quota<T>(
+    key_id: &str,
+    limits: &ClientLimits,
+    jobs: &JobMap,
+    job_history: &JobHistory,
+    queue: impl FnOnce() -> T,
+) -> async_graphql::Result<T> {
+    let since = Utc::now() - Duration::days(1);
+
+    let mut created = job_history.entry(key_id.to_owned()).or_default();
+    created.retain(|at| *at > since);
+
+    check_job_limits(key_id, limits, jobs, &created)?;
+
+    created.push(Utc::now());
+    Ok(queue())
+}
+
+fn check_job_limits(
+    key_id: &str,
+    limits: &ClientLimits,
+    jobs: &JobMap,
+    created: &[DateTime<Utc>],
+) -> async_graphql::Result<()> {
+    if let Some(max_queued_jobs) = limits.max_queued_jobs {
+        let queued_jobs = jobs
+            .iter()
+            .filter(|job| job.key_id.as_deref() == Some(key_id))
+            .filter(|job| job.status == JobStatus::Waiting)
+            .count();
+
+        if queued_jobs >= max_queued_jobs {
+            return Err(quota_error(
+                "max_queued_jobs",
+                format!("Too many queued jobs, the limit is {max_queued_jobs}"),
+            ));
+        }
+    }
+
+    if let Some(max_jobs_per_day) = limits.max_jobs_per_day {
+        let since = Utc::now() - Duration::days(1);
+        let jobs_today = created.iter().filter(|at| **at > since).count();
+
+        if jobs_today >= max_jobs_per_day {
+            return Err(quota_error(
+                "max_jobs_per_day",
+                format!("Too many jobs in the past 24 hours, the limit is {max_jobs_per_day}"),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Cuts off the request body once it is larger than the client key's `max_upload_bytes` allows,
+/// so that oversized uploads are not received in full before they are rejected. The returned flag
+/// is set if the body was cut off.
+pub fn limit_request_body(
+    req: Request<Body>,
+    max_upload_bytes: u64,
+) -> (Request<Body>, Arc<AtomicBool>) {
+    let limit = max_upload_bytes.saturating_add(REQUEST_OVERHEAD_BYTES);
+    let exceeded = Arc::new(AtomicBool::new(false));
+
+    let (parts, body) = req.into_parts();
+
+    let mut received = 0u64;
+    let body = body.map({
+        let exceeded = Arc::clone(&exceeded);
+
+        move |chunk| -> Result<Bytes, BoxError> {
+            let chunk = chunk?;
+            received += chunk.len() as u64;
+
+            if received > limit {
+                exceeded.store(true, Ordering::SeqCst);
+                return Err(
+                    eyre!("The request is larger than the client key's upload limit").into(),
+                );
+            }
+
+            Ok(chunk)
+        }
+    });
+
+    (
+        Request::from_parts(parts, Body::wrap_stream(body)),
+        exceeded,
+    )
+}
+
+/// The QUOTA_EXCEEDED error for uploads over the client key's `max_upload_bytes`
+pub fn upload_limit_error(max_upload_bytes: u64) -> async_graphql::Error {
+    quota_error(
+        "max_upload_bytes",
+        format!("The job's uploads are larger than the limit of {max_upload_bytes} bytes"),
+    )
+}
+
+/// A GraphQL error with the QUOTA_EXCEEDED code. The `limit` extension names the client key
+/// setting that was exceeded.
+fn quota_error(limit: &str, message: String) -> async_graphql::Error {
+    async_graphql::Error::new(format!("Quota exceeded: {message}")).extend_with(|_, ext| {
+        ext.set("code", QUOTA_EXCEEDED);
+        ext.set("limit", limit);
+    })
+}
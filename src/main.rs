@@ -1,8 +1,12 @@
+use auth::key_usage::KeyUsage;
+use auth::revocation::Revocations;
+use auth::Scope;
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use config::directories;
 use config::Config;
-use engine::ENGINES;
-use eyre::Result;
+use engine::EngineRegistry;
+use eyre::{eyre, Result};
 use release::Release;
 use self_host_space::KeyManager;
 use std::io::Write;
@@ -11,14 +15,20 @@ use tracing_subscriber::{prelude::*, EnvFilter};
 
 mod auth;
 mod config;
+mod doctor;
 mod engine;
 mod error;
 mod execution_context;
+mod gcode;
 mod job;
+mod mesh;
 mod mutation_root;
+mod profile;
 mod query_root;
 mod release;
+mod routes;
 mod server;
+mod url_signer;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -33,6 +43,8 @@ enum Action {
     Keys(KeyArgs),
     /// Run the slicing server
     Serve,
+    /// Check the config, client keys, engines, slicing worker user and listeners for problems
+    Doctor,
 }
 
 // Engines
@@ -82,6 +94,9 @@ struct KeyArgs {
 enum KeyAction {
     Add(AddKeyArgs),
     Remove(RemoveKeyArgs),
+    Revoke(RevokeKeyArgs),
+    Rotate(RotateKeyArgs),
+    Inspect(InspectKeyArgs),
     /// List the authorized client keys
     List,
 }
@@ -92,6 +107,15 @@ struct AddKeyArgs {
     /// Choose a unique label in order to distinguish this key from your other ones
     #[arg(index = 1)]
     label: String,
+
+    /// The key's scopes, eg. --scope submit_jobs --scope manage_profiles. Defaults to submit_jobs
+    /// and read_own_jobs.
+    #[arg(long = "scope", value_enum)]
+    scopes: Vec<Scope>,
+
+    /// Expire the key after a duration in minutes, hours, days or weeks, eg. 30d
+    #[arg(long, value_parser = parse_duration)]
+    expires: Option<chrono::Duration>,
 }
 
 /// De-authorize an existing key
@@ -100,6 +124,79 @@ struct RemoveKeyArgs {
     id: String,
 }
 
+/// Stop accepting a key's tokens without restarting the server
+#[derive(Parser, Debug)]
+struct RevokeKeyArgs {
+    id: String,
+
+    /// Only revoke the token with this id (it's jti claim) instead of every token of the key
+    #[arg(long)]
+    token: Option<String>,
+}
+
+/// Issue a new invite token for a key, replacing it's key pair. The key keeps it's id, label,
+/// scopes and limits.
+#[derive(Parser, Debug)]
+struct RotateKeyArgs {
+    id: String,
+
+    /// Change the key's expiry to a duration from now, eg. 30d. Otherwise the expiry is unchanged.
+    #[arg(long, value_parser = parse_duration)]
+    expires: Option<chrono::Duration>,
+}
+
+/// Decode an invite token and check it against the config and the server's identity
+#[derive(Parser, Debug)]
+struct InspectKeyArgs {
+    token: String,
+}
+
+/// Parses a duration such as "90m", "12h", "30d" or "2w"
+fn parse_duration(duration: &str) -> Result<chrono::Duration, String> {
+    let invalid = || format!("Invalid duration \"{duration}\", expected eg. 90m, 12h, 30d or 2w");
+
+    let unit = duration.chars().last().ok_or_else(invalid)?;
+    let value: i64 = duration[..duration.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+
+    if value <= 0 {
+        return Err(format!("Duration \"{duration}\" must be greater than 0"));
+    }
+
+    let seconds_per_unit = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    // chrono panics on durations it cannot represent so very large values are rejected first
+    let seconds = value
+        .checked_mul(seconds_per_unit)
+        .filter(|seconds| *seconds <= chrono::Duration::max_value().num_seconds())
+        .ok_or_else(invalid)?;
+    let duration = chrono::Duration::seconds(seconds);
+
+    if Utc::now().checked_add_signed(duration).is_none() {
+        return Err(invalid());
+    }
+
+    Ok(duration)
+}
+
+/// When a key created or rotated now with the given lifetime expires
+fn expires_at(expires: Option<chrono::Duration>) -> Result<Option<DateTime<Utc>>> {
+    expires
+        .map(|expires| {
+            Utc::now()
+                .checked_add_signed(expires)
+                .ok_or_else(|| eyre!("Invalid duration, the expiry time is too far in the future"))
+        })
+        .transpose()
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::registry()
@@ -112,21 +209,38 @@ async fn main() -> Result<()> {
         .init();
 
     let args = Args::parse();
+
+    // The doctor reports config errors itself instead of failing to load the config
+    if let Action::Doctor = args.action {
+        let is_healthy = doctor::run().await?;
+        std::process::exit(if is_healthy { 0 } else { 1 });
+    }
+
     let dirs = directories()?;
     let mut config = Config::load().await?;
+    let engines = EngineRegistry::from_config(&config)?;
 
     match args.action {
         Action::Engines(EngineArgs { action }) => match action {
             EngineAction::Install(install_args) => {
-                install(install_args).await?;
+                install(&engines, install_args).await?;
             }
-            EngineAction::List => print_engines(),
+            EngineAction::List => print_engines(&engines),
         },
         Action::Keys(KeyArgs { action }) => match action {
             KeyAction::Add(add_args) => {
                 let server_keys = KeyManager::load_or_create(dirs.config_dir()).await?;
 
-                let (invite_token, _) = config.add_key(&server_keys, add_args.label.clone())?;
+                let scopes = if add_args.scopes.is_empty() {
+                    Scope::defaults()
+                } else {
+                    add_args.scopes
+                };
+
+                let expires_at = expires_at(add_args.expires)?;
+
+                let (invite_token, _) =
+                    config.add_key(&server_keys, add_args.label.clone(), scopes, expires_at)?;
                 config.save().await?;
 
                 println!("Key created with label \"{}\"\n", add_args.label);
@@ -141,50 +255,130 @@ async fn main() -> Result<()> {
 
                 println!("Key (id: \"{}\") removed", rm_args.id);
             }
+            KeyAction::Revoke(revoke_args) => {
+                if !config.authorized_keys.contains_key(&revoke_args.id) {
+                    return Err(eyre!("Key (id: \"{}\") not found", revoke_args.id));
+                }
+
+                let path = Revocations::default_path()?;
+                let mut revocations = Revocations::load(&path).await?;
+
+                // Token ids are only unique per key so they are revoked for the given key
+                let (revoked, id) = match revoke_args.token {
+                    Some(token_id) => (
+                        revocations.tokens.entry(revoke_args.id).or_default(),
+                        token_id,
+                    ),
+                    None => (&mut revocations.keys, revoke_args.id),
+                };
+
+                if !revoked.contains(&id) {
+                    revoked.push(id.clone());
+                }
+                revocations.save(&path).await?;
+
+                println!(
+                    "\"{}\" revoked. The running server will reject it immediately.",
+                    id
+                );
+            }
+            KeyAction::Rotate(rotate_args) => {
+                let server_keys = KeyManager::load_or_create(dirs.config_dir()).await?;
+
+                let expires_at = expires_at(rotate_args.expires)?;
+
+                let (invite_token, retired_public_key_pem, client_key) =
+                    config.rotate_key(&server_keys, &rotate_args.id, expires_at)?;
+                let label = client_key.label.clone();
+                config.save().await?;
+
+                // Revoke the old key pair so that the running server rejects it immediately
+                let path = Revocations::default_path()?;
+                let mut revocations = Revocations::load(&path).await?;
+                revocations.public_keys.push(retired_public_key_pem);
+                revocations.save(&path).await?;
+
+                println!("Key \"{}\" (id: \"{}\") rotated\n", label, rotate_args.id);
+                println!(
+                    "Replace the key's invite token in your PrintSpool slicing settings with the token bellow. The running server accepts the new token and rejects the old one immediately:\n\n{}\n",
+                    invite_token
+                )
+            }
+            KeyAction::Inspect(inspect_args) => {
+                let is_valid = doctor::inspect_invite_token(&config, &inspect_args.token).await?;
+
+                if !is_valid {
+                    std::process::exit(1);
+                }
+            }
             KeyAction::List => {
                 if config.authorized_keys.is_empty() {
                     println!("No authorized client keys. Use slicing-server keys add [label] to authorize a slicing client.");
                     return Ok(());
                 }
 
+                let key_usage = KeyUsage::load(&KeyUsage::default_path()?).await?;
+
                 println!("Authorized client keys:\n");
                 for key in config.authorized_keys.values() {
-                    println!("  - {} (id: \"{}\")", key.label, key.id)
+                    let is_expired = key
+                        .expires_at
+                        .map_or(false, |expires_at| expires_at <= Utc::now());
+
+                    println!(
+                        "  - {} (id: \"{}\"){}",
+                        key.label,
+                        key.id,
+                        if is_expired { " [EXPIRED]" } else { "" },
+                    );
+                    println!(
+                        "      created: {}, last used: {}, expires: {}",
+                        format_time(key.created_at, "unknown"),
+                        format_time(key_usage.last_used.get(&key.id).copied(), "never"),
+                        format_time(key.expires_at, "never"),
+                    );
                 }
             }
         },
         Action::Serve => server::serve().await?,
+        Action::Doctor => unreachable!("The doctor is run before the config is loaded"),
     };
 
     Ok(())
 }
 
-fn print_engines() {
+fn print_engines(engines: &EngineRegistry) {
     println!(
         "Available Slicing Engines:\n\n{}",
-        ENGINES
-            .keys()
-            .map(|engine_id| format!("  - {}", engine_id.0))
+        engines
+            .all()
+            .iter()
+            .map(|engine| format!("  - {}", engine.metadata().id.0))
             .collect::<Vec<_>>()
             .join("\n"),
     );
 }
 
-async fn install(args: InstallEngineArgs) -> Result<()> {
-    let engine = match ENGINES.get(&(&args.engine).into()) {
+fn format_time(time: Option<DateTime<Utc>>, default: &str) -> String {
+    time.map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| default.to_owned())
+}
+
+async fn install(engines: &EngineRegistry, args: InstallEngineArgs) -> Result<()> {
+    let engine = match engines.get(&(&args.engine).into()) {
         Some(engine) => engine,
         None => {
             error!("Unknown engine: {}", &args.engine);
-            print_engines();
+            print_engines(engines);
             let _ = std::io::stdout().flush();
             std::process::exit(1);
         }
     };
 
     let release = if let Some(release_url) = args.release_url {
-        engine.release_config.parse(&release_url)?
+        engine.parse_release(&release_url)?
     } else {
-        (&*engine.release_config).latest_release().await?
+        engine.latest_release().await?
     };
 
     if let Release::Local(_) = &release {
@@ -197,3 +391,34 @@ async fn install(args: InstallEngineArgs) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("90m"), Ok(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("12h"), Ok(chrono::Duration::hours(12)));
+        assert_eq!(parse_duration("30d"), Ok(chrono::Duration::days(30)));
+        assert_eq!(parse_duration("2w"), Ok(chrono::Duration::weeks(2)));
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for duration in [
+            "",
+            "d",
+            "30",
+            "30x",
+            "30é",
+            "é",
+            "0d",
+            "-1d",
+            "99999999999w",
+            "9223372036854775807m",
+        ] {
+            assert!(parse_duration(duration).is_err(), "{duration:?}");
+        }
+    }
+}
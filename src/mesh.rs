@@ -0,0 +1,124 @@
+use cgmath::{Matrix4, Point3, SquareMatrix, Transform};
+use eyre::{eyre, Result};
+use std::path::Path;
+
+pub mod analysis;
+pub mod arrange;
+pub mod obj;
+pub mod render;
+pub mod stl;
+pub mod three_mf;
+
+/// An indexed triangle mesh
+#[derive(Clone, Default, Debug)]
+pub struct Mesh {
+    pub vertices: Vec<Point3<f32>>,
+    /// Indices into `vertices`, wound counter-clockwise when viewed from outside the mesh
+    pub triangles: Vec<[usize; 3]>,
+}
+
+/// The model file formats that the server can read and write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshFormat {
+    Stl,
+    Obj,
+    ThreeMf,
+}
+
+impl MeshFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("stl") => Ok(Self::Stl),
+            Some("obj") => Ok(Self::Obj),
+            Some("3mf") => Ok(Self::ThreeMf),
+            _ => Err(eyre!("Unsupported model format: {:?}", path)),
+        }
+    }
+}
+
+impl Mesh {
+    /// Reads a mesh from an STL, OBJ or 3MF file. The objects in 3MF files are merged into a
+    /// single mesh in their build positions.
+    pub fn read(path: &Path) -> Result<Self> {
+        match MeshFormat::from_path(path)? {
+            MeshFormat::Stl => stl::read(path),
+            MeshFormat::Obj => obj::read(path),
+            MeshFormat::ThreeMf => Ok(Self::merge(three_mf::read(path)?)),
+        }
+    }
+
+    /// Reads the volumes of a model, eg. the parts of a multi-material 3MF. STL and OBJ files
+    /// contain a single volume.
+    pub fn read_volumes(path: &Path) -> Result<Vec<Self>> {
+        match MeshFormat::from_path(path)? {
+            MeshFormat::ThreeMf => Ok(three_mf::read_volumes(path)?.concat()),
+            _ => Ok(vec![Self::read(path)?]),
+        }
+    }
+
+    /// Writes the mesh in the format matching the path's extension
+    pub fn write(&self, path: &Path) -> Result<()> {
+        match MeshFormat::from_path(path)? {
+            MeshFormat::Stl => stl::write(path, self),
+            MeshFormat::Obj => obj::write(path, self),
+            MeshFormat::ThreeMf => three_mf::write(path, std::slice::from_ref(self)),
+        }
+    }
+
+    /// Combines several meshes into one
+    pub fn merge(meshes: impl IntoIterator<Item = Mesh>) -> Self {
+        let mut merged = Mesh::default();
+
+        for mesh in meshes {
+            let offset = merged.vertices.len();
+
+            merged.vertices.extend(mesh.vertices);
+            merged.triangles.extend(
+                mesh.triangles
+                    .into_iter()
+                    .map(|[a, b, c]| [a + offset, b + offset, c + offset]),
+            );
+        }
+
+        merged
+    }
+
+    /// The minimum and maximum corners of the mesh's bounding box, if it has any vertices
+    pub fn bounds(&self) -> Option<(Point3<f32>, Point3<f32>)> {
+        let first = *self.vertices.first()?;
+        let (mut min, mut max) = (first, first);
+
+        for vertex in &self.vertices {
+            min = Point3::new(
+                min.x.min(vertex.x),
+                min.y.min(vertex.y),
+                min.z.min(vertex.z),
+            );
+            max = Point3::new(
+                max.x.max(vertex.x),
+                max.y.max(vertex.y),
+                max.z.max(vertex.z),
+            );
+        }
+
+        Some((min, max))
+    }
+
+    pub fn transform(&mut self, mat4: &Matrix4<f32>) {
+        for vertex in self.vertices.iter_mut() {
+            *vertex = mat4.transform_point(*vertex);
+        }
+
+        // Mirroring transforms turn the mesh inside out so the winding has to be reversed to keep
+        // the triangles facing outwards
+        if mat4.determinant() < 0.0 {
+            for triangle in self.triangles.iter_mut() {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+}
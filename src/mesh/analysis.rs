@@ -0,0 +1,281 @@
+use cgmath::{EuclideanSpace, InnerSpace, Point3};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::Mesh;
+
+#[derive(async_graphql::SimpleObject, Clone, Copy, Debug)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl From<Point3<f32>> for Vec3 {
+    fn from(point: Point3<f32>) -> Self {
+        Self {
+            x: point.x,
+            y: point.y,
+            z: point.z,
+        }
+    }
+}
+
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct BoundingBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+/// The geometry of a model and any problems with it that are likely to cause slicing to fail
+#[derive(async_graphql::SimpleObject, Clone, Debug)]
+pub struct MeshReport {
+    pub vertex_count: u32,
+    pub triangle_count: u32,
+    /// The model's bounds in millimeters, if it has any vertices
+    pub bounding_box: Option<BoundingBox>,
+    /// The enclosed volume in cubic millimeters. Only meaningful for manifold models.
+    pub volume: f32,
+    /// Edges that belong to a single triangle, ie. the edges of holes in the surface
+    pub open_edges: u32,
+    /// Edges shared by more than two triangles
+    pub non_manifold_edges: u32,
+    /// Edges where neighbouring triangles are wound in opposite directions, ie. flipped normals
+    pub misoriented_edges: u32,
+    /// Triangles that use the same vertex more than once
+    pub degenerate_triangles: u32,
+    /// True if the surface is closed and every edge is shared by exactly two triangles
+    pub is_manifold: bool,
+    /// True if the triangles face into the model instead of out of it
+    pub is_inside_out: bool,
+    /// A description of each problem found
+    pub problems: Vec<String>,
+}
+
+impl MeshReport {
+    pub fn has_problems(&self) -> bool {
+        !self.problems.is_empty()
+    }
+}
+
+/// The triangles using each edge, keyed by the edge's vertices in ascending order. Each use
+/// records whether the triangle traverses the edge in ascending order.
+type EdgeMap = HashMap<(usize, usize), Vec<(usize, bool)>>;
+
+fn edge_map(triangles: &[[usize; 3]]) -> EdgeMap {
+    let mut edges = EdgeMap::new();
+
+    for (i, triangle) in triangles.iter().enumerate() {
+        for (a, b) in triangle_edges(triangle) {
+            edges
+                .entry((a.min(b), a.max(b)))
+                .or_default()
+                .push((i, a < b));
+        }
+    }
+
+    edges
+}
+
+fn triangle_edges(&[a, b, c]: &[usize; 3]) -> [(usize, usize); 3] {
+    [(a, b), (b, c), (c, a)]
+}
+
+fn is_degenerate(&[a, b, c]: &[usize; 3]) -> bool {
+    a == b || b == c || c == a
+}
+
+/// Six times the signed volume of the triangles, positive if they face outwards
+fn signed_volume6<'a>(mesh: &Mesh, triangles: impl IntoIterator<Item = &'a [usize; 3]>) -> f32 {
+    triangles
+        .into_iter()
+        .map(|&[a, b, c]| {
+            let [a, b, c] = [a, b, c].map(|i| mesh.vertices[i].to_vec());
+            a.dot(b.cross(c))
+        })
+        .sum()
+}
+
+pub fn analyze(mesh: &Mesh) -> MeshReport {
+    let (degenerate, triangles): (Vec<_>, Vec<_>) =
+        mesh.triangles.iter().copied().partition(is_degenerate);
+
+    let mut open_edges = 0;
+    let mut non_manifold_edges = 0;
+    let mut misoriented_edges = 0;
+
+    for uses in edge_map(&triangles).values() {
+        match uses.as_slice() {
+            [_] => open_edges += 1,
+            [(_, a_forward), (_, b_forward)] if a_forward == b_forward => misoriented_edges += 1,
+            [_, _] => {}
+            _ => non_manifold_edges += 1,
+        }
+    }
+
+    let volume = signed_volume6(mesh, &triangles) / 6.0;
+    let is_manifold = open_edges == 0 && non_manifold_edges == 0;
+    let is_inside_out = is_manifold && misoriented_edges == 0 && volume < 0.0;
+
+    let mut problems = vec![];
+    if open_edges > 0 {
+        problems.push(format!("{open_edges} open edges (holes in the surface)"));
+    }
+    if non_manifold_edges > 0 {
+        problems.push(format!(
+            "{non_manifold_edges} edges shared by more than two triangles"
+        ));
+    }
+    if misoriented_edges > 0 {
+        problems.push(format!(
+            "{misoriented_edges} edges between triangles facing opposite directions"
+        ));
+    }
+    if !degenerate.is_empty() {
+        problems.push(format!("{} degenerate triangles", degenerate.len()));
+    }
+    if is_inside_out {
+        problems.push("The model is inside out".to_owned());
+    }
+
+    MeshReport {
+        vertex_count: mesh.vertices.len() as u32,
+        triangle_count: mesh.triangles.len() as u32,
+        bounding_box: mesh.bounds().map(|(min, max)| BoundingBox {
+            min: min.into(),
+            max: max.into(),
+        }),
+        volume: volume.abs(),
+        open_edges,
+        non_manifold_edges,
+        misoriented_edges,
+        degenerate_triangles: degenerate.len() as u32,
+        is_manifold,
+        is_inside_out,
+        problems,
+    }
+}
+
+/// Fixes the problems that can be fixed automatically: degenerate and duplicate triangles are
+/// removed, the triangles of each connected part are wound consistently and facing outwards, and
+/// holes are closed. Non-manifold edges are left as they are.
+pub fn repair(mesh: &Mesh) -> Mesh {
+    let mut seen = HashSet::new();
+    let mut triangles = mesh
+        .triangles
+        .iter()
+        .copied()
+        .filter(|triangle| !is_degenerate(triangle))
+        .filter(|triangle| {
+            let mut key = *triangle;
+            key.sort();
+            seen.insert(key)
+        })
+        .collect::<Vec<_>>();
+
+    orient(mesh, &mut triangles);
+    fill_holes(&mut triangles);
+    // Open parts do not have a meaningful volume so the direction they face is checked again once
+    // their holes are closed
+    orient(mesh, &mut triangles);
+
+    Mesh {
+        vertices: mesh.vertices.clone(),
+        triangles,
+    }
+}
+
+/// Flips triangles so that neighbours are wound in the same direction, then flips any part that
+/// is inside out
+fn orient(mesh: &Mesh, triangles: &mut [[usize; 3]]) {
+    let edges = edge_map(triangles);
+
+    let mut flipped = vec![false; triangles.len()];
+    let mut visited = vec![false; triangles.len()];
+
+    for start in 0..triangles.len() {
+        if visited[start] {
+            continue;
+        }
+
+        visited[start] = true;
+        let mut part = vec![start];
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(current) = queue.pop_front() {
+            for (a, b) in triangle_edges(&triangles[current]) {
+                let uses = &edges[&(a.min(b), a.max(b))];
+
+                // Orientation can only be propagated across manifold edges
+                if uses.len() != 2 {
+                    continue;
+                }
+
+                let current_forward = (a < b) != flipped[current];
+
+                for &(neighbour, forward) in uses {
+                    if neighbour == current || visited[neighbour] {
+                        continue;
+                    }
+
+                    // Neighbours must traverse the shared edge in the opposite direction
+                    flipped[neighbour] = forward == current_forward;
+                    visited[neighbour] = true;
+                    part.push(neighbour);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        for &i in &part {
+            if flipped[i] {
+                triangles[i].swap(1, 2);
+            }
+        }
+
+        let volume = signed_volume6(mesh, part.iter().map(|&i| &triangles[i]));
+        if volume < 0.0 {
+            for &i in &part {
+                triangles[i].swap(1, 2);
+            }
+        }
+    }
+}
+
+/// Closes each hole with a fan of triangles
+fn fill_holes(triangles: &mut Vec<[usize; 3]>) {
+    let edges = edge_map(triangles);
+
+    // The edges around each hole, wound in the direction the filling triangles need
+    let mut hole_edges: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (&(low, high), uses) in &edges {
+        if let [(_, forward)] = uses.as_slice() {
+            let (a, b) = if *forward { (low, high) } else { (high, low) };
+            hole_edges.entry(b).or_default().push(a);
+        }
+    }
+
+    let mut starts = hole_edges.keys().copied().collect::<Vec<_>>();
+    starts.sort();
+
+    for start in starts {
+        let mut hole = vec![start];
+        let mut current = start;
+
+        loop {
+            let next = match hole_edges.get_mut(&current).and_then(|next| next.pop()) {
+                Some(next) => next,
+                None => break,
+            };
+
+            if next == start {
+                for i in 1..hole.len().saturating_sub(1) {
+                    triangles.push([hole[0], hole[i], hole[i + 1]]);
+                }
+                break;
+            }
+
+            hole.push(next);
+            current = next;
+        }
+    }
+}
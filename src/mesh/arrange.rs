@@ -0,0 +1,171 @@
+use cgmath::Point2;
+use eyre::{eyre, Result};
+
+/// The outline of a printer's bed, as described by a profile's `bed_shape` setting
+#[derive(Clone, Debug)]
+pub struct BedShape {
+    pub points: Vec<Point2<f32>>,
+}
+
+impl BedShape {
+    /// Parses a list of XxY points, eg. "0x0,250x0,250x210,0x210"
+    pub fn parse(value: &str) -> Option<Self> {
+        let points = value
+            .split(',')
+            .map(|point| {
+                let (x, y) = point.trim().split_once('x')?;
+                Some(Point2::new(x.parse().ok()?, y.parse().ok()?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self { points })
+    }
+
+    /// The minimum and maximum corners of the bed's bounding box
+    pub fn bounds(&self) -> Option<(Point2<f32>, Point2<f32>)> {
+        let first = *self.points.first()?;
+
+        Some(
+            self.points
+                .iter()
+                .fold((first, first), |(min, max), point| {
+                    (
+                        Point2::new(min.x.min(point.x), min.y.min(point.y)),
+                        Point2::new(max.x.max(point.x), max.y.max(point.y)),
+                    )
+                }),
+        )
+    }
+
+    /// True if the point is inside the outline
+    fn contains(&self, point: Point2<f32>) -> bool {
+        let mut inside = false;
+        let mut previous = match self.points.last() {
+            Some(previous) => *previous,
+            None => return false,
+        };
+
+        for current in &self.points {
+            let crosses = (current.y > point.y) != (previous.y > point.y);
+
+            if crosses {
+                let x = current.x
+                    + (point.y - current.y) * (previous.x - current.x) / (previous.y - current.y);
+
+                if point.x < x {
+                    inside = !inside;
+                }
+            }
+
+            previous = *current;
+        }
+
+        inside
+    }
+}
+
+/// Where an object's footprint was placed on the bed
+#[derive(Clone, Copy, Debug)]
+pub struct Placement {
+    /// The center of the footprint on the bed
+    pub center: Point2<f32>,
+    /// True if the object was turned 90 degrees about the Z axis to fit
+    pub rotated: bool,
+}
+
+/// Packs rectangular footprints (width, depth) onto the bed in rows, leaving `spacing` millimeters
+/// between them. The packed footprints are centered on the bed.
+///
+/// When `allow_rotation` is set, footprints may be turned 90 degrees so that rows are kept short.
+pub fn arrange(
+    bed: &BedShape,
+    footprints: &[(f32, f32)],
+    spacing: f32,
+    allow_rotation: bool,
+) -> Result<Vec<Placement>> {
+    let (bed_min, bed_max) = bed
+        .bounds()
+        .ok_or_else(|| eyre!("The bed shape has no points"))?;
+    let bed_size = bed_max - bed_min;
+
+    let oriented = footprints
+        .iter()
+        .map(|&(width, depth)| {
+            let rotated =
+                allow_rotation && (width > bed_size.x || (depth > width && depth <= bed_size.x));
+
+            if rotated {
+                (depth, width, true)
+            } else {
+                (width, depth, false)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Placing the deepest footprints first keeps each row close to the depth of it's footprints
+    let mut order = (0..oriented.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| oriented[*b].1.total_cmp(&oriented[*a].1));
+
+    let mut corners = vec![Point2::new(0.0, 0.0); oriented.len()];
+    let (mut x, mut y, mut row_depth) = (0f32, 0f32, 0f32);
+    let (mut layout_width, mut layout_depth) = (0f32, 0f32);
+
+    for i in order {
+        let (width, depth, _) = oriented[i];
+
+        if x > 0.0 && x + width > bed_size.x {
+            x = 0.0;
+            y += row_depth + spacing;
+            row_depth = 0.0;
+        }
+
+        if x + width > bed_size.x || y + depth > bed_size.y {
+            return Err(eyre!("The objects do not fit on the bed"));
+        }
+
+        corners[i] = Point2::new(x, y);
+
+        x += width + spacing;
+        row_depth = row_depth.max(depth);
+        layout_width = layout_width.max(x - spacing);
+        layout_depth = layout_depth.max(y + depth);
+    }
+
+    let offset_x = bed_min.x + (bed_size.x - layout_width) / 2.0;
+    let offset_y = bed_min.y + (bed_size.y - layout_depth) / 2.0;
+
+    let placements = corners
+        .iter()
+        .zip(&oriented)
+        .map(|(corner, &(width, depth, rotated))| Placement {
+            center: Point2::new(
+                offset_x + corner.x + width / 2.0,
+                offset_y + corner.y + depth / 2.0,
+            ),
+            rotated,
+        })
+        .collect::<Vec<_>>();
+
+    // Packing uses the bed's bounding box so footprints also have to be checked against the
+    // outline of non-rectangular beds, eg. round delta printer beds. The corners are inset slightly
+    // so that footprints touching the edge of the bed are not rejected.
+    for (placement, &(width, depth, _)) in placements.iter().zip(&oriented) {
+        let half_width = (width / 2.0 - 0.01).max(0.0);
+        let half_depth = (depth / 2.0 - 0.01).max(0.0);
+
+        let fits = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .all(|(dx, dy)| {
+                bed.contains(Point2::new(
+                    placement.center.x + dx * half_width,
+                    placement.center.y + dy * half_depth,
+                ))
+            });
+
+        if !fits {
+            return Err(eyre!("The objects do not fit on the bed"));
+        }
+    }
+
+    Ok(placements)
+}
@@ -0,0 +1,84 @@
+use cgmath::Point3;
+use eyre::{eyre, Result};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::Mesh;
+
+/// Reads the geometry of a Wavefront OBJ. Polygons are split into triangle fans and everything
+/// other than vertex positions and faces (normals, texture coordinates, materials) is ignored.
+pub fn read(path: &Path) -> Result<Mesh> {
+    let text = std::fs::read_to_string(path)?;
+    let mut mesh = Mesh::default();
+
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+
+        match words.next() {
+            Some("v") => {
+                let coords = words
+                    .take(3)
+                    .map(|word| word.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| eyre!("Invalid OBJ vertex: {:?}", line.trim()))?;
+
+                if coords.len() != 3 {
+                    return Err(eyre!("Invalid OBJ vertex: {:?}", line.trim()));
+                }
+                mesh.vertices
+                    .push(Point3::new(coords[0], coords[1], coords[2]));
+            }
+            Some("f") => {
+                let indices = words
+                    .map(|word| parse_index(word, mesh.vertices.len()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| eyre!("Invalid OBJ face: {:?}", line.trim()))?;
+
+                if indices.len() < 3 {
+                    return Err(eyre!("Invalid OBJ face: {:?}", line.trim()));
+                }
+
+                for i in 1..indices.len() - 1 {
+                    mesh.triangles
+                        .push([indices[0], indices[i], indices[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(mesh)
+}
+
+/// Parses a face's vertex reference, eg. "3", "3/1" or "3/1/2". Indices start at 1 and negative
+/// indices are relative to the end of the vertices read so far.
+fn parse_index(word: &str, vertex_count: usize) -> Option<usize> {
+    let index = word.split('/').next()?.parse::<isize>().ok()?;
+
+    let index = if index < 0 {
+        vertex_count as isize + index
+    } else {
+        index - 1
+    };
+
+    if index >= 0 && (index as usize) < vertex_count {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+pub fn write(path: &Path, mesh: &Mesh) -> Result<()> {
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+
+    for vertex in &mesh.vertices {
+        writeln!(writer, "v {} {} {}", vertex.x, vertex.y, vertex.z)?;
+    }
+    for [a, b, c] in &mesh.triangles {
+        writeln!(writer, "f {} {} {}", a + 1, b + 1, c + 1)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
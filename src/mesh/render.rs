@@ -0,0 +1,107 @@
+use cgmath::{Deg, InnerSpace, Matrix4, Vector3};
+use eyre::Result;
+
+use super::Mesh;
+
+/// The color of the model's faces when lit head on
+const MODEL_COLOR: [f32; 3] = [237.0, 107.0, 33.0];
+/// The fraction of the image's size left empty around the model
+const MARGIN: f32 = 0.05;
+
+/// Renders the mesh from above and in front of it's front right corner as a PNG with a
+/// transparent background. Returns None if the mesh has no triangles.
+pub fn render_png(mesh: &Mesh, width: u32, height: u32) -> Result<Option<Vec<u8>>> {
+    if mesh.triangles.is_empty() || width == 0 || height == 0 {
+        return Ok(None);
+    }
+
+    // Turn the model to face the camera and then tilt it towards the camera so that the top is
+    // visible. After this the camera looks down the Z axis with Y pointing up.
+    let mut view = mesh.clone();
+    view.transform(&(Matrix4::from_angle_x(Deg(-60.0)) * Matrix4::from_angle_z(Deg(-45.0))));
+
+    let (min, max) = match view.bounds() {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+
+    let (width_f, height_f) = (width as f32, height as f32);
+    let scale = ((width_f * (1.0 - 2.0 * MARGIN)) / (max.x - min.x).max(f32::EPSILON))
+        .min((height_f * (1.0 - 2.0 * MARGIN)) / (max.y - min.y).max(f32::EPSILON));
+
+    // Center the model in the image, flipping Y so that rows run from the top of the image down
+    let offset_x = (width_f - (max.x - min.x) * scale) / 2.0;
+    let offset_y = (height_f - (max.y - min.y) * scale) / 2.0;
+    let to_screen = |x: f32, y: f32| {
+        (
+            offset_x + (x - min.x) * scale,
+            height_f - (offset_y + (y - min.y) * scale),
+        )
+    };
+
+    let light = Vector3::new(0.3, 0.5, 1.0).normalize();
+
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    let mut depths = vec![f32::NEG_INFINITY; (width * height) as usize];
+
+    for &[a, b, c] in &view.triangles {
+        let [a, b, c] = [a, b, c].map(|i| view.vertices[i]);
+
+        let normal = (b - a).cross(c - a);
+        if normal.magnitude2() == 0.0 {
+            continue;
+        }
+
+        // Faces are lit from both sides so that models with flipped normals are still visible
+        let brightness = 0.35 + 0.65 * normal.normalize().dot(light).abs();
+        let color = MODEL_COLOR.map(|channel| (channel * brightness).min(255.0) as u8);
+
+        let [(ax, ay), (bx, by), (cx, cy)] = [a, b, c].map(|vertex| to_screen(vertex.x, vertex.y));
+
+        let area = (bx - ax) * (cy - ay) - (cx - ax) * (by - ay);
+        if area == 0.0 {
+            continue;
+        }
+
+        let min_x = ax.min(bx).min(cx).floor().max(0.0) as u32;
+        let max_x = ax.max(bx).max(cx).ceil().min(width_f - 1.0) as u32;
+        let min_y = ay.min(by).min(cy).floor().max(0.0) as u32;
+        let max_y = ay.max(by).max(cy).ceil().min(height_f - 1.0) as u32;
+
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                // Sample the center of each pixel
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+                let wa = ((bx - px) * (cy - py) - (cx - px) * (by - py)) / area;
+                let wb = ((cx - px) * (ay - py) - (ax - px) * (cy - py)) / area;
+                let wc = 1.0 - wa - wb;
+
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+
+                let depth = wa * a.z + wb * b.z + wc * c.z;
+                let i = (y * width + x) as usize;
+
+                if depth > depths[i] {
+                    depths[i] = depth;
+                    pixels[i * 4..i * 4 + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+                }
+            }
+        }
+    }
+
+    let mut png = vec![];
+    {
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        writer.finish()?;
+    }
+
+    Ok(Some(png))
+}
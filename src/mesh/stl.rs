@@ -0,0 +1,193 @@
+use cgmath::{InnerSpace, Point3};
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::Mesh;
+
+const HEADER_LEN: usize = 80;
+const TRIANGLE_LEN: usize = 50;
+
+/// Reads an ASCII or binary STL, merging duplicate vertices
+pub fn read(path: &Path) -> Result<Mesh> {
+    let bytes = std::fs::read(path)?;
+
+    let mut builder = MeshBuilder::default();
+
+    if let Some(triangle_count) = binary_triangle_count(&bytes) {
+        let triangles = &bytes[HEADER_LEN + 4..HEADER_LEN + 4 + triangle_count * TRIANGLE_LEN];
+
+        for triangle in triangles.chunks_exact(TRIANGLE_LEN) {
+            // Skip the normal, which is recalculated from the winding when needed
+            let vertices = triangle[12..48]
+                .chunks_exact(12)
+                .map(|vertex| {
+                    let coord = |i: usize| {
+                        f32::from_le_bytes([
+                            vertex[i * 4],
+                            vertex[i * 4 + 1],
+                            vertex[i * 4 + 2],
+                            vertex[i * 4 + 3],
+                        ])
+                    };
+                    Point3::new(coord(0), coord(1), coord(2))
+                })
+                .collect::<Vec<_>>();
+
+            builder.add_triangle([vertices[0], vertices[1], vertices[2]]);
+        }
+    } else {
+        let text = std::str::from_utf8(&bytes).map_err(|_| eyre!("Invalid STL file"))?;
+        let mut vertices = vec![];
+
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+
+            if words.next() != Some("vertex") {
+                continue;
+            }
+
+            let coords = words
+                .map(|word| word.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| eyre!("Invalid STL vertex: {:?}", line.trim()))?;
+
+            if coords.len() != 3 {
+                return Err(eyre!("Invalid STL vertex: {:?}", line.trim()));
+            }
+            vertices.push(Point3::new(coords[0], coords[1], coords[2]));
+
+            if vertices.len() == 3 {
+                builder.add_triangle([vertices[0], vertices[1], vertices[2]]);
+                vertices.clear();
+            }
+        }
+    }
+
+    Ok(builder.mesh)
+}
+
+/// Returns the number of triangles if the STL is binary. Binary STLs may also start with "solid"
+/// so the triangle count is checked against the file length instead. Some exporters pad the file
+/// after the last triangle so longer files are accepted.
+fn binary_triangle_count(bytes: &[u8]) -> Option<usize> {
+    if bytes.len() < HEADER_LEN + 4 {
+        return None;
+    }
+
+    let triangle_count = u32::from_le_bytes([
+        bytes[HEADER_LEN],
+        bytes[HEADER_LEN + 1],
+        bytes[HEADER_LEN + 2],
+        bytes[HEADER_LEN + 3],
+    ]) as usize;
+
+    if bytes.len() >= HEADER_LEN + 4 + triangle_count * TRIANGLE_LEN {
+        Some(triangle_count)
+    } else {
+        None
+    }
+}
+
+/// Writes the mesh as a binary STL
+pub fn write(path: &Path, mesh: &Mesh) -> Result<()> {
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+
+    writer.write_all(&[0u8; HEADER_LEN])?;
+    writer.write_all(&(mesh.triangles.len() as u32).to_le_bytes())?;
+
+    for [a, b, c] in &mesh.triangles {
+        let [a, b, c] = [mesh.vertices[*a], mesh.vertices[*b], mesh.vertices[*c]];
+
+        let normal = (b - a).cross(c - a);
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        };
+
+        for value in [normal.x, normal.y, normal.z] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for vertex in [a, b, c] {
+            for value in [vertex.x, vertex.y, vertex.z] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        writer.write_all(&0u16.to_le_bytes())?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Builds an indexed mesh from a triangle soup
+#[derive(Default)]
+struct MeshBuilder {
+    mesh: Mesh,
+    indices: HashMap<[u32; 3], usize>,
+}
+
+impl MeshBuilder {
+    fn add_triangle(&mut self, vertices: [Point3<f32>; 3]) {
+        let triangle = vertices.map(|vertex| {
+            let key = [vertex.x.to_bits(), vertex.y.to_bits(), vertex.z.to_bits()];
+            let vertices = &mut self.mesh.vertices;
+
+            *self.indices.entry(key).or_insert_with(|| {
+                vertices.push(vertex);
+                vertices.len() - 1
+            })
+        });
+
+        self.mesh.triangles.push(triangle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary_stl(header: &[u8], triangle_count: u32, trailing_bytes: usize) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        bytes.resize(HEADER_LEN, b' ');
+        bytes.extend(triangle_count.to_le_bytes());
+        bytes.resize(
+            bytes.len() + triangle_count as usize * TRIANGLE_LEN + trailing_bytes,
+            0,
+        );
+        bytes
+    }
+
+    #[test]
+    fn binary_stls_can_start_with_solid() {
+        let bytes = binary_stl(b"solid exported by a CAD tool", 2, 0);
+        assert_eq!(binary_triangle_count(&bytes), Some(2));
+    }
+
+    #[test]
+    fn binary_stls_can_have_trailing_bytes() {
+        let bytes = binary_stl(b"", 2, 3);
+        assert_eq!(binary_triangle_count(&bytes), Some(2));
+    }
+
+    #[test]
+    fn truncated_binary_stls_are_not_binary() {
+        let mut bytes = binary_stl(b"", 2, 0);
+        bytes.pop();
+        assert_eq!(binary_triangle_count(&bytes), None);
+    }
+
+    #[test]
+    fn ascii_stls_are_not_binary() {
+        let mut text = "solid cube\n".to_owned();
+        for _ in 0..4 {
+            text.push_str("facet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\n");
+        }
+        text.push_str("endsolid cube\n");
+
+        assert_eq!(binary_triangle_count(text.as_bytes()), None);
+    }
+}
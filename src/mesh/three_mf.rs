@@ -0,0 +1,515 @@
+use cgmath::{Matrix4, Point3, SquareMatrix};
+use eyre::{eyre, Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::path::Path;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use super::Mesh;
+
+const DEFAULT_MODEL_PATH: &'static str = "3D/3dmodel.model";
+const PRUSA_SLICER_CONFIG_PATH: &'static str = "Metadata/Slic3r_PE_model.config";
+
+/// Components can reference other objects so their depth is limited to avoid cycles
+const MAX_COMPONENT_DEPTH: usize = 16;
+
+/// Reads the objects on a 3MF's build plate, each in their build position
+pub fn read(path: &Path) -> Result<Vec<Mesh>> {
+    Ok(read_volumes(path)?.into_iter().map(Mesh::merge).collect())
+}
+
+/// Reads the volumes of each object on a 3MF's build plate, eg. the parts of a multi-material
+/// model. Volumes are read from the object's components and from PrusaSlicer's volume metadata.
+pub fn read_volumes(path: &Path) -> Result<Vec<Vec<Mesh>>> {
+    let mut archive = ZipArchive::new(std::fs::File::open(path)?).wrap_err("Invalid 3MF file")?;
+
+    let model_path = model_path(&mut archive)?;
+
+    let mut model = String::new();
+    archive
+        .by_name(&model_path)
+        .wrap_err_with(|| format!("3MF model not found: {model_path}"))?
+        .read_to_string(&mut model)?;
+
+    let mut model = Model::parse(&model)?;
+
+    if let Ok(mut file) = archive.by_name(PRUSA_SLICER_CONFIG_PATH) {
+        let mut config = String::new();
+        file.read_to_string(&mut config)?;
+
+        model.volume_ranges = parse_volume_ranges(&config)?;
+    }
+
+    // Files without build items are treated as containing a single instance of every object
+    let items = if model.items.is_empty() {
+        let mut ids = model.objects.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        ids.into_iter()
+            .map(|id| (id, Matrix4::identity()))
+            .collect()
+    } else {
+        model.items.clone()
+    };
+
+    items
+        .iter()
+        .map(|(object_id, transform)| {
+            let mut volumes = model.object_volumes(object_id)?;
+            for volume in volumes.iter_mut() {
+                volume.transform(transform);
+            }
+            Ok(volumes)
+        })
+        .collect()
+}
+
+/// Reads the triangle range of each volume of each object from PrusaSlicer's model config
+fn parse_volume_ranges(config: &str) -> Result<HashMap<String, Vec<(usize, usize)>>> {
+    let object_re = Regex::new(r"(?s)<object\b([^>]*)>(.*?)</object>")?;
+    let volume_re = Regex::new(r"<volume\b([^>]*?)/?>")?;
+
+    let mut volume_ranges = HashMap::new();
+
+    for captures in object_re.captures_iter(config) {
+        let id = match attributes(&captures[1]).remove("id") {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let ranges = volume_re
+            .captures_iter(&captures[2])
+            .filter_map(|volume| {
+                let attrs = attributes(&volume[1]);
+                let first = attrs.get("firstid")?.parse().ok()?;
+                let last = attrs.get("lastid")?.parse().ok()?;
+
+                Some((first, last))
+            })
+            .collect::<Vec<_>>();
+
+        volume_ranges.insert(id, ranges);
+    }
+
+    Ok(volume_ranges)
+}
+
+/// Finds the model part via the package relationships, falling back to the conventional path
+fn model_path(archive: &mut ZipArchive<std::fs::File>) -> Result<String> {
+    let mut rels = String::new();
+
+    if let Ok(mut file) = archive.by_name("_rels/.rels") {
+        file.read_to_string(&mut rels)?;
+    }
+
+    let target = Regex::new(r#"Target="/?([^"]+\.model)""#)?
+        .captures(&rels)
+        .and_then(|captures| captures.get(1))
+        .map(|target| target.as_str().to_owned());
+
+    Ok(target.unwrap_or_else(|| DEFAULT_MODEL_PATH.to_owned()))
+}
+
+struct Object {
+    mesh: Mesh,
+    components: Vec<(String, Matrix4<f32>)>,
+}
+
+struct Model {
+    objects: HashMap<String, Object>,
+    items: Vec<(String, Matrix4<f32>)>,
+    /// The first and last triangle of each volume of an object's mesh, keyed by object id
+    volume_ranges: HashMap<String, Vec<(usize, usize)>>,
+}
+
+impl Model {
+    fn parse(xml: &str) -> Result<Self> {
+        let object_re = Regex::new(r"(?s)<object\b([^>]*)>(.*?)</object>")?;
+        let vertex_re = Regex::new(r"<vertex\b([^>]*?)/?>")?;
+        let triangle_re = Regex::new(r"<triangle\b([^>]*?)/?>")?;
+        let component_re = Regex::new(r"<component\b([^>]*?)/?>")?;
+        let item_re = Regex::new(r"<item\b([^>]*?)/?>")?;
+
+        let mut objects = HashMap::new();
+
+        for captures in object_re.captures_iter(xml) {
+            let attrs = attributes(&captures[1]);
+            let body = &captures[2];
+
+            let id = attrs
+                .get("id")
+                .ok_or_else(|| eyre!("3MF object is missing an id"))?
+                .clone();
+
+            let mut mesh = Mesh::default();
+
+            for vertex in vertex_re.captures_iter(body) {
+                let attrs = attributes(&vertex[1]);
+                let coord = |name: &str| -> Result<f32> {
+                    attrs
+                        .get(name)
+                        .and_then(|value| value.parse().ok())
+                        .ok_or_else(|| eyre!("Invalid 3MF vertex in object {id}"))
+                };
+
+                mesh.vertices
+                    .push(Point3::new(coord("x")?, coord("y")?, coord("z")?));
+            }
+
+            for triangle in triangle_re.captures_iter(body) {
+                let attrs = attributes(&triangle[1]);
+                let index = |name: &str| -> Result<usize> {
+                    attrs
+                        .get(name)
+                        .and_then(|value| value.parse().ok())
+                        .filter(|index| *index < mesh.vertices.len())
+                        .ok_or_else(|| eyre!("Invalid 3MF triangle in object {id}"))
+                };
+
+                let triangle = [index("v1")?, index("v2")?, index("v3")?];
+                mesh.triangles.push(triangle);
+            }
+
+            let components = component_re
+                .captures_iter(body)
+                .map(|component| {
+                    let attrs = attributes(&component[1]);
+                    let object_id = attrs
+                        .get("objectid")
+                        .ok_or_else(|| eyre!("3MF component is missing an objectid"))?;
+
+                    Ok((object_id.clone(), parse_transform(attrs.get("transform"))?))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            objects.insert(id, Object { mesh, components });
+        }
+
+        let items = item_re
+            .captures_iter(xml)
+            .map(|item| {
+                let attrs = attributes(&item[1]);
+                let object_id = attrs
+                    .get("objectid")
+                    .ok_or_else(|| eyre!("3MF build item is missing an objectid"))?;
+
+                Ok((object_id.clone(), parse_transform(attrs.get("transform"))?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            objects,
+            items,
+            volume_ranges: HashMap::new(),
+        })
+    }
+
+    /// The object's volumes. Each component is a volume, as is each of PrusaSlicer's volumes
+    /// within the object's own mesh.
+    fn object_volumes(&self, id: &str) -> Result<Vec<Mesh>> {
+        let object = self
+            .objects
+            .get(id)
+            .ok_or_else(|| eyre!("3MF object not found: {id}"))?;
+
+        let mut volumes = match self.volume_ranges.get(id) {
+            Some(ranges) if !ranges.is_empty() => ranges
+                .iter()
+                .map(|(first, last)| submesh(&object.mesh, *first, *last))
+                .collect(),
+            _ => vec![object.mesh.clone()],
+        };
+
+        for (component_id, transform) in &object.components {
+            let mut mesh = self.object_mesh(component_id, 1)?;
+            mesh.transform(transform);
+            volumes.push(mesh);
+        }
+
+        volumes.retain(|volume| !volume.triangles.is_empty());
+
+        Ok(volumes)
+    }
+
+    /// The object's mesh combined with the meshes of it's components
+    fn object_mesh(&self, id: &str, depth: usize) -> Result<Mesh> {
+        if depth > MAX_COMPONENT_DEPTH {
+            return Err(eyre!("3MF components are nested too deeply"));
+        }
+
+        let object = self
+            .objects
+            .get(id)
+            .ok_or_else(|| eyre!("3MF object not found: {id}"))?;
+
+        let components = object
+            .components
+            .iter()
+            .map(|(component_id, transform)| {
+                let mut mesh = self.object_mesh(component_id, depth + 1)?;
+                mesh.transform(transform);
+                Ok(mesh)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Mesh::merge(
+            std::iter::once(object.mesh.clone()).chain(components),
+        ))
+    }
+}
+
+/// The triangles from first to last (inclusive) along with the vertices they use
+fn submesh(mesh: &Mesh, first: usize, last: usize) -> Mesh {
+    let mut submesh = Mesh::default();
+    let mut vertex_indices = HashMap::new();
+
+    for triangle in mesh.triangles.iter().take(last + 1).skip(first) {
+        let triangle = triangle.map(|index| {
+            *vertex_indices.entry(index).or_insert_with(|| {
+                submesh.vertices.push(mesh.vertices[index]);
+                submesh.vertices.len() - 1
+            })
+        });
+
+        submesh.triangles.push(triangle);
+    }
+
+    submesh
+}
+
+fn attributes(xml: &str) -> HashMap<String, String> {
+    let attribute_re = Regex::new(r#"([\w:]+)\s*=\s*"([^"]*)""#).expect("Invalid attribute regex");
+
+    attribute_re
+        .captures_iter(xml)
+        .map(|captures| (captures[1].to_owned(), captures[2].to_owned()))
+        .collect()
+}
+
+/// Parses a 3MF transform attribute, a 4x3 row-major matrix applied to row vectors, eg.
+/// "1 0 0 0 1 0 0 0 1 10 20 0"
+fn parse_transform(transform: Option<&String>) -> Result<Matrix4<f32>> {
+    let transform = match transform {
+        Some(transform) => transform,
+        None => return Ok(Matrix4::identity()),
+    };
+
+    let m = transform
+        .split_whitespace()
+        .map(|value| value.parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|m| m.len() == 12)
+        .ok_or_else(|| eyre!("Invalid 3MF transform: {:?}", transform))?;
+
+    #[rustfmt::skip]
+    let mat4 = Matrix4::new(
+        m[0], m[1], m[2], 0.0,
+        m[3], m[4], m[5], 0.0,
+        m[6], m[7], m[8], 0.0,
+        m[9], m[10], m[11], 1.0,
+    );
+
+    Ok(mat4)
+}
+
+/// An object to write to a 3MF scene
+pub struct SceneObject<'a> {
+    pub name: &'a str,
+    /// The object's parts. Most objects have a single volume but multi-material objects have a
+    /// volume for each material.
+    pub volumes: Vec<SceneVolume<'a>>,
+    /// Slicing settings for this object only, written as PrusaSlicer/SuperSlicer object metadata
+    pub settings: &'a [(String, String)],
+}
+
+pub struct SceneVolume<'a> {
+    pub mesh: &'a Mesh,
+    /// The extruder that prints the volume, starting at 1. Defaults to the object's extruder.
+    pub extruder: Option<u32>,
+}
+
+impl SceneObject<'_> {
+    /// The object's volumes combined into one mesh
+    fn mesh(&self) -> Mesh {
+        Mesh::merge(self.volumes.iter().map(|volume| volume.mesh.clone()))
+    }
+
+    /// The first and last triangle of each volume within the object's combined mesh
+    fn volume_ranges(&self) -> Vec<(usize, usize)> {
+        let mut first = 0;
+
+        self.volumes
+            .iter()
+            .map(|volume| {
+                let len = volume.mesh.triangles.len();
+                let range = (first, (first + len).saturating_sub(1));
+                first += len;
+                range
+            })
+            .collect()
+    }
+
+    /// True if the object needs PrusaSlicer's model config to be sliced as intended
+    fn has_config(&self) -> bool {
+        !self.settings.is_empty()
+            || self.volumes.len() > 1
+            || self.volumes.iter().any(|volume| volume.extruder.is_some())
+    }
+}
+
+/// Writes each mesh as a separate object on the build plate
+pub fn write(path: &Path, meshes: &[Mesh]) -> Result<()> {
+    let names = (1..=meshes.len())
+        .map(|i| format!("object_{i}"))
+        .collect::<Vec<_>>();
+
+    let objects = meshes
+        .iter()
+        .zip(&names)
+        .map(|(mesh, name)| SceneObject {
+            name,
+            volumes: vec![SceneVolume {
+                mesh,
+                extruder: None,
+            }],
+            settings: &[],
+        })
+        .collect::<Vec<_>>();
+
+    write_scene(path, &objects)
+}
+
+/// Writes each object on the build plate in it's current position
+pub fn write_scene(path: &Path, objects: &[SceneObject]) -> Result<()> {
+    let mut model = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<model unit="millimeter" xml:lang="en-US" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">
+  <resources>
+"#,
+    );
+
+    for (i, object) in objects.iter().enumerate() {
+        let mesh = object.mesh();
+
+        writeln!(
+            model,
+            r#"    <object id="{}" name="{}" type="model">"#,
+            i + 1,
+            escape_xml(object.name),
+        )?;
+        model.push_str("      <mesh>\n        <vertices>\n");
+        for vertex in &mesh.vertices {
+            writeln!(
+                model,
+                r#"          <vertex x="{}" y="{}" z="{}"/>"#,
+                vertex.x, vertex.y, vertex.z,
+            )?;
+        }
+        model.push_str("        </vertices>\n        <triangles>\n");
+        for [a, b, c] in &mesh.triangles {
+            writeln!(model, r#"          <triangle v1="{a}" v2="{b}" v3="{c}"/>"#)?;
+        }
+        model.push_str("        </triangles>\n      </mesh>\n    </object>\n");
+    }
+
+    model.push_str("  </resources>\n  <build>\n");
+    for i in 0..objects.len() {
+        writeln!(model, r#"    <item objectid="{}"/>"#, i + 1)?;
+    }
+    model.push_str("  </build>\n</model>\n");
+
+    let mut zip = ZipWriter::new(std::fs::File::create(path)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("[Content_Types].xml", options)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+  <Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+  <Default Extension="model" ContentType="application/vnd.ms-package.3dmanufacturing-3dmodel+xml"/>
+</Types>
+"#,
+    )?;
+
+    zip.start_file("_rels/.rels", options)?;
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Target="/3D/3dmodel.model" Id="rel0" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>
+"#,
+    )?;
+
+    zip.start_file(DEFAULT_MODEL_PATH, options)?;
+    zip.write_all(model.as_bytes())?;
+
+    if objects.iter().any(SceneObject::has_config) {
+        zip.start_file(PRUSA_SLICER_CONFIG_PATH, options)?;
+        zip.write_all(prusa_slicer_config(objects)?.as_bytes())?;
+    }
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// PrusaSlicer and SuperSlicer read per-object settings and volumes from their model config
+fn prusa_slicer_config(objects: &[SceneObject]) -> Result<String> {
+    let mut config = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<config>\n");
+
+    for (i, object) in objects.iter().enumerate() {
+        let name = escape_xml(object.name);
+
+        writeln!(config, r#" <object id="{}" instances_count="1">"#, i + 1)?;
+        writeln!(
+            config,
+            r#"  <metadata type="object" key="name" value="{name}"/>"#
+        )?;
+        for (key, value) in object.settings {
+            writeln!(
+                config,
+                r#"  <metadata type="object" key="{}" value="{}"/>"#,
+                escape_xml(key),
+                escape_xml(value),
+            )?;
+        }
+
+        let ranges = object.volume_ranges();
+
+        for (j, (volume, (first, last))) in object.volumes.iter().zip(ranges).enumerate() {
+            let volume_name = if object.volumes.len() > 1 {
+                format!("{name} - Part {}", j + 1)
+            } else {
+                name.clone()
+            };
+
+            writeln!(config, r#"  <volume firstid="{first}" lastid="{last}">"#)?;
+            writeln!(
+                config,
+                r#"   <metadata type="volume" key="name" value="{volume_name}"/>"#
+            )?;
+            if let Some(extruder) = volume.extruder {
+                writeln!(
+                    config,
+                    r#"   <metadata type="volume" key="extruder" value="{extruder}"/>"#
+                )?;
+            }
+            config.push_str("  </volume>\n");
+        }
+        config.push_str(" </object>\n");
+    }
+
+    config.push_str("</config>\n");
+
+    Ok(config)
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
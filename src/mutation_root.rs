@@ -1,6 +1,14 @@
 use async_graphql::MergedObject;
 
+use crate::job::cancel_job_mutation::CancelJobMutation;
 use crate::job::create_job_mutation::CreateJobMutation;
+use crate::job::queue_mutation::QueueMutation;
+use crate::profile::profile_mutation::ProfileMutation;
 
 #[derive(MergedObject, Default)]
-pub struct Mutation(CreateJobMutation);
+pub struct Mutation(
+    CreateJobMutation,
+    CancelJobMutation,
+    QueueMutation,
+    ProfileMutation,
+);
@@ -0,0 +1,309 @@
+use async_graphql::{Context, FieldResult, ID};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use eyre::{eyre, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tracing::warn;
+
+use crate::auth::{Scope, ScopeGuard};
+use crate::config::directories;
+use crate::engine::Engines;
+use conversion::{ConvertProfileInput, ConvertedProfile};
+use validation::{ProfileValidation, ValidateProfileInput};
+
+pub mod conversion;
+pub mod profile_mutation;
+pub mod settings;
+pub mod validation;
+
+const PROFILE_METADATA_FILE: &'static str = "profile.toml";
+
+/// A slicing profile (eg. a PrusaSlicer INI) stored on the server for reuse across jobs
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    /// The id of the engine that the profile was created for
+    pub engine_id: String,
+    /// The engine version that the profile was validated against, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine_version: Option<String>,
+    /// The original file name of the profile. Some engines infer the profile format from it.
+    pub filename: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub type Profiles = Arc<ProfileStore>;
+
+/// The server's slicing profile library. Each profile is stored in it's own directory containing
+/// it's metadata and the profile file itself.
+pub struct ProfileStore {
+    dir: PathBuf,
+    profiles: DashMap<String, Profile>,
+}
+
+impl ProfileStore {
+    pub fn default_dir() -> Result<PathBuf> {
+        Ok(directories()?.data_dir().join("profiles"))
+    }
+
+    pub async fn open(dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)
+            .await
+            .wrap_err("Unable to create profiles directory")?;
+
+        let profiles = DashMap::new();
+        let mut entries = fs::read_dir(&dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata_path = entry.path().join(PROFILE_METADATA_FILE);
+
+            if !metadata_path.exists() {
+                continue;
+            }
+
+            match fs::read_to_string(&metadata_path)
+                .await
+                .map_err(eyre::Error::from)
+                .and_then(|toml| Ok(toml::from_str::<Profile>(&toml)?))
+            {
+                Ok(profile) => {
+                    profiles.insert(profile.id.clone(), profile);
+                }
+                Err(err) => warn!("Skipping invalid profile {:?}: {:?}", metadata_path, err),
+            }
+        }
+
+        Ok(Self { dir, profiles })
+    }
+
+    pub fn get(&self, id: &str) -> Option<Profile> {
+        self.profiles.get(id).map(|profile| profile.value().clone())
+    }
+
+    /// All profiles (optionally only those for a specific engine), oldest first
+    pub fn list(&self, engine_id: Option<&str>) -> Vec<Profile> {
+        let mut profiles = self
+            .profiles
+            .iter()
+            .map(|profile| profile.value().clone())
+            .filter(|profile| engine_id.map_or(true, |engine_id| profile.engine_id == engine_id))
+            .collect::<Vec<_>>();
+
+        profiles.sort_by_key(|profile| profile.created_at);
+        profiles
+    }
+
+    pub async fn create(
+        &self,
+        name: String,
+        engine_id: String,
+        engine_version: Option<String>,
+        filename: &str,
+        content: &[u8],
+    ) -> Result<Profile> {
+        // Only keep the file name in case the client sent a path
+        let filename = Path::new(filename)
+            .file_name()
+            .ok_or_else(|| eyre!("Invalid profile file name: {:?}", filename))?
+            .to_string_lossy()
+            .to_string();
+
+        // The profile file is stored alongside it's metadata so it cannot take the metadata's name
+        if filename == PROFILE_METADATA_FILE {
+            return Err(eyre!(
+                "Profile files cannot be named {}, please rename the file",
+                PROFILE_METADATA_FILE,
+            ));
+        }
+
+        let profile = Profile {
+            id: nanoid::nanoid!(),
+            name,
+            engine_id,
+            engine_version,
+            filename,
+            created_at: Utc::now(),
+        };
+
+        let profile_dir = self.dir.join(&profile.id);
+        fs::create_dir_all(&profile_dir).await?;
+
+        fs::write(self.file_path(&profile), content).await?;
+        fs::write(
+            profile_dir.join(PROFILE_METADATA_FILE),
+            toml::to_string_pretty(&profile)?,
+        )
+        .await?;
+
+        self.profiles.insert(profile.id.clone(), profile.clone());
+
+        Ok(profile)
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<()> {
+        let (_, profile) = self
+            .profiles
+            .remove(id)
+            .ok_or_else(|| eyre!("Profile not found"))?;
+
+        fs::remove_dir_all(self.dir.join(&profile.id)).await?;
+
+        Ok(())
+    }
+
+    /// The path of the profile file itself
+    pub fn file_path(&self, profile: &Profile) -> PathBuf {
+        self.dir.join(&profile.id).join(&profile.filename)
+    }
+
+    /// Copies the profile file into a job's directory, returning the path of the copy
+    pub async fn copy_to_dir(&self, profile: &Profile, dir: &Path) -> Result<PathBuf> {
+        let path = dir.join(&profile.filename);
+
+        fs::copy(self.file_path(profile), &path)
+            .await
+            .wrap_err("Unable to copy profile")?;
+
+        Ok(path)
+    }
+}
+
+#[derive(Default)]
+pub struct ProfileQuery;
+
+#[async_graphql::Object]
+impl ProfileQuery {
+    /// Lists the stored slicing profiles, optionally filtered to a single engine
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs, Scope::ManageProfiles])")]
+    async fn profiles<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        engine_id: Option<ID>,
+    ) -> FieldResult<Vec<Profile>> {
+        let profiles: &Profiles = ctx.data()?;
+
+        Ok(profiles.list(engine_id.as_deref().map(String::as_str)))
+    }
+
+    /// Checks a slicing profile against an engine without creating a job
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs, Scope::ManageProfiles])")]
+    async fn validate_profile<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        input: ValidateProfileInput,
+    ) -> FieldResult<ProfileValidation> {
+        let engines: &Engines = ctx.data()?;
+
+        let engine = engines
+            .get(&input.engine_id)
+            .ok_or_else(|| eyre!("Engine not found: {}", input.engine_id.0))?;
+
+        let validation = match validation::validate_profile(engine.as_ref(), &input.content) {
+            Ok(settings) => ProfileValidation {
+                valid: true,
+                errors: vec![],
+                normalized: Some(settings.to_string()),
+            },
+            Err(errors) => ProfileValidation {
+                valid: false,
+                errors,
+                normalized: None,
+            },
+        };
+
+        Ok(validation)
+    }
+
+    /// Converts a profile between engines, eg. a PrusaSlicer profile for use with SuperSlicer
+    #[graphql(guard = "ScopeGuard(&[Scope::SubmitJobs, Scope::ManageProfiles])")]
+    async fn convert_profile<'ctx>(
+        &self,
+        ctx: &'ctx Context<'_>,
+        input: ConvertProfileInput,
+    ) -> FieldResult<ConvertedProfile> {
+        let engines: &Engines = ctx.data()?;
+
+        let engine = engines
+            .get(&input.to_engine_id)
+            .ok_or_else(|| eyre!("Engine not found: {}", input.to_engine_id.0))?;
+
+        let (settings, conversion) =
+            conversion::convert_profile(engine.as_ref(), &input.from_engine_id, &input.content)?;
+
+        Ok(ConvertedProfile {
+            content: settings.to_string(),
+            conversion,
+        })
+    }
+}
+
+#[async_graphql::Object]
+impl Profile {
+    async fn id(&self) -> ID {
+        self.id.as_str().into()
+    }
+    async fn name(&self) -> &str {
+        &self.name
+    }
+    async fn engine_id(&self) -> ID {
+        self.engine_id.as_str().into()
+    }
+    async fn engine_version(&self) -> Option<&str> {
+        self.engine_version.as_deref()
+    }
+    async fn filename(&self) -> &str {
+        &self.filename
+    }
+    async fn created_at(&self) -> String {
+        self.created_at.to_rfc3339()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn profile_files_cannot_replace_the_metadata() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = ProfileStore::open(dir.path().to_owned()).await?;
+
+        for filename in ["profile.toml", "configs/profile.toml"] {
+            let result = store
+                .create(
+                    "Test Profile".to_owned(),
+                    "test_engine".to_owned(),
+                    None,
+                    filename,
+                    b"layer_height = 0.2\n",
+                )
+                .await;
+            assert!(result.is_err());
+        }
+        assert!(store.list(None).is_empty());
+
+        let profile = store
+            .create(
+                "Test Profile".to_owned(),
+                "test_engine".to_owned(),
+                None,
+                "profile.ini",
+                b"layer_height = 0.2\n",
+            )
+            .await?;
+
+        // Profiles are still readable after the server restarts
+        let store = ProfileStore::open(dir.path().to_owned()).await?;
+        let stored = store.get(&profile.id).expect("Profile not found");
+        assert_eq!(
+            fs::read_to_string(store.file_path(&stored)).await?,
+            "layer_height = 0.2\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use async_graphql::ID;
+use eyre::{eyre, Result};
+
+use super::settings::ProfileSettings;
+use crate::engine::SlicingEngine;
+
+/// A report of the settings that could not be carried over exactly when converting a profile
+/// between engines
+#[derive(Default, Debug, async_graphql::SimpleObject)]
+pub struct ProfileConversion {
+    /// Settings which the target engine has no equivalent for
+    pub dropped_keys: Vec<String>,
+    /// Settings which were converted to the closest equivalent in the target engine
+    pub approximated: Vec<ApproximatedSetting>,
+}
+
+#[derive(Debug, async_graphql::SimpleObject)]
+pub struct ApproximatedSetting {
+    /// The setting's key in the target engine
+    pub key: String,
+    /// How the setting differs from the original
+    pub note: String,
+}
+
+/// Converts a profile created for another engine into the format of `engine`
+pub fn convert_profile(
+    engine: &dyn SlicingEngine,
+    from_engine_id: &str,
+    content: &str,
+) -> Result<(ProfileSettings, ProfileConversion)> {
+    let mut settings = ProfileSettings::parse(engine.profile_format(), content)?;
+
+    let conversion = engine
+        .convert_profile(from_engine_id, &mut settings)
+        .ok_or_else(|| {
+            eyre!(
+                "Profiles for {} cannot be converted for {}",
+                from_engine_id,
+                engine.metadata().id.0,
+            )
+        })?;
+
+    Ok((settings, conversion))
+}
+
+#[derive(async_graphql::InputObject)]
+pub struct ConvertProfileInput {
+    /// The content of the profile file
+    pub content: String,
+    /// The engine that the profile was created for
+    pub from_engine_id: ID,
+    /// The engine to convert the profile for
+    pub to_engine_id: ID,
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct ConvertedProfile {
+    /// The converted profile
+    pub content: String,
+    pub conversion: ProfileConversion,
+}
@@ -0,0 +1,78 @@
+use super::{Profile, Profiles};
+use crate::auth::{Scope, ScopeGuard};
+use crate::engine::Engines;
+use async_graphql::{FieldResult, ID};
+use eyre::eyre;
+use std::io::Read;
+use tracing::instrument;
+
+#[derive(Default)]
+pub struct ProfileMutation;
+
+#[derive(async_graphql::InputObject)]
+struct CreateProfileInput {
+    name: String,
+    /// The engine that the profile is for
+    engine_id: ID,
+    /// The engine version that the profile was validated against, eg. "version_2.5.0"
+    engine_version: Option<String>,
+    /// The profile file. It cannot be named profile.toml, which is reserved for it's metadata.
+    file: async_graphql::Upload,
+}
+
+#[derive(async_graphql::InputObject)]
+struct DeleteProfileInput {
+    id: ID,
+}
+
+#[async_graphql::Object]
+impl ProfileMutation {
+    /// Stores a slicing profile on the server so that jobs can reference it by id
+    #[instrument(skip(self, input, ctx))]
+    #[graphql(guard = "ScopeGuard(&[Scope::ManageProfiles])")]
+    async fn create_profile<'ctx>(
+        &self,
+        ctx: &'ctx async_graphql::Context<'_>,
+        input: CreateProfileInput,
+    ) -> FieldResult<Profile> {
+        let profiles: &Profiles = ctx.data()?;
+        let engines: &Engines = ctx.data()?;
+
+        if engines.get(&input.engine_id).is_none() {
+            return Err(eyre!("Engine not found: {}", input.engine_id.0).into());
+        }
+
+        let file = input.file.value(&ctx)?;
+        let filename = file.filename.clone();
+
+        let mut content = vec![];
+        file.into_read().read_to_end(&mut content)?;
+
+        let profile = profiles
+            .create(
+                input.name,
+                input.engine_id.0,
+                input.engine_version,
+                &filename,
+                &content,
+            )
+            .await?;
+
+        Ok(profile)
+    }
+
+    /// Deletes a stored slicing profile, returning it's id
+    #[instrument(skip(self, input, ctx))]
+    #[graphql(guard = "ScopeGuard(&[Scope::ManageProfiles])")]
+    async fn delete_profile<'ctx>(
+        &self,
+        ctx: &'ctx async_graphql::Context<'_>,
+        input: DeleteProfileInput,
+    ) -> FieldResult<ID> {
+        let profiles: &Profiles = ctx.data()?;
+
+        profiles.delete(&input.id).await?;
+
+        Ok(input.id)
+    }
+}
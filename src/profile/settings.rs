@@ -0,0 +1,420 @@
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// The file format of an engine's slicing profiles
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileFormat {
+    /// `key = value` lines with optional `[section]` headers, eg. Slic3r INIs and BeltEngine's
+    /// .cfg.ini
+    #[default]
+    Ini,
+    /// A JSON object of settings
+    Json,
+}
+
+/// A parsed slicing profile which can be modified while preserving it's original layout
+pub enum ProfileSettings {
+    Ini(Vec<IniLine>),
+    Json(serde_json::Map<String, serde_json::Value>),
+}
+
+pub enum IniLine {
+    Entry {
+        /// The section the entry belongs to (if any) and the entry's key, eg. "layer_height" or
+        /// "print.layer_height"
+        key: String,
+        raw_key: String,
+        value: String,
+    },
+    /// Section headers, comments and blank lines
+    Other(String),
+}
+
+impl IniLine {
+    /// The name of the section if the line is a section header
+    fn section(&self) -> Option<&str> {
+        match self {
+            IniLine::Other(line) => {
+                let trimmed = line.trim();
+                if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                    Some(trimmed[1..trimmed.len() - 1].trim())
+                } else {
+                    None
+                }
+            }
+            IniLine::Entry { .. } => None,
+        }
+    }
+}
+
+impl ProfileSettings {
+    pub fn parse(format: ProfileFormat, content: &str) -> Result<Self> {
+        let settings = match format {
+            ProfileFormat::Ini => {
+                let mut section: Option<String> = None;
+
+                let lines = content
+                    .lines()
+                    .map(|line| {
+                        let trimmed = line.trim();
+
+                        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                            section = Some(trimmed[1..trimmed.len() - 1].trim().to_owned());
+                            return IniLine::Other(line.to_owned());
+                        }
+
+                        if trimmed.starts_with('#') || trimmed.starts_with(';') {
+                            return IniLine::Other(line.to_owned());
+                        }
+
+                        match trimmed.split_once('=') {
+                            Some((raw_key, value)) => {
+                                let raw_key = raw_key.trim().to_owned();
+                                let key = match &section {
+                                    Some(section) => format!("{section}.{raw_key}"),
+                                    None => raw_key.clone(),
+                                };
+
+                                IniLine::Entry {
+                                    key,
+                                    raw_key,
+                                    value: value.trim().to_owned(),
+                                }
+                            }
+                            None => IniLine::Other(line.to_owned()),
+                        }
+                    })
+                    .collect();
+
+                ProfileSettings::Ini(lines)
+            }
+            ProfileFormat::Json => match serde_json::from_str(content)? {
+                serde_json::Value::Object(map) => ProfileSettings::Json(map),
+                _ => return Err(eyre!("JSON profiles must be an object")),
+            },
+        };
+
+        Ok(settings)
+    }
+
+    /// Returns the value of a setting, formatted as a string
+    pub fn get(&self, key: &str) -> Option<String> {
+        match self {
+            ProfileSettings::Ini(lines) => lines.iter().find_map(|line| match line {
+                IniLine::Entry {
+                    key: entry_key,
+                    value,
+                    ..
+                } if entry_key == key => Some(value.clone()),
+                _ => None,
+            }),
+            ProfileSettings::Json(map) => map.get(key).map(|value| match value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            }),
+        }
+    }
+
+    /// The keys of every setting in the profile
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            ProfileSettings::Ini(lines) => lines
+                .iter()
+                .filter_map(|line| match line {
+                    IniLine::Entry { key, .. } => Some(key.clone()),
+                    IniLine::Other(_) => None,
+                })
+                .collect(),
+            ProfileSettings::Json(map) => map.keys().cloned().collect(),
+        }
+    }
+
+    /// Replaces the value of an existing setting. Returns false if the setting does not exist.
+    pub fn set(&mut self, key: &str, new_value: &str) -> bool {
+        match self {
+            ProfileSettings::Ini(lines) => {
+                let mut found = false;
+
+                for line in lines.iter_mut() {
+                    if let IniLine::Entry {
+                        key: entry_key,
+                        value,
+                        ..
+                    } = line
+                    {
+                        if entry_key == key {
+                            *value = new_value.to_owned();
+                            found = true;
+                        }
+                    }
+                }
+
+                found
+            }
+            ProfileSettings::Json(map) => match map.get_mut(key) {
+                Some(value) => {
+                    // Keep the setting's existing type where possible
+                    *value = match value {
+                        serde_json::Value::String(_) => new_value.to_owned().into(),
+                        _ => serde_json::from_str(new_value)
+                            .unwrap_or_else(|_| new_value.to_owned().into()),
+                    };
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Replaces one extruder's value of a per-extruder setting, eg. the second value of
+    /// "temperature = 210,215". Lists shorter than the index are padded with their last value so
+    /// that a single value shared by every extruder can be split. Returns false if the setting
+    /// does not exist.
+    pub fn set_list_item(&mut self, key: &str, index: usize, new_value: &str) -> bool {
+        if let ProfileSettings::Json(map) = self {
+            return match map.get_mut(key) {
+                Some(serde_json::Value::Array(items)) => {
+                    let last = items.last().cloned().unwrap_or_default();
+                    if items.len() <= index {
+                        items.resize(index + 1, last);
+                    }
+
+                    items[index] = match items[index] {
+                        serde_json::Value::String(_) => new_value.to_owned().into(),
+                        _ => serde_json::from_str(new_value)
+                            .unwrap_or_else(|_| new_value.to_owned().into()),
+                    };
+                    true
+                }
+                Some(_) if index == 0 => self.set(key, new_value),
+                Some(_) => false,
+                None => false,
+            };
+        }
+
+        let value = match self.get(key) {
+            Some(value) => value,
+            None => return false,
+        };
+
+        // Slic3r separates numeric lists with commas and text lists, eg. filament_type, with
+        // semicolons
+        let is_numeric = |value: &str| value.trim().parse::<f64>().is_ok();
+        let separator = if value.contains(';') {
+            ";"
+        } else if value.contains(',') || (is_numeric(&value) && is_numeric(new_value)) {
+            ","
+        } else {
+            ";"
+        };
+
+        let mut items = value
+            .split(separator)
+            .map(|item| item.trim().to_owned())
+            .collect::<Vec<_>>();
+
+        let last = items.last().cloned().unwrap_or_default();
+        if items.len() <= index {
+            items.resize(index + 1, last);
+        }
+        items[index] = new_value.to_owned();
+
+        self.set(key, &items.join(separator))
+    }
+
+    /// Renames a setting, keeping it's position and section. Returns false without changing the
+    /// profile if the setting does not exist or if the new key is already in use.
+    pub fn rename(&mut self, key: &str, new_key: &str) -> bool {
+        match self {
+            ProfileSettings::Ini(lines) => {
+                let new_entry_key = lines.iter().find_map(|line| match line {
+                    IniLine::Entry {
+                        key: entry_key,
+                        raw_key,
+                        ..
+                    } if entry_key == key => {
+                        let section_prefix = &entry_key[..entry_key.len() - raw_key.len()];
+                        Some(format!("{section_prefix}{new_key}"))
+                    }
+                    _ => None,
+                });
+
+                let new_entry_key = match new_entry_key {
+                    Some(new_entry_key) => new_entry_key,
+                    None => return false,
+                };
+
+                let is_taken = lines.iter().any(|line| match line {
+                    IniLine::Entry { key: entry_key, .. } => *entry_key == new_entry_key,
+                    IniLine::Other(_) => false,
+                });
+                if is_taken {
+                    return false;
+                }
+
+                for line in lines.iter_mut() {
+                    if let IniLine::Entry {
+                        key: entry_key,
+                        raw_key,
+                        ..
+                    } = line
+                    {
+                        if entry_key == key {
+                            *entry_key = new_entry_key.clone();
+                            *raw_key = new_key.to_owned();
+                        }
+                    }
+                }
+
+                true
+            }
+            ProfileSettings::Json(map) => {
+                if map.contains_key(new_key) {
+                    return false;
+                }
+
+                match map.remove(key) {
+                    Some(value) => {
+                        map.insert(new_key.to_owned(), value);
+                        true
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+
+    /// Removes a setting. Returns false if the setting does not exist.
+    pub fn remove(&mut self, key: &str) -> bool {
+        match self {
+            ProfileSettings::Ini(lines) => {
+                let len = lines.len();
+                lines.retain(|line| {
+                    !matches!(line, IniLine::Entry { key: entry_key, .. } if entry_key == key)
+                });
+                lines.len() != len
+            }
+            ProfileSettings::Json(map) => map.remove(key).is_some(),
+        }
+    }
+
+    /// Sets a setting, adding it to the profile if it does not exist. New INI settings are added
+    /// to the end of their section, or before the first section if the key has no section.
+    pub fn set_or_insert(&mut self, key: &str, new_value: &str) {
+        if self.set(key, new_value) {
+            return;
+        }
+
+        match self {
+            ProfileSettings::Ini(lines) => {
+                let first_section = lines.iter().position(|line| line.section().is_some());
+
+                let (index, raw_key) = match (first_section, key.split_once('.')) {
+                    (Some(_), Some((section, raw_key))) => {
+                        let header = lines
+                            .iter()
+                            .position(|line| line.section() == Some(section));
+
+                        match header {
+                            Some(header) => {
+                                // The section ends at the next section header
+                                let end = lines[header + 1..]
+                                    .iter()
+                                    .position(|line| line.section().is_some())
+                                    .map_or(lines.len(), |offset| header + 1 + offset);
+
+                                (end, raw_key)
+                            }
+                            None => {
+                                lines.push(IniLine::Other(format!("[{section}]")));
+                                (lines.len(), raw_key)
+                            }
+                        }
+                    }
+                    (Some(first_section), None) => (first_section, key),
+                    (None, _) => (lines.len(), key),
+                };
+
+                lines.insert(
+                    index,
+                    IniLine::Entry {
+                        key: key.to_owned(),
+                        raw_key: raw_key.to_owned(),
+                        value: new_value.to_owned(),
+                    },
+                );
+            }
+            ProfileSettings::Json(map) => {
+                let value =
+                    serde_json::from_str(new_value).unwrap_or_else(|_| new_value.to_owned().into());
+                map.insert(key.to_owned(), value);
+            }
+        }
+    }
+
+    /// Returns an error listing any of the keys that the engine does not support. Keys that are
+    /// already in the profile are accepted as the profile was written for the engine. If the
+    /// engine does not list it's settings, only keys in the profile are accepted.
+    pub fn check_keys<'a>(
+        &self,
+        engine_keys: Option<&HashSet<&str>>,
+        keys: impl IntoIterator<Item = &'a str>,
+    ) -> Result<()> {
+        let existing_keys = self.keys();
+
+        let unknown_keys = keys
+            .into_iter()
+            .filter(|key| {
+                !engine_keys.map_or(false, |engine_keys| engine_keys.contains(key))
+                    && !existing_keys.iter().any(|existing_key| existing_key == key)
+            })
+            .collect::<Vec<_>>();
+
+        if !unknown_keys.is_empty() {
+            return Err(eyre!("Unknown settings: {}", unknown_keys.join(", ")));
+        }
+
+        Ok(())
+    }
+
+    /// Applies each override, rejecting all of them if any of their keys are unknown. Settings
+    /// that the engine supports but that are missing from the profile are added to it.
+    pub fn apply_overrides(
+        &mut self,
+        engine_keys: Option<&HashSet<&str>>,
+        overrides: &[(String, String)],
+    ) -> Result<()> {
+        self.check_keys(engine_keys, overrides.iter().map(|(key, _)| key.as_str()))?;
+
+        for (key, value) in overrides {
+            self.set_or_insert(key, value);
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ProfileSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileSettings::Ini(lines) => {
+                for line in lines {
+                    match line {
+                        IniLine::Entry { raw_key, value, .. } => {
+                            writeln!(f, "{raw_key} = {value}")?
+                        }
+                        IniLine::Other(line) => writeln!(f, "{line}")?,
+                    }
+                }
+                Ok(())
+            }
+            ProfileSettings::Json(map) => {
+                let json = serde_json::to_string_pretty(map).map_err(|_| fmt::Error)?;
+                write!(f, "{json}")
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use async_graphql::ID;
+use serde::Serialize;
+use std::{fmt, str::FromStr};
+
+use super::settings::ProfileSettings;
+use crate::engine::SlicingEngine;
+
+/// A problem with a slicing profile, either with a specific setting or with the profile as a whole
+#[derive(Clone, Debug, Serialize, async_graphql::SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileError {
+    /// The setting that is invalid, if the error is specific to one setting
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl ProfileError {
+    pub fn new(key: &str, message: impl Into<String>) -> Self {
+        Self {
+            key: Some(key.to_owned()),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{key}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Parses a profile for the engine and checks it, normalizing the settings where the engine
+/// accepts several equivalent forms.
+pub fn validate_profile(
+    engine: &dyn SlicingEngine,
+    content: &str,
+) -> Result<ProfileSettings, Vec<ProfileError>> {
+    let settings = ProfileSettings::parse(engine.profile_format(), content).map_err(|err| {
+        vec![ProfileError {
+            key: None,
+            message: format!("Unable to parse profile: {err}"),
+        }]
+    })?;
+
+    validate_settings(engine, settings)
+}
+
+/// Checks an already parsed profile for the engine
+pub fn validate_settings(
+    engine: &dyn SlicingEngine,
+    mut settings: ProfileSettings,
+) -> Result<ProfileSettings, Vec<ProfileError>> {
+    let errors = engine.validate_profile(&mut settings);
+
+    if errors.is_empty() {
+        Ok(settings)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Converts profile errors into a GraphQL error. Each error is also listed in the `fieldErrors`
+/// extension so that clients can highlight the invalid settings.
+pub fn into_graphql_error(errors: Vec<ProfileError>) -> async_graphql::Error {
+    let message = errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    async_graphql::Error::new(format!("Invalid profile: {message}")).extend_with(|_, ext| {
+        ext.set(
+            "fieldErrors",
+            async_graphql::to_value(&errors).unwrap_or_default(),
+        )
+    })
+}
+
+/// Returns a required setting, adding an error if it is missing
+pub fn require(
+    settings: &ProfileSettings,
+    key: &str,
+    errors: &mut Vec<ProfileError>,
+) -> Option<String> {
+    let value = settings.get(key);
+
+    if value.is_none() {
+        errors.push(ProfileError::new(key, "is required"));
+    }
+
+    value
+}
+
+/// Parses a comma separated list of values, eg. "0.4,0.4", adding an error if any are invalid
+pub fn parse_list<T: FromStr>(
+    key: &str,
+    value: &str,
+    errors: &mut Vec<ProfileError>,
+) -> Option<Vec<T>> {
+    let list = value
+        .split(',')
+        .map(|item| item.trim().parse::<T>())
+        .collect::<Result<Vec<_>, _>>();
+
+    match list {
+        Ok(list) if !list.is_empty() => Some(list),
+        _ => {
+            errors.push(ProfileError::new(key, format!("invalid value {value:?}")));
+            None
+        }
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct ProfileValidation {
+    pub valid: bool,
+    pub errors: Vec<ProfileError>,
+    /// The normalized profile, if it is valid
+    pub normalized: Option<String>,
+}
+
+#[derive(async_graphql::InputObject)]
+pub struct ValidateProfileInput {
+    /// The engine that the profile is intended for
+    pub engine_id: ID,
+    /// The content of the profile file
+    pub content: String,
+}
@@ -1,9 +1,17 @@
-use async_graphql::{Context, FieldResult, Object, ID};
+use async_graphql::{Context, FieldResult, MergedObject, Object, ID};
 
+use crate::auth::{Scope, ScopeGuard};
+use crate::engine::EnginesQuery;
+use crate::job::model_analysis::ModelQuery;
 use crate::job::{JobGraphQL, JobMap};
+use crate::profile::ProfileQuery;
 use eyre::eyre;
 
-pub struct QueryRoot;
+#[derive(MergedObject, Default)]
+pub struct QueryRoot(JobQuery, EnginesQuery, ProfileQuery, ModelQuery);
+
+#[derive(Default)]
+pub struct JobQuery;
 
 #[derive(async_graphql::InputObject)]
 struct JobInput {
@@ -11,10 +19,12 @@ struct JobInput {
 }
 
 #[Object]
-impl QueryRoot {
+impl JobQuery {
+    #[graphql(guard = "ScopeGuard(&[Scope::ReadOwnJobs, Scope::ReadAllJobs])")]
     async fn job<'a>(&self, ctx: &'a Context<'_>, input: JobInput) -> FieldResult<JobGraphQL> {
         let jobs: &JobMap = ctx.data()?;
         let job = jobs.get(&input.id).ok_or_else(|| eyre!("Job not found"))?;
+        job.check_readable(ctx)?;
 
         Ok(job.graphql())
     }
@@ -1,11 +1,8 @@
-use crate::execution_context::ExecutionContext;
 use eyre::eyre;
 use eyre::Result;
 use futures_util::Future;
-use futures_util::Stream;
 use std::path::PathBuf;
 use std::pin::Pin;
-use std::sync::Arc;
 
 mod github_release;
 mod github_release_config;
@@ -19,7 +16,7 @@ pub use github_release_config::GithubReleaseConfig;
 
 pub trait ReleaseConfig {
     fn parse<'a>(&self, url: &'a str) -> Result<Release>;
-    fn latest_release(&self) -> Pin<Box<dyn Future<Output = Result<Release>>>>;
+    fn latest_release(&self) -> Pin<Box<dyn Future<Output = Result<Release>> + Send>>;
 }
 
 pub enum Release {
@@ -35,39 +32,10 @@ impl Release {
         }
     }
 
-    pub fn generate_gcode(
-        self,
-        src_path: PathBuf,
-        config_path: PathBuf,
-        gcode_path: PathBuf,
-    ) -> impl Stream<Item = Result<f32>> {
-        genawaiter::sync::Gen::new(move |co| async move {
-            let co = Arc::new(co);
-            let result = match self {
-                Release::Github(release) => {
-                    (release.config.generate_gcode_inner)(ExecutionContext {
-                        release,
-                        co: Arc::clone(&co),
-                        src_path,
-                        config_path,
-                        gcode_path,
-                    })
-                    .await
-                }
-                Release::Local(release) => {
-                    (release.config.generate_gcode_inner)(ExecutionContext {
-                        release,
-                        co: Arc::clone(&co),
-                        src_path,
-                        config_path,
-                        gcode_path,
-                    })
-                    .await
-                }
-            };
-            if let Err(err) = result {
-                co.yield_(Err(err.into())).await;
-            }
-        })
+    pub fn bin_path_if_downloaded(&self) -> Result<PathBuf> {
+        match &self {
+            Release::Github(github_release) => github_release.bin_path_if_downloaded(),
+            Release::Local(local_release) => local_release.bin_path_if_downloaded(),
+        }
     }
 }
@@ -1,23 +1,21 @@
 use super::github_req_client::query_github_api;
 use super::Release;
 use super::ReleaseConfig;
-use crate::execution_context::ExecutionContext;
 use crate::release::GithubRelease;
 use eyre::eyre;
 use eyre::Result;
 use futures_util::Future;
 use futures_util::FutureExt;
 use std::pin::Pin;
+use std::sync::Arc;
 use tracing::warn;
 
-pub type AssetFilter = &'static (dyn Fn(&str) -> bool + Sync);
+pub type AssetFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
 
 #[derive(Clone)]
 pub struct GithubReleaseConfig {
     pub repo: String,
     pub asset_filter: AssetFilter,
-    pub generate_gcode_inner: &'static (dyn Fn(ExecutionContext<GithubRelease>) -> Pin<Box<dyn Future<Output = Result<()>>>>
-                  + Sync),
 }
 
 impl ReleaseConfig for GithubReleaseConfig {
@@ -30,7 +28,7 @@ impl ReleaseConfig for GithubReleaseConfig {
         Ok(Release::Github(release))
     }
 
-    fn latest_release(&self) -> Pin<Box<dyn Future<Output = Result<Release>>>> {
+    fn latest_release(&self) -> Pin<Box<dyn Future<Output = Result<Release>> + Send>> {
         let config = self.clone();
 
         async move {
@@ -1,4 +1,3 @@
-use crate::execution_context::ExecutionContext;
 use eyre::eyre;
 use eyre::Result;
 use futures_util::future;
@@ -14,8 +13,6 @@ use super::ReleaseConfig;
 pub struct LocalReleaseConfig {
     pub release_url: String,
     pub bin_path: PathBuf,
-    pub generate_gcode_inner: &'static (dyn Fn(ExecutionContext<LocalRelease>) -> Pin<Box<dyn Future<Output = Result<()>>>>
-                  + Sync),
 }
 
 pub struct LocalRelease {
@@ -33,7 +30,7 @@ impl ReleaseConfig for LocalReleaseConfig {
         }
     }
 
-    fn latest_release(&self) -> Pin<Box<dyn Future<Output = Result<Release>>>> {
+    fn latest_release(&self) -> Pin<Box<dyn Future<Output = Result<Release>> + Send>> {
         future::ok(Release::Local(LocalRelease {
             config: self.clone(),
         }))
@@ -46,7 +43,10 @@ impl LocalRelease {
         if self.config.bin_path.exists() {
             Ok(self.config.bin_path.clone())
         } else {
-            Err(eyre!("Belt engine is not installed"))
+            Err(eyre!(
+                "Engine binary not found at {}",
+                self.config.bin_path.display()
+            ))
         }
     }
 }
@@ -0,0 +1,22 @@
+//! The HTTP route table. Handlers and any URLs returned to clients are both derived from these
+//! paths so that they cannot drift apart.
+
+pub const GRAPHQL: &'static str = "/";
+pub const JOB_GCODE: &'static str = "/jobs/:job_id/gcode";
+pub const JOB_THUMBNAIL: &'static str = "/jobs/:job_id/thumbnail.png";
+pub const JOB_LAYERS: &'static str = "/jobs/:job_id/layers";
+
+/// The download path of a job's GCode
+pub fn job_gcode(job_id: &str) -> String {
+    JOB_GCODE.replace(":job_id", job_id)
+}
+
+/// The path of a job's PNG thumbnail
+pub fn job_thumbnail(job_id: &str) -> String {
+    JOB_THUMBNAIL.replace(":job_id", job_id)
+}
+
+/// The path of a job's per-layer toolpath summary
+pub fn job_layers(job_id: &str) -> String {
+    JOB_LAYERS.replace(":job_id", job_id)
+}
@@ -1,88 +1,238 @@
-use crate::auth::auth;
-use crate::config::{directories, Config};
-use crate::error::AppResult;
+use crate::auth::authorized_keys::AuthorizedKeys;
+use crate::auth::key_usage::{KeyUsage, KeyUsageRecorder};
+use crate::auth::revocation::{RevocationList, Revocations};
+use crate::auth::{auth, ClientKeyId, Scopes};
+use crate::config::{directories, Config, HttpsListenerConfig, ListenerConfig};
+use crate::engine::{EngineRegistry, Engines};
+use crate::job::gcode_download::get_job_gcode;
+use crate::job::preview::{get_job_layers, get_job_thumbnail};
+use crate::job::quota::{self, JobHistory};
 use crate::job::{self, JobMap, JobQueue};
 use crate::mutation_root::Mutation;
+use crate::profile::{ProfileStore, Profiles};
 use crate::query_root::QueryRoot;
+use crate::routes;
+use crate::url_signer::UrlSigner;
 use async_graphql::EmptySubscription;
 use async_graphql::{
     http::{playground_source, GraphQLPlaygroundConfig},
     Schema,
 };
 use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::body::Body;
+use axum::extract::FromRequest;
+use axum::http::Request;
 use axum::middleware;
-use axum::response::Html;
-use axum::{
-    extract::Extension, extract::Path, http::StatusCode, response::IntoResponse, routing::get,
-    Router,
-};
+use axum::response::{Html, Response};
+use axum::{extract::Extension, response::IntoResponse, routing::get, Router};
+use axum_server::tls_rustls::RustlsConfig;
 use dashmap::DashMap;
 use eyre::Result;
 use eyre::{eyre, Context};
+use futures_util::future::{try_join_all, LocalBoxFuture};
+use futures_util::FutureExt;
 use hyper::server::accept;
 use self_host_space::KeyManager;
 use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::{fs, sync::mpsc::unbounded_channel};
-use tracing::info;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
 
 pub struct SharedState {
     pub jobs: JobMap,
+    pub engines: Engines,
+    pub profiles: Profiles,
+    pub job_queue: JobQueue,
+    /// When each client key created it's recent jobs
+    pub job_history: JobHistory,
+    pub url_signer: UrlSigner,
+    pub config: Arc<Config>,
+    /// The client keys in config.toml, which are re-read while the server is running
+    pub authorized_keys: AuthorizedKeys,
+    /// Revoked keys and tokens, which are re-read while the server is running
+    pub revocations: RevocationList,
+    /// When each client key was last used
+    pub key_usage: KeyUsageRecorder,
 }
 
-type AppSchema = Schema<QueryRoot, Mutation, EmptySubscription>;
+pub type AppSchema = Schema<QueryRoot, Mutation, EmptySubscription>;
 
 pub async fn serve() -> Result<()> {
     info!("Starting Slicing Server");
 
     let dirs = directories()?;
     let config = Arc::new(Config::load().await?);
+    config.listeners.ensure_enabled()?;
 
     let jobs: JobMap = Arc::new(DashMap::new());
-    let (_job_queue_tx, job_queue_rx): (JobQueue, _) = unbounded_channel();
+    let engines: Engines = Arc::new(EngineRegistry::from_config(&config)?);
+    let profiles: Profiles = Arc::new(ProfileStore::open(ProfileStore::default_dir()?).await?);
 
     // Start the job queue
-    job::run_job_queue(jobs.clone(), job_queue_rx).await?;
+    let (job_queue, job_queue_task) =
+        job::spawn_job_queue(jobs.clone(), engines.clone(), Arc::clone(&config));
+
+    let shared_state = Arc::new(SharedState {
+        jobs,
+        engines,
+        profiles,
+        job_queue,
+        job_history: Arc::new(DashMap::new()),
+        url_signer: UrlSigner::new(),
+        config: Arc::clone(&config),
+        authorized_keys: AuthorizedKeys::new(Config::config_path()?, &config),
+        revocations: RevocationList::new(Revocations::default_path()?),
+        key_usage: KeyUsageRecorder::new(KeyUsage::default_path()?),
+    });
+
+    // Save the client key usage in the background while the server is running
+    let key_usage = shared_state.key_usage.clone();
+    let key_usage_task = key_usage.spawn_flush_task();
+
+    // build the http server routes. Every listener serves the same router.
+    let router = app(Arc::clone(&config), shared_state);
+
+    let ListenerConfig {
+        self_host_space,
+        http,
+        https,
+    } = &config.listeners;
+
+    let mut listeners: Vec<LocalBoxFuture<'_, Result<()>>> = vec![];
+
+    if *self_host_space {
+        let server_keys = KeyManager::load_or_create(dirs.config_dir()).await?;
+        listeners.push(serve_self_host_space(server_keys, router.clone()).boxed_local());
+    }
+
+    if let Some(addr) = http {
+        listeners.push(serve_http(*addr, router.clone()).boxed_local());
+    }
+
+    if let Some(https_config) = https {
+        listeners.push(serve_https(https_config, router.clone()).boxed_local());
+    }
+
+    // The job queue runs until it is aborted so it should never stop while the listeners are
+    // running.
+    listeners.push(
+        async move {
+            job_queue_task.await?;
+            Err::<(), _>(eyre!("The job queue stopped unexpectedly"))
+        }
+        .boxed_local(),
+    );
+
+    let result = tokio::select! {
+        result = try_join_all(listeners) => result.map(|_| ()),
+        result = shutdown_signal() => {
+            info!("Shutting down");
+            result
+        }
+    };
+
+    // Save the usage recorded since the last flush before the server stops
+    key_usage_task.abort();
+    if let Err(err) = key_usage.flush().await {
+        warn!("Unable to save client key usage: {:?}", err);
+    }
+
+    result
+}
+
+/// Resolves when the server is asked to stop by Ctrl+C or SIGTERM
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    };
+
+    Ok(())
+}
+
+pub fn schema(shared_state: &SharedState) -> AppSchema {
+    Schema::build(QueryRoot::default(), Mutation::default(), EmptySubscription)
+        .data(shared_state.jobs.clone())
+        .data(shared_state.engines.clone())
+        .data(shared_state.profiles.clone())
+        .data(shared_state.job_queue.clone())
+        .data(shared_state.job_history.clone())
+        .data(shared_state.url_signer.clone())
+        .data(shared_state.config.clone())
+        .finish()
+}
+
+pub fn app(config: Arc<Config>, shared_state: Arc<SharedState>) -> Router {
+    let url_signer = shared_state.url_signer.clone();
+    let authorized_keys = shared_state.authorized_keys.clone();
+    let revocations = shared_state.revocations.clone();
+    let key_usage = shared_state.key_usage.clone();
+    let schema = schema(&shared_state);
 
-    let shared_state = Arc::new(SharedState { jobs });
+    Router::new()
+        .route(
+            routes::JOB_GCODE,
+            get({
+                let shared_state = Arc::clone(&shared_state);
+                move |path, headers, key_id, scopes| {
+                    get_job_gcode(path, headers, key_id, scopes, shared_state)
+                }
+            }),
+        )
+        .route(
+            routes::JOB_THUMBNAIL,
+            get({
+                let shared_state = Arc::clone(&shared_state);
+                move |path, key_id, scopes| get_job_thumbnail(path, key_id, scopes, shared_state)
+            }),
+        )
+        .route(
+            routes::JOB_LAYERS,
+            get({
+                let shared_state = Arc::clone(&shared_state);
+                move |path, key_id, scopes| get_job_layers(path, key_id, scopes, shared_state)
+            }),
+        )
+        .route(
+            routes::GRAPHQL,
+            get(graphql_playground).post(graphql_handler),
+        )
+        .layer(Extension(schema))
+        .layer(Extension(Arc::clone(&config)))
+        // The auth extractor will run before all routes
+        .route_layer(middleware::from_fn(move |req, next| {
+            auth(
+                Arc::clone(&config),
+                authorized_keys.clone(),
+                url_signer.clone(),
+                revocations.clone(),
+                key_usage.clone(),
+                req,
+                next,
+            )
+        }))
+}
 
-    // build the http server
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    tracing::debug!("listening on {}", addr);
+async fn serve_self_host_space(server_keys: KeyManager, router: Router) -> Result<()> {
+    info!("Listening via self-host.space WebTransport");
 
-    let server_keys = KeyManager::load_or_create(dirs.config_dir()).await?;
     let self_host_server = self_host_space::Server::new(server_keys);
 
     self_host_server
         .serve(move |async_wt_server| {
-            let config = Arc::clone(&config);
-            let shared_state = Arc::clone(&shared_state);
+            let make_service = router.clone().into_make_service();
 
             async move {
-                let schema: AppSchema =
-                    Schema::new(QueryRoot, Mutation::default(), EmptySubscription);
-
-                // build the server routes
-                let routes = Router::new()
-                    .route(
-                        "/jobs/:job_id/gcode",
-                        get({
-                            let shared_state = Arc::clone(&shared_state);
-                            move |path| get_job_gcode(path, shared_state)
-                        }),
-                    )
-                    .route("/", get(graphql_playground).post(graphql_handler))
-                    .layer(Extension(schema))
-                    // The auth extractor will run before all routes
-                    .route_layer(middleware::from_fn(move |req, next| {
-                        auth(Arc::clone(&config), req, next)
-                    }));
-                let make_service = routes.into_make_service();
-
                 // Start the http server. It will receive it's requests via the self-host.space Web Transport server to fasciliate
                 // secure connections without a doman & signed certificate.
                 let accept = accept::from_stream(async_wt_server.into_stream());
-                axum::Server::builder(accept).serve(make_service);
+
+                if let Err(err) = axum::Server::builder(accept).serve(make_service).await {
+                    warn!("self-host.space HTTP server error: {:?}", err);
+                }
             }
         })
         .await?;
@@ -90,32 +240,96 @@ pub async fn serve() -> Result<()> {
     Ok(())
 }
 
+async fn serve_http(addr: SocketAddr, router: Router) -> Result<()> {
+    info!("Listening for HTTP connections on {}", addr);
+
+    axum_server::bind(addr)
+        .serve(router.into_make_service())
+        .await
+        .wrap_err_with(|| format!("HTTP listener error on {}", addr))?;
+
+    Ok(())
+}
+
+async fn serve_https(https_config: &HttpsListenerConfig, router: Router) -> Result<()> {
+    let HttpsListenerConfig {
+        bind,
+        cert_path,
+        key_path,
+    } = https_config;
+
+    let tls_config = RustlsConfig::from_pem_file(cert_path, key_path)
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "Unable to load TLS certificate ({:?}) or private key ({:?})",
+                cert_path, key_path,
+            )
+        })?;
+
+    info!("Listening for HTTPS connections on {}", bind);
+
+    axum_server::bind_rustls(*bind, tls_config)
+        .serve(router.into_make_service())
+        .await
+        .wrap_err_with(|| format!("HTTPS listener error on {}", bind))?;
+
+    Ok(())
+}
+
 async fn graphql_playground() -> impl IntoResponse {
-    Html(playground_source(GraphQLPlaygroundConfig::new("/")))
+    Html(playground_source(GraphQLPlaygroundConfig::new(
+        routes::GRAPHQL,
+    )))
 }
 
 async fn graphql_handler(
     Extension(schema): Extension<AppSchema>,
-    req: GraphQLRequest,
-) -> GraphQLResponse {
-    let req = req.into_inner();
-    // req = req.data(token);
+    Extension(config): Extension<Arc<Config>>,
+    key_id: Option<Extension<ClientKeyId>>,
+    scopes: Option<Extension<Scopes>>,
+    req: Request<Body>,
+) -> Response {
+    // Uploads over the client key's limit are rejected as they are received
+    let max_upload_bytes = key_id
+        .as_ref()
+        .and_then(|Extension(ClientKeyId(key_id))| config.authorized_keys.get(key_id))
+        .and_then(|client_key| client_key.limits.max_upload_bytes);
 
-    schema.execute(req).await.into()
-}
+    let (req, upload_limit_exceeded) = match max_upload_bytes {
+        Some(max_upload_bytes) => {
+            let (req, exceeded) = quota::limit_request_body(req, max_upload_bytes);
+            (req, Some(exceeded))
+        }
+        None => (req, None),
+    };
 
-async fn get_job_gcode(
-    Path(job_id): Path<String>,
-    shared_state: Arc<SharedState>,
-) -> AppResult<impl IntoResponse> {
-    let job = shared_state
-        .jobs
-        .get_mut(&job_id.into())
-        .ok_or_else(|| eyre!("Job not found"))?;
+    let req = match <GraphQLRequest as FromRequest<(), Body>>::from_request(req, &()).await {
+        Ok(req) => req,
+        Err(rejection) => {
+            return match (max_upload_bytes, upload_limit_exceeded) {
+                (Some(max_upload_bytes), Some(exceeded)) if exceeded.load(Ordering::SeqCst) => {
+                    let err = quota::upload_limit_error(max_upload_bytes)
+                        .into_server_error(Default::default());
+                    GraphQLResponse::from(async_graphql::Response::from_errors(vec![err]))
+                        .into_response()
+                }
+                _ => rejection.into_response(),
+            };
+        }
+    };
 
-    let gcode = fs::read(job.gcode_path())
-        .await
-        .wrap_err("Error reading GCode file")?;
+    let mut req = req.into_inner();
 
-    Ok((StatusCode::OK, gcode))
+    if let Some(Extension(key_id)) = key_id {
+        req = req.data(key_id);
+    }
+    if let Some(Extension(scopes)) = scopes {
+        req = req.data(scopes);
+    }
+
+    GraphQLResponse::from(schema.execute(req).await).into_response()
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,2145 @@
+use super::*;
+use crate::auth::authorized_keys::AuthorizedKeys;
+use crate::auth::key_usage::{KeyUsage, KeyUsageRecorder};
+use crate::auth::revocation::{RevocationList, Revocations};
+use crate::auth::{CustomClaims, Scope};
+use crate::config::{
+    ClientKey, ClientLimits, CustomEngineConfig, InviteToken, PostProcessorConfig,
+};
+use crate::engine::belt_engine::BELT_ENGINE_URL;
+use crate::engine::{Engine, SlicingEngine};
+use crate::execution_context::ExecutionContext;
+use crate::release::{LocalReleaseConfig, Release, ReleaseConfig};
+use async_graphql::{UploadValue, Variables};
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use cgmath::Matrix4;
+use jwt_simple::prelude::*;
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tower::ServiceExt;
+
+const MODEL: &'static str = "solid test_model";
+const TEST_ENGINE_URL: &'static str = "test://test_engine";
+const TEST_3MF_ENGINE_URL: &'static str = "test://test_3mf_engine";
+/// The settings supported by the test engine, in addition to those in each job's profile
+const TEST_ENGINE_SETTINGS: &[&str] = &["fill_density", "layer_height", "temperature"];
+
+/// A stand-in engine which "slices" models by wrapping them in GCode comments. 3MF plates are
+/// summarized by their PrusaSlicer model config.
+struct TestEngine {
+    metadata: Engine,
+    release_config: LocalReleaseConfig,
+}
+
+impl TestEngine {
+    fn new() -> Self {
+        Self::with_formats("test_engine", TEST_ENGINE_URL, &[".stl"])
+    }
+
+    fn with_3mf_support() -> Self {
+        Self::with_formats("test_3mf_engine", TEST_3MF_ENGINE_URL, &[".stl", ".3mf"])
+    }
+
+    fn with_formats(id: &str, url: &str, accepted_file_formats: &[&str]) -> Self {
+        Self {
+            metadata: Engine {
+                id: id.into(),
+                name: "Test Engine".to_owned(),
+                transform_mat4: Matrix4::from_scale(1.0),
+                allows_positioning: true,
+                invert_rotation: Default::default(),
+                accepted_file_formats: accepted_file_formats
+                    .iter()
+                    .map(|format| format.to_string())
+                    .collect(),
+                release_url: None,
+                home_page: url.to_owned(),
+            },
+            release_config: LocalReleaseConfig {
+                bin_path: "/bin/true".into(),
+                release_url: url.to_owned(),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl SlicingEngine for TestEngine {
+    fn metadata(&self) -> &Engine {
+        &self.metadata
+    }
+
+    fn parse_release(&self, url: &str) -> Result<Release> {
+        self.release_config.parse(url)
+    }
+
+    async fn latest_release(&self) -> Result<Release> {
+        self.release_config.latest_release().await
+    }
+
+    fn accepts_multiple_models(&self) -> bool {
+        true
+    }
+
+    fn args(&self, _exec_ctx: &ExecutionContext) -> Vec<OsString> {
+        vec![]
+    }
+
+    fn setting_keys(&self) -> Option<HashSet<&'static str>> {
+        Some(TEST_ENGINE_SETTINGS.iter().copied().collect())
+    }
+
+    async fn generate_gcode(&self, exec_ctx: ExecutionContext) -> Result<()> {
+        exec_ctx.co.yield_(Ok(50.0)).await;
+
+        let mut src = String::new();
+        for src_path in &exec_ctx.src_paths {
+            if src_path.extension() == Some("3mf".as_ref()) {
+                let mut archive = zip::ZipArchive::new(std::fs::File::open(src_path)?)?;
+                archive
+                    .by_name("Metadata/Slic3r_PE_model.config")?
+                    .read_to_string(&mut src)?;
+            } else {
+                src.push_str(&fs::read_to_string(src_path).await?);
+            }
+        }
+        let config = fs::read_to_string(&exec_ctx.config_path).await?;
+        let gcode = format!("; generated by test_engine\n; {src}\n; {config}\nG28\n");
+        fs::write(&exec_ctx.gcode_path, gcode).await?;
+
+        exec_ctx.co.yield_(Ok(100.0)).await;
+
+        Ok(())
+    }
+}
+
+/// The sections of config.toml that tests can add to the server's config
+#[derive(serde::Deserialize)]
+struct ConfigToml {
+    #[serde(default)]
+    engines: Vec<CustomEngineConfig>,
+    #[serde(default)]
+    post_processors: Vec<PostProcessorConfig>,
+    max_concurrent_jobs: Option<usize>,
+    /// The limits of the test client key
+    #[serde(default)]
+    limits: ClientLimits,
+    /// The expiry of the test client key
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// An in-process slicing server with a running job queue and an authorized client
+struct TestServer {
+    schema: AppSchema,
+    router: Router,
+    bearer: String,
+    /// The test client key, which GraphQL requests are made with
+    key_pair: ES256KeyPair,
+    key_id: String,
+    config_path: PathBuf,
+    revocations_path: PathBuf,
+    key_usage_path: PathBuf,
+    key_usage: KeyUsageRecorder,
+    _profiles_dir: tempfile::TempDir,
+    config_dir: tempfile::TempDir,
+}
+
+impl TestServer {
+    async fn new() -> Result<Self> {
+        Self::with_config_toml("").await
+    }
+
+    /// Starts a server with additional config.toml sections, eg. `[[engines]]`
+    async fn with_config_toml(config_toml: &str) -> Result<Self> {
+        let (mut config, key_pair) = test_config()?;
+        let key_id = key_pair.key_id().clone().unwrap_or_default();
+        let bearer = bearer_token(&key_pair, jwt_claims(Duration::from_mins(5)))?;
+        let config_toml = toml::from_str::<ConfigToml>(config_toml)?;
+        config.engines = config_toml.engines;
+        config.post_processors = config_toml.post_processors;
+        config.max_concurrent_jobs = config_toml.max_concurrent_jobs;
+        if let Some(client_key) = config.authorized_keys.get_mut(&key_id) {
+            client_key.limits = config_toml.limits;
+            client_key.expires_at = config_toml.expires_at;
+        }
+        let config = Arc::new(config);
+
+        let jobs: JobMap = Arc::new(DashMap::new());
+
+        let engines: Engines = Arc::new(EngineRegistry::from_config(&config)?);
+        engines.register(Arc::new(TestEngine::new()));
+        engines.register(Arc::new(TestEngine::with_3mf_support()));
+
+        let profiles_dir = tempfile::tempdir()?;
+        let profiles: Profiles =
+            Arc::new(ProfileStore::open(profiles_dir.path().to_owned()).await?);
+
+        let (job_queue, _) =
+            job::spawn_job_queue(jobs.clone(), engines.clone(), Arc::clone(&config));
+
+        let config_dir = tempfile::tempdir()?;
+        let config_path = config_dir.path().join("config.toml");
+        let revocations_path = config_dir.path().join("revocations.toml");
+        let key_usage_path = config_dir.path().join("key_usage.toml");
+        let key_usage = KeyUsageRecorder::new(key_usage_path.clone());
+
+        let shared_state = Arc::new(SharedState {
+            jobs,
+            engines,
+            profiles,
+            job_queue,
+            job_history: Arc::new(DashMap::new()),
+            url_signer: UrlSigner::new(),
+            config: Arc::clone(&config),
+            authorized_keys: AuthorizedKeys::new(config_path.clone(), &config),
+            revocations: RevocationList::new(revocations_path.clone()),
+            key_usage: key_usage.clone(),
+        });
+
+        Ok(Self {
+            schema: schema(&shared_state),
+            router: app(config, shared_state),
+            bearer,
+            key_pair,
+            key_id,
+            config_path,
+            revocations_path,
+            key_usage_path,
+            key_usage,
+            _profiles_dir: profiles_dir,
+            config_dir,
+        })
+    }
+
+    /// Executes a GraphQL request as the test client key with every scope
+    async fn execute(&self, req: async_graphql::Request) -> Result<serde_json::Value> {
+        self.execute_as(req, &self.key_id, Scope::all()).await
+    }
+
+    /// Executes a GraphQL request with the key id and scopes that the auth middleware would pass
+    /// to the GraphQL handler
+    async fn execute_as(
+        &self,
+        req: async_graphql::Request,
+        key_id: &str,
+        scopes: Vec<Scope>,
+    ) -> Result<serde_json::Value> {
+        let req = req
+            .data(ClientKeyId(key_id.to_owned()))
+            .data(Scopes(scopes));
+        let res = self.schema.execute(req).await;
+
+        if !res.errors.is_empty() {
+            return Err(eyre!("GraphQL errors: {:?}", res.errors));
+        }
+
+        Ok(res.data.into_json()?)
+    }
+
+    async fn create_job(&self, engine_url: &str) -> Result<serde_json::Value> {
+        self.create_job_with(json!({ "engineURL": engine_url }), Some(""))
+            .await
+    }
+
+    /// Creates a job from the given CreateJobInput fields, uploading a model along with the
+    /// config (if any)
+    async fn create_job_with(
+        &self,
+        mut input: serde_json::Value,
+        config: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        let mut uploads = vec![("src", "model.stl", MODEL)];
+        if let Some(config) = config {
+            uploads.push(("config", "config.ini", config));
+        }
+
+        for (field, _, _) in &uploads {
+            input[field] = serde_json::Value::Null;
+        }
+
+        self.create_job_with_uploads(input, &uploads).await
+    }
+
+    /// Creates a job, uploading each (CreateJobInput field, filename, content) file
+    async fn create_job_with_uploads<C: AsRef<[u8]>>(
+        &self,
+        input: serde_json::Value,
+        uploads: &[(&str, &str, C)],
+    ) -> Result<serde_json::Value> {
+        let req = create_job_request(input, uploads)?;
+
+        let mut data = self.execute(req).await?;
+        Ok(data["createJob"].take())
+    }
+
+    async fn create_profile(&self, engine_id: &str, content: &str) -> Result<serde_json::Value> {
+        let mut req = async_graphql::Request::new(
+            r#"
+                mutation($input: CreateProfileInput!) {
+                    createProfile(input: $input) {
+                        id
+                        name
+                        engineId
+                    }
+                }
+            "#,
+        )
+        .variables(Variables::from_json(json!({
+            "input": {
+                "name": "Test Profile",
+                "engineId": engine_id,
+                "file": null,
+            },
+        })));
+        req.set_upload("variables.input.file", upload("profile.ini", content)?);
+
+        let mut data = self.execute(req).await?;
+        Ok(data["createProfile"].take())
+    }
+
+    /// Polls the job until it has either completed or errored
+    async fn wait_for_job(&self, job_id: &str) -> Result<serde_json::Value> {
+        for _ in 0..500 {
+            let req = async_graphql::Request::new(
+                r#"
+                    query($id: ID!) {
+                        job(input: { id: $id }) {
+                            isDone
+                            percentComplete
+                            error { message }
+                            priority
+                            queuePosition
+                            estimatedStartTime
+                            thumbnailUrl
+                            layersUrl
+                            statistics {
+                                extruders {
+                                    extruder
+                                    filamentUsedMm
+                                }
+                            }
+                        }
+                    }
+                "#,
+            )
+            .variables(Variables::from_json(json!({ "id": job_id })));
+
+            let mut data = self.execute(req).await?;
+            let job = data["job"].take();
+
+            if job["isDone"] == true || !job["error"].is_null() {
+                return Ok(job);
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        Err(eyre!("Timed out waiting for job {job_id}"))
+    }
+
+    async fn get(&self, url: &str, authorized: bool) -> Result<(StatusCode, String)> {
+        let (status, body) = self.get_bytes(url, authorized).await?;
+
+        Ok((status, String::from_utf8(body)?))
+    }
+
+    async fn get_bytes(&self, url: &str, authorized: bool) -> Result<(StatusCode, Vec<u8>)> {
+        let (status, _, body) = self.get_with_headers(url, authorized).await?;
+
+        Ok((status, body))
+    }
+
+    async fn get_with_headers(
+        &self,
+        url: &str,
+        authorized: bool,
+    ) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
+        let bearer = authorized.then_some(self.bearer.as_str());
+
+        self.get_with_bearer(url, bearer).await
+    }
+
+    async fn get_with_bearer(
+        &self,
+        url: &str,
+        bearer: Option<&str>,
+    ) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
+        self.send_get(url, bearer, &[]).await
+    }
+
+    /// Makes an authorized GET request with additional request headers, eg. `Range`
+    async fn get_with_request_headers(
+        &self,
+        url: &str,
+        request_headers: &[(header::HeaderName, &str)],
+    ) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
+        self.send_get(url, Some(&self.bearer), request_headers)
+            .await
+    }
+
+    async fn send_get(
+        &self,
+        url: &str,
+        bearer: Option<&str>,
+        request_headers: &[(header::HeaderName, &str)],
+    ) -> Result<(StatusCode, HeaderMap, Vec<u8>)> {
+        let mut req = Request::get(url);
+        if let Some(bearer) = bearer {
+            req = req.header(header::AUTHORIZATION, bearer);
+        }
+        for (name, value) in request_headers {
+            req = req.header(name, *value);
+        }
+
+        let res = self
+            .router
+            .clone()
+            .oneshot(req.body(Body::empty())?)
+            .await?;
+        let status = res.status();
+        let headers = res.headers().clone();
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+
+        Ok((status, headers, body.to_vec()))
+    }
+}
+
+/// Authorizes a new client key with every scope and returns a config containing it along with the
+/// key's signing key pair
+fn test_config() -> Result<(Config, ES256KeyPair)> {
+    let id = nanoid::nanoid!();
+    let key_pair = ES256KeyPair::generate().with_key_id(&id);
+
+    let public_key_pem = key_pair
+        .public_key()
+        .to_pem()
+        .map_err(|_| eyre!("Failed to generate PEM for client public key"))?;
+
+    let mut config = Config::default();
+    config.authorized_keys.insert(
+        id.clone(),
+        ClientKey {
+            id: id.clone(),
+            label: "test".to_owned(),
+            public_key_pem,
+            created_at: Some(chrono::Utc::now()),
+            expires_at: None,
+            scopes: Scope::all(),
+            limits: ClientLimits::default(),
+        },
+    );
+
+    Ok((config, key_pair))
+}
+
+/// Claims for a slicing server JWT that is valid for the given duration
+fn jwt_claims(valid_for: Duration) -> JWTClaims<CustomClaims> {
+    let custom_claims = CustomClaims {
+        slicing: true,
+        scopes: None,
+    };
+
+    Claims::with_custom_claims(custom_claims, valid_for)
+}
+
+fn bearer_token(key_pair: &ES256KeyPair, claims: JWTClaims<CustomClaims>) -> Result<String> {
+    let token = key_pair
+        .sign(claims)
+        .map_err(|_| eyre!("Failed to sign JWT"))?;
+
+    Ok(format!("Bearer {token}"))
+}
+
+/// A createJob request that uploads each (CreateJobInput field, filename, content) file
+fn create_job_request<C: AsRef<[u8]>>(
+    input: serde_json::Value,
+    uploads: &[(&str, &str, C)],
+) -> Result<async_graphql::Request> {
+    let mut req = async_graphql::Request::new(
+        r#"
+            mutation($input: CreateJobInput!) {
+                createJob(input: $input) {
+                    id
+                    gcodeUrl
+                    signedGcodeUrl
+                    arrangement {
+                        object
+                        copy
+                        x
+                        y
+                        rotation
+                    }
+                    modelReports {
+                        name
+                        analysis {
+                            problems
+                        }
+                        repaired {
+                            isManifold
+                            problems
+                        }
+                        error
+                    }
+                }
+            }
+        "#,
+    )
+    .variables(Variables::from_json(json!({ "input": input })));
+
+    for (field, filename, content) in uploads {
+        req.set_upload(
+            &format!("variables.input.{field}"),
+            upload(filename, content)?,
+        );
+    }
+
+    Ok(req)
+}
+
+fn upload(filename: &str, content: impl AsRef<[u8]>) -> Result<UploadValue> {
+    let mut file = tempfile::tempfile()?;
+    file.write_all(content.as_ref())?;
+    file.rewind()?;
+
+    Ok(UploadValue {
+        filename: filename.to_owned(),
+        content_type: None,
+        content: file,
+    })
+}
+
+#[tokio::test]
+async fn jobs_are_sliced_and_downloadable() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server.create_job(TEST_ENGINE_URL).await?;
+    let job_id = job["id"].as_str().unwrap();
+
+    let completed_job = server.wait_for_job(job_id).await?;
+    assert_eq!(completed_job["isDone"], true);
+    assert_eq!(completed_job["percentComplete"], 100.0);
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.starts_with("; generated by test_engine"));
+    assert!(gcode.contains(MODEL));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn signed_gcode_urls_do_not_require_a_jwt() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server.create_job(TEST_ENGINE_URL).await?;
+    let job_id = job["id"].as_str().unwrap();
+    let gcode_url = job["gcodeUrl"].as_str().unwrap();
+    let signed_gcode_url = job["signedGcodeUrl"].as_str().unwrap();
+
+    server.wait_for_job(job_id).await?;
+
+    let (status, _) = server.get(gcode_url, false).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, gcode) = server.get(signed_gcode_url, false).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.contains(MODEL));
+
+    // Signed URLs are only valid for the path they were issued for
+    let other_job_url = signed_gcode_url.replace(job_id, "another_job");
+    let (status, _) = server.get(&other_job_url, false).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn jobs_with_unknown_engines_are_rejected() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let result = server.create_job("test://unknown_engine").await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("Engine not found"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn registered_engines_are_listed() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let data = server
+        .execute(async_graphql::Request::new("{ engines { id } }"))
+        .await?;
+
+    let engine_ids = data["engines"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|engine| engine["id"].as_str().unwrap())
+        .collect::<Vec<_>>();
+
+    assert!(engine_ids.contains(&"prusa_slicer"));
+    assert!(engine_ids.contains(&"test_engine"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn custom_engines_are_listed() -> Result<()> {
+    let server = TestServer::with_config_toml(
+        r#"
+            [[engines]]
+            id = "in_house_slicer"
+            name = "In-House Slicer"
+            bin_path = "/opt/in-house-slicer/bin/slicer"
+            args = ["--profile", "{config}", "--out", "{gcode}", "{src}"]
+            accepted_file_formats = [".stl", ".3mf"]
+            transform_mat4 = [[1, 0, 0, 0], [0, -1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]]
+            progress_regex = '^progress: (\d+)%'
+        "#,
+    )
+    .await?;
+
+    let data = server
+        .execute(async_graphql::Request::new(
+            "{ engines { id name acceptedFileFormats transformMat4 } }",
+        ))
+        .await?;
+
+    let engine = data["engines"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|engine| engine["id"] == "in_house_slicer")
+        .unwrap();
+
+    assert_eq!(engine["name"], "In-House Slicer");
+    assert_eq!(engine["acceptedFileFormats"], json!([".stl", ".3mf"]));
+    assert_eq!(engine["transformMat4"][1][1], -1.0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn custom_engines_cannot_replace_builtin_engines() -> Result<()> {
+    let result = TestServer::with_config_toml(
+        r#"
+            [[engines]]
+            id = "prusa_slicer"
+            name = "Prusa Slicer Fork"
+            bin_path = "/opt/prusa-slicer-fork/bin/prusa-slicer"
+            args = ["{src}"]
+            accepted_file_formats = [".stl"]
+        "#,
+    )
+    .await;
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn jobs_can_use_stored_profiles() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let profile = server
+        .create_profile("test_engine", "layer_height = 0.2")
+        .await?;
+
+    let data = server
+        .execute(async_graphql::Request::new(
+            r#"{ profiles(engineId: "test_engine") { id name } }"#,
+        ))
+        .await?;
+    assert_eq!(data["profiles"][0]["id"], profile["id"]);
+
+    let job = server
+        .create_job_with(
+            json!({ "engineURL": TEST_ENGINE_URL, "profileId": profile["id"] }),
+            None,
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.contains("layer_height = 0.2"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn profiles_are_restricted_to_their_engine() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let profile = server.create_profile("prusa_slicer", "").await?;
+
+    let result = server
+        .create_job_with(
+            json!({ "engineURL": TEST_ENGINE_URL, "profileId": profile["id"] }),
+            None,
+        )
+        .await;
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn profiles_can_be_deleted() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let profile = server.create_profile("test_engine", "").await?;
+
+    let req = async_graphql::Request::new(
+        r#"
+            mutation($id: ID!) {
+                deleteProfile(input: { id: $id })
+            }
+        "#,
+    )
+    .variables(Variables::from_json(json!({ "id": profile["id"] })));
+    server.execute(req).await?;
+
+    let data = server
+        .execute(async_graphql::Request::new("{ profiles { id } }"))
+        .await?;
+    assert_eq!(data["profiles"], json!([]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn setting_overrides_are_applied_to_the_config() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server
+        .create_job_with(
+            json!({
+                "engineURL": TEST_ENGINE_URL,
+                "settingOverrides": [
+                    { "key": "layer_height", "value": "0.1" },
+                    { "key": "print.fill_density", "value": "40%" },
+                ],
+            }),
+            Some("layer_height = 0.2\nsupport_material = 0\n[print]\nfill_density = 20%\n"),
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.contains("layer_height = 0.1"));
+    assert!(gcode.contains("support_material = 0"));
+    assert!(gcode.contains("fill_density = 40%"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn setting_overrides_can_add_engine_settings_missing_from_the_config() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server
+        .create_job_with(
+            json!({
+                "engineURL": TEST_ENGINE_URL,
+                "settingOverrides": [{ "key": "fill_density", "value": "15%" }],
+            }),
+            Some("layer_height = 0.2\n"),
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.contains("layer_height = 0.2"));
+    assert!(gcode.contains("fill_density = 15%"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unknown_setting_overrides_are_rejected() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let result = server
+        .create_job_with(
+            json!({
+                "engineURL": TEST_ENGINE_URL,
+                "settingOverrides": [{ "key": "not_a_setting", "value": "1" }],
+            }),
+            Some("layer_height = 0.2\n"),
+        )
+        .await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("Unknown settings: not_a_setting"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn profiles_can_be_validated_before_submitting() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let validate = |content: &str| {
+        async_graphql::Request::new(
+            r#"
+                query($content: String!) {
+                    validateProfile(input: { engineId: "prusa_slicer", content: $content }) {
+                        valid
+                        errors { key message }
+                        normalized
+                    }
+                }
+            "#,
+        )
+        .variables(Variables::from_json(json!({ "content": content })))
+    };
+
+    let data = server
+        .execute(validate(
+            "nozzle_diameter = 0.4\nlayer_height = 0.6\nbed_shape = 0x0,200x0\n",
+        ))
+        .await?;
+    let validation = &data["validateProfile"];
+    assert_eq!(validation["valid"], false);
+
+    let invalid_keys = validation["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["key"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(invalid_keys, vec!["bed_shape", "layer_height"]);
+
+    let data = server
+        .execute(validate(
+            "nozzle_diameter = 0.4\n\
+            layer_height = 0.2\n\
+            bed_shape = 0x0,200x0,200x200,0x200\n\
+            fill_density = 20\n",
+        ))
+        .await?;
+    let validation = &data["validateProfile"];
+    assert_eq!(validation["valid"], true);
+    assert!(validation["normalized"]
+        .as_str()
+        .unwrap()
+        .contains("fill_density = 20%"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_configs_are_rejected_before_queueing() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let result = server
+        .create_job_with(
+            json!({
+                "engineURL": "https://github.com/prusa3d/PrusaSlicer/releases/tag/version_2.5.0",
+            }),
+            Some("nozzle_diameter = 0.4\nlayer_height = 0.2\n"),
+        )
+        .await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("bed_shape: is required"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn profiles_can_be_converted_between_slic3r_engines() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let req = async_graphql::Request::new(
+        r#"
+            query($content: String!) {
+                convertProfile(input: {
+                    content: $content,
+                    fromEngineId: "prusa_slicer",
+                    toEngineId: "super_slicer",
+                }) {
+                    content
+                    conversion {
+                        droppedKeys
+                        approximated { key note }
+                    }
+                }
+            }
+        "#,
+    )
+    .variables(Variables::from_json(json!({
+        "content": "elefant_foot_compensation = 0.2\n\
+            brim_separation = 0.1\n\
+            seam_position = nearest\n\
+            layer_height = 0.2\n",
+    })));
+
+    let data = server.execute(req).await?;
+    let converted = &data["convertProfile"];
+    let content = converted["content"].as_str().unwrap();
+
+    assert!(content.contains("first_layer_size_compensation = -0.2"));
+    assert!(content.contains("brim_offset = 0.1"));
+    assert!(content.contains("seam_position = cost"));
+    assert!(content.contains("layer_height = 0.2"));
+    assert_eq!(
+        converted["conversion"]["approximated"][0]["key"],
+        "seam_position"
+    );
+
+    // Converting to Slic3r drops settings that Slic3r does not support
+    let req = async_graphql::Request::new(
+        r#"
+            query($content: String!) {
+                convertProfile(input: {
+                    content: $content,
+                    fromEngineId: "super_slicer",
+                    toEngineId: "slic3r",
+                }) {
+                    conversion { droppedKeys }
+                }
+            }
+        "#,
+    )
+    .variables(Variables::from_json(json!({ "content": content })));
+
+    let data = server.execute(req).await?;
+    assert_eq!(
+        data["convertProfile"]["conversion"]["droppedKeys"],
+        json!(["brim_offset", "first_layer_size_compensation"])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn stored_profiles_are_converted_for_the_selected_engine() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let profile = server
+        .create_profile(
+            "prusa_slicer",
+            "nozzle_diameter = 0.4\n\
+            layer_height = 0.2\n\
+            bed_shape = 0x0,200x0,200x200,0x200\n\
+            elefant_foot_compensation = 0.2\n",
+        )
+        .await?;
+
+    let job = server
+        .create_job_with(
+            json!({
+                "engineURL": "https://github.com/supermerill/SuperSlicer/releases/tag/2.4.58.5",
+                "profileId": profile["id"],
+            }),
+            None,
+        )
+        .await?;
+    assert!(job["id"].is_string());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn models_are_transformed_before_slicing() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server
+        .create_job_with_uploads(
+            json!({
+                "src": null,
+                "config": null,
+                "engineURL": TEST_ENGINE_URL,
+                "transform": {
+                    "position": { "x": 10, "y": 20, "z": 0 },
+                    "scale": { "x": 2, "y": 2, "z": 2 },
+                },
+            }),
+            &[
+                ("src", "model.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n"),
+                ("config", "config.ini", ""),
+            ],
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.contains("v 10 20 0"));
+    assert!(gcode.contains("v 12 20 0"));
+    assert!(gcode.contains("v 10 22 0"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn transforms_cannot_mix_a_matrix_with_components() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let identity = json!([[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]]);
+    let result = server
+        .create_job_with(
+            json!({
+                "engineURL": TEST_ENGINE_URL,
+                "transform": {
+                    "mat4": identity,
+                    "position": { "x": 10, "y": 20, "z": 0 },
+                },
+            }),
+            Some(""),
+        )
+        .await;
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn plates_are_sliced_into_a_single_gcode_file() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server
+        .create_job_with_uploads(
+            json!({
+                "objects": [
+                    { "src": null, "copies": 2 },
+                    { "src": null },
+                ],
+                "config": null,
+                "engineURL": TEST_ENGINE_URL,
+            }),
+            &[
+                ("objects.0.src", "part.stl", "solid part_a"),
+                ("objects.1.src", "part.stl", "solid part_b"),
+                ("config", "config.ini", ""),
+            ],
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(gcode.matches("solid part_a").count(), 2);
+    assert_eq!(gcode.matches("solid part_b").count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn positioned_plates_are_rejected_by_engines_that_position_models() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let result = server
+        .create_job_with_uploads(
+            json!({
+                "objects": [
+                    { "src": null, "transform": { "position": { "x": 10, "y": 0, "z": 0 } } },
+                    { "src": null },
+                ],
+                "config": null,
+                "engineURL": BELT_ENGINE_URL,
+            }),
+            &[
+                ("objects.0.src", "a.stl", MODEL),
+                ("objects.1.src", "b.stl", MODEL),
+                ("config", "config.ini", ""),
+            ],
+        )
+        .await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("does not accept positioned plates"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn per_object_settings_require_3mf_support() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let result = server
+        .create_job_with_uploads(
+            json!({
+                "objects": [
+                    {
+                        "src": null,
+                        "settingOverrides": [{ "key": "layer_height", "value": "0.1" }],
+                    },
+                    { "src": null },
+                ],
+                "config": null,
+                "engineURL": TEST_ENGINE_URL,
+            }),
+            &[
+                ("objects.0.src", "a.stl", MODEL),
+                ("objects.1.src", "b.stl", MODEL),
+                ("config", "config.ini", "layer_height = 0.2\n"),
+            ],
+        )
+        .await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("does not support per-object settings"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn plates_can_be_arranged_on_the_bed() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server
+        .create_job_with_uploads(
+            json!({
+                "objects": [{ "src": null, "copies": 2 }],
+                "config": null,
+                "engineURL": TEST_ENGINE_URL,
+                "arrange": { "spacing": 10 },
+            }),
+            &[
+                (
+                    "objects.0.src",
+                    "part.obj",
+                    "v 0 0 0\nv 20 0 0\nv 0 10 0\nf 1 2 3\n",
+                ),
+                (
+                    "config",
+                    "config.ini",
+                    "bed_shape = 0x0,100x0,100x100,0x100\n",
+                ),
+            ],
+        )
+        .await?;
+
+    assert_eq!(
+        job["arrangement"],
+        json!([
+            { "object": 0, "copy": 0, "x": 35.0, "y": 50.0, "rotation": 0.0 },
+            { "object": 0, "copy": 1, "x": 65.0, "y": 50.0, "rotation": 0.0 },
+        ])
+    );
+
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.contains("v 25 45 0"));
+    assert!(gcode.contains("v 55 45 0"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn arranging_requires_a_bed_shape() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let result = server
+        .create_job_with_uploads(
+            json!({
+                "src": null,
+                "config": null,
+                "engineURL": TEST_ENGINE_URL,
+                "arrange": {},
+            }),
+            &[("src", "model.stl", MODEL), ("config", "config.ini", "")],
+        )
+        .await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("bed_shape is required"));
+
+    Ok(())
+}
+
+/// A tetrahedron with it's slanted face missing
+const OPEN_TETRAHEDRON: &'static str =
+    "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 3 2\nf 1 2 4\nf 1 4 3\n";
+
+#[tokio::test]
+async fn models_can_be_analyzed() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let mut req = async_graphql::Request::new(
+        r#"
+            query($upload: Upload!) {
+                analyzeModel(upload: $upload) {
+                    triangleCount
+                    boundingBox {
+                        max { x y z }
+                    }
+                    openEdges
+                    misorientedEdges
+                    isManifold
+                }
+            }
+        "#,
+    )
+    .variables(Variables::from_json(json!({ "upload": null })));
+    req.set_upload("variables.upload", upload("model.obj", OPEN_TETRAHEDRON)?);
+
+    let data = server.execute(req).await?;
+
+    assert_eq!(
+        data["analyzeModel"],
+        json!({
+            "triangleCount": 3,
+            "boundingBox": { "max": { "x": 1.0, "y": 1.0, "z": 1.0 } },
+            "openEdges": 3,
+            "misorientedEdges": 0,
+            "isManifold": false,
+        })
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn models_are_repaired_before_slicing() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    // Flip the bottom face as well
+    let model = OPEN_TETRAHEDRON.replace("f 1 3 2", "f 1 2 3");
+
+    let job = server
+        .create_job_with_uploads(
+            json!({
+                "src": null,
+                "config": null,
+                "engineURL": TEST_ENGINE_URL,
+                "repairModels": true,
+            }),
+            &[("src", "model.obj", &model), ("config", "config.ini", "")],
+        )
+        .await?;
+
+    let report = &job["modelReports"][0];
+    assert_eq!(report["name"], "model.obj");
+    assert_eq!(report["analysis"]["problems"].as_array().unwrap().len(), 2);
+    assert_eq!(
+        report["repaired"],
+        json!({ "isManifold": true, "problems": [] })
+    );
+
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(gcode.matches("\nf ").count(), 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn unreadable_models_are_sliced_unless_repairs_are_requested() -> Result<()> {
+    let server = TestServer::new().await?;
+    let model = "solid broken\nvertex 1 2\nendsolid broken\n";
+
+    let job = server
+        .create_job_with_uploads(
+            json!({ "src": null, "config": null, "engineURL": TEST_ENGINE_URL }),
+            &[("src", "model.stl", model), ("config", "config.ini", "")],
+        )
+        .await?;
+
+    let report = &job["modelReports"][0];
+    assert_eq!(report["analysis"], json!(null));
+    assert!(report["error"]
+        .as_str()
+        .unwrap()
+        .contains("Unable to read model.stl"));
+
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+    let (status, _) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let result = server
+        .create_job_with_uploads(
+            json!({
+                "src": null,
+                "config": null,
+                "engineURL": TEST_ENGINE_URL,
+                "repairModels": true,
+            }),
+            &[("src", "model.stl", model), ("config", "config.ini", "")],
+        )
+        .await;
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("Unable to read model.stl"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn models_larger_than_the_printer_are_rejected() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let config = "nozzle_diameter = 0.4\nbed_shape = 0x0,100x0,100x100,0x100\nlayer_height = 0.2\nmax_print_height = 50\n";
+
+    let result = server
+        .create_job_with_uploads(
+            json!({
+                "src": null,
+                "config": null,
+                "engineURL": "https://github.com/prusa3d/PrusaSlicer/releases/tag/version_2.5.0",
+            }),
+            &[
+                (
+                    "src",
+                    "model.obj",
+                    "v 0 0 0\nv 150 0 0\nv 0 10 60\nf 1 2 3\n",
+                ),
+                ("config", "config.ini", config),
+            ],
+        )
+        .await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("model.obj is too large for the printer: X by 50.00mm, Z by 10.00mm"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn belt_printers_accept_models_of_any_length() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let config = "machine_width = 100\nmachine_height = 100\n";
+
+    let result = server
+        .create_job_with_uploads(
+            json!({ "src": null, "config": null, "engineURL": BELT_ENGINE_URL }),
+            &[
+                (
+                    "src",
+                    "model.obj",
+                    "v 0 0 0\nv 150 0 0\nv 0 1000 10\nf 1 2 3\n",
+                ),
+                ("config", "config.ini", config),
+            ],
+        )
+        .await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("model.obj is too large for the printer: X by 50.00mm"));
+    assert!(!err.contains("Y by"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn completed_jobs_have_previews() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    // The test engine copies the config into the GCode, so the config's lines after the first
+    // become the GCode's moves
+    let config = "\nG1 Z0.2\nG1 X10 Y0 E1\nG1 X10 Y10 E2\nG1 Z0.4\nG1 X0 Y10 E3\n";
+
+    let job = server
+        .create_job_with_uploads(
+            json!({ "src": null, "config": null, "engineURL": TEST_ENGINE_URL }),
+            &[
+                ("src", "model.obj", OPEN_TETRAHEDRON),
+                ("config", "config.ini", config),
+            ],
+        )
+        .await?;
+    let completed_job = server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, thumbnail) = server
+        .get_bytes(completed_job["thumbnailUrl"].as_str().unwrap(), true)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(thumbnail.starts_with(b"\x89PNG"));
+
+    let (status, layers) = server
+        .get(completed_job["layersUrl"].as_str().unwrap(), true)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&layers)?,
+        json!({
+            "layers": [
+                { "z": 0.2, "paths": [[0.0, 0.0, 10.0, 0.0, 10.0, 10.0]] },
+                { "z": 0.4, "paths": [[10.0, 10.0, 0.0, 10.0]] },
+            ],
+        })
+    );
+
+    let (_, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert!(gcode.starts_with("; generated by test_engine\n;\n; thumbnail begin 16x16 "));
+    assert!(gcode.contains("; thumbnail begin 220x124 "));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn gcode_is_post_processed() -> Result<()> {
+    let server = TestServer::with_config_toml(
+        r#"
+            [[post_processors]]
+            type = "header"
+            comments = ["Post-processed"]
+
+            [[post_processors]]
+            type = "replace"
+            pattern = "^G28$"
+            replacement = "G28 X Y"
+
+            [[post_processors]]
+            type = "progress"
+
+            [[post_processors]]
+            type = "pause_at_layer"
+            layers = [2, 50]
+        "#,
+    )
+    .await?;
+
+    // Two layers of moves, copied into the GCode by the test engine
+    let config = "\nG1 Z0.2\nG1 X10 Y0 E1\nG1 Z0.4\nG1 X0 Y0 E2\n";
+
+    let job = server
+        .create_job_with_uploads(
+            json!({ "src": null, "config": null, "engineURL": TEST_ENGINE_URL }),
+            &[
+                ("src", "model.stl", MODEL),
+                ("config", "config.ini", config),
+            ],
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.starts_with("; generated by test_engine\n; Post-processed\n"));
+    assert!(gcode.contains("M73 P0\nG1 X10 Y0 E1\nG1 Z0.4\nM73 P50\nM600\nG1 X0 Y0 E2\n"));
+    assert!(gcode.ends_with("G28 X Y\nM73 P100\n"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn post_processor_failures_error_the_job() -> Result<()> {
+    let server = TestServer::with_config_toml(
+        r#"
+            [[post_processors]]
+            name = "firmware-fixes"
+            type = "command"
+            command = "/bin/false"
+        "#,
+    )
+    .await?;
+
+    let job = server.create_job(TEST_ENGINE_URL).await?;
+    let errored_job = server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let message = errored_job["error"]["message"].as_str().unwrap();
+    assert!(message.starts_with("Post-processor firmware-fixes failed"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn gcode_can_be_delivered_gzipped() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server
+        .create_job_with(
+            json!({ "engineURL": TEST_ENGINE_URL, "outputFormat": "GCODE_GZ" }),
+            Some(""),
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, headers, body) = server
+        .get_with_headers(job["gcodeUrl"].as_str().unwrap(), true)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "application/gzip");
+    assert_eq!(
+        headers[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"model.gcode.gz\""
+    );
+
+    let mut gcode = String::new();
+    async_compression::tokio::bufread::GzipDecoder::new(&body[..])
+        .read_to_string(&mut gcode)
+        .await?;
+    assert!(gcode.starts_with("; generated by test_engine"));
+    assert!(gcode.contains(MODEL));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn gcode_downloads_can_be_resumed() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server.create_job(TEST_ENGINE_URL).await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+    let gcode_url = job["gcodeUrl"].as_str().unwrap();
+
+    let (_, headers, gcode) = server.get_with_headers(gcode_url, true).await?;
+    let len = gcode.len();
+
+    let (status, range_headers, body) = server
+        .get_with_request_headers(gcode_url, &[(header::RANGE, "bytes=10-")])
+        .await?;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        range_headers[header::CONTENT_RANGE],
+        format!("bytes 10-{}/{}", len - 1, len)
+    );
+    assert_eq!(body, gcode[10..]);
+
+    // Ranges are ignored if the file has changed since the client's copy
+    let (status, _, body) = server
+        .get_with_request_headers(
+            gcode_url,
+            &[
+                (header::RANGE, "bytes=10-"),
+                (header::IF_RANGE, "\"stale\""),
+            ],
+        )
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, gcode);
+
+    let (status, range_headers, _) = server
+        .get_with_request_headers(gcode_url, &[(header::RANGE, "bytes=100000-")])
+        .await?;
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        range_headers[header::CONTENT_RANGE],
+        format!("bytes */{len}")
+    );
+
+    let etag = headers[header::ETAG].to_str()?;
+    let (status, _, body) = server
+        .get_with_request_headers(gcode_url, &[(header::IF_NONE_MATCH, etag)])
+        .await?;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert!(body.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn gcode_is_only_downloadable_once_the_job_completes() -> Result<()> {
+    // The test client key cannot start any jobs so the job stays in the queue
+    let server = TestServer::with_config_toml("[limits]\nmax_concurrent_jobs = 0\n").await?;
+
+    let job = server.create_job(TEST_ENGINE_URL).await?;
+
+    let (status, _) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _) = server.get(&routes::job_gcode("not_a_job"), true).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn gcode_can_be_converted_to_binary_gcode_and_3mf() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server
+        .create_job_with(
+            json!({ "engineURL": TEST_ENGINE_URL, "outputFormat": "BGCODE" }),
+            Some(""),
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, headers, bgcode) = server
+        .get_with_headers(job["gcodeUrl"].as_str().unwrap(), true)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "application/x-bgcode");
+    assert!(bgcode.starts_with(b"GCDE"));
+    // GCode blocks are not compressed
+    assert!(String::from_utf8_lossy(&bgcode).contains("G28\n"));
+
+    let job = server
+        .create_job_with(
+            json!({ "engineURL": TEST_ENGINE_URL, "outputFormat": "GCODE_3MF" }),
+            Some(""),
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, headers, three_mf) = server
+        .get_with_headers(job["gcodeUrl"].as_str().unwrap(), true)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "model/3mf");
+
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(three_mf))?;
+
+    let mut gcode = String::new();
+    archive
+        .by_name("Metadata/plate_1.gcode")?
+        .read_to_string(&mut gcode)?;
+    assert!(gcode.contains(MODEL));
+
+    let mut md5 = String::new();
+    archive
+        .by_name("Metadata/plate_1.gcode.md5")?
+        .read_to_string(&mut md5)?;
+    assert_eq!(md5, format!("{:X}", md5::compute(&gcode)));
+
+    Ok(())
+}
+
+/// A 3MF containing a single object made of two triangles, each in a separate volume
+fn two_volume_3mf() -> Result<Vec<u8>> {
+    use crate::mesh::three_mf::{write_scene, SceneObject, SceneVolume};
+    use crate::mesh::Mesh;
+    use cgmath::Point3;
+
+    let triangle = |z: f32| Mesh {
+        vertices: vec![
+            Point3::new(0.0, 0.0, z),
+            Point3::new(10.0, 0.0, z),
+            Point3::new(0.0, 10.0, z),
+        ],
+        triangles: vec![[0, 1, 2]],
+    };
+    let (lower, upper) = (triangle(0.0), triangle(5.0));
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("multi.3mf");
+
+    write_scene(
+        &path,
+        &[SceneObject {
+            name: "multi",
+            volumes: vec![
+                SceneVolume {
+                    mesh: &lower,
+                    extruder: None,
+                },
+                SceneVolume {
+                    mesh: &upper,
+                    extruder: None,
+                },
+            ],
+            settings: &[],
+        }],
+    )?;
+
+    Ok(std::fs::read(path)?)
+}
+
+#[tokio::test]
+async fn extruders_are_assigned_to_objects_and_volumes() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let config = "nozzle_diameter = 0.4,0.4,0.4\ntemperature = 210\n";
+
+    let job = server
+        .create_job_with_uploads(
+            json!({
+                "objects": [{ "src": null }, { "src": null }],
+                "config": null,
+                "engineURL": TEST_3MF_ENGINE_URL,
+                "extruderAssignments": [
+                    { "object": 0, "extruder": 2 },
+                    { "object": 1, "volume": 1, "extruder": 3 },
+                ],
+                "extruders": [
+                    {
+                        "extruder": 2,
+                        "settingOverrides": [{ "key": "temperature", "value": "240" }],
+                    },
+                ],
+            }),
+            &[
+                (
+                    "objects.0.src",
+                    "part.obj",
+                    b"v 0 0 0\nv 20 0 0\nv 0 10 0\nf 1 2 3\n".to_vec(),
+                ),
+                ("objects.1.src", "multi.3mf", two_volume_3mf()?),
+                ("config", "config.ini", config.as_bytes().to_vec()),
+            ],
+        )
+        .await?;
+    server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    let (status, gcode) = server.get(job["gcodeUrl"].as_str().unwrap(), true).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert!(gcode.contains("temperature = 210,240\n"));
+    assert!(gcode.contains(r#"<metadata type="object" key="extruder" value="2"/>"#));
+    assert!(gcode.contains(r#"<volume firstid="0" lastid="0">"#));
+    assert!(gcode.contains(r#"<volume firstid="1" lastid="1">"#));
+    assert_eq!(gcode.matches(r#"key="extruder" value="3""#).count(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn extruder_assignments_are_checked_against_the_profile() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let config =
+        "nozzle_diameter = 0.4,0.4\nbed_shape = 0x0,100x0,100x100,0x100\nlayer_height = 0.2\n";
+
+    let result = server
+        .create_job_with_uploads(
+            json!({
+                "src": null,
+                "config": null,
+                "engineURL": "https://github.com/prusa3d/PrusaSlicer/releases/tag/version_2.5.0",
+                "extruderAssignments": [{ "extruder": 3 }],
+            }),
+            &[
+                ("src", "model.stl", MODEL),
+                ("config", "config.ini", config),
+            ],
+        )
+        .await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("The profile does not have an extruder 3"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn filament_usage_is_reported_per_extruder() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    // Moves copied into the GCode by the test engine, including a retraction on the second tool
+    let config = "\nT0\nG1 X10 E5\nT1\nG92 E0\nG1 X20 E8\nG1 E7\nG1 E8\n";
+
+    let job = server
+        .create_job_with_uploads(
+            json!({ "src": null, "config": null, "engineURL": TEST_ENGINE_URL }),
+            &[
+                ("src", "model.stl", MODEL),
+                ("config", "config.ini", config),
+            ],
+        )
+        .await?;
+    let completed_job = server.wait_for_job(job["id"].as_str().unwrap()).await?;
+
+    assert_eq!(
+        completed_job["statistics"]["extruders"],
+        json!([
+            { "extruder": 1, "filamentUsedMm": 5.0 },
+            { "extruder": 2, "filamentUsedMm": 8.0 },
+        ])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn only_waiting_jobs_can_be_reprioritized() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server
+        .create_job_with(
+            json!({ "engineURL": TEST_ENGINE_URL, "priority": 3 }),
+            Some(""),
+        )
+        .await?;
+    let job_id = job["id"].as_str().unwrap();
+
+    let completed_job = server.wait_for_job(job_id).await?;
+    assert_eq!(completed_job["priority"], 3);
+    assert_eq!(completed_job["queuePosition"], serde_json::Value::Null);
+    assert_eq!(completed_job["estimatedStartTime"], serde_json::Value::Null);
+
+    let req = async_graphql::Request::new(
+        r#"
+            mutation($id: ID!) {
+                setJobPriority(input: { id: $id, priority: 10 }) { id }
+            }
+        "#,
+    )
+    .variables(Variables::from_json(json!({ "id": job_id })));
+
+    let err = server.execute(req).await.unwrap_err().to_string();
+    assert!(err.contains("Only waiting jobs can be moved in the queue"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_key_limits_are_enforced() -> Result<()> {
+    let server = TestServer::with_config_toml("[limits]\nmax_jobs_per_day = 1\n").await?;
+
+    server.create_job(TEST_ENGINE_URL).await?;
+
+    let err = server
+        .create_job(TEST_ENGINE_URL)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("QUOTA_EXCEEDED"));
+    assert!(err.contains("max_jobs_per_day"));
+
+    let server = TestServer::with_config_toml("[limits]\nmax_upload_bytes = 8\n").await?;
+
+    let err = server
+        .create_job(TEST_ENGINE_URL)
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("QUOTA_EXCEEDED"));
+    assert!(err.contains("max_upload_bytes"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn uploads_over_the_client_key_limit_are_cut_off() -> Result<()> {
+    let server = TestServer::with_config_toml("[limits]\nmax_upload_bytes = 8\n").await?;
+
+    let padding = " ".repeat(128 * 1024);
+    let body = json!({ "query": format!("{{ engines {{ id }} }}{padding}") }).to_string();
+
+    let req = Request::post(routes::GRAPHQL)
+        .header(header::AUTHORIZATION, &server.bearer)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))?;
+
+    let res = server.router.clone().oneshot(req).await?;
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let body = String::from_utf8(body.to_vec())?;
+
+    assert!(body.contains("QUOTA_EXCEEDED"));
+    assert!(body.contains("max_upload_bytes"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn jwts_must_expire_within_the_max_token_lifetime() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let mut claims = jwt_claims(Duration::from_mins(5));
+    claims.expires_at = None;
+    let never_expires = bearer_token(&server.key_pair, claims)?;
+    let expires_tomorrow = bearer_token(&server.key_pair, jwt_claims(Duration::from_days(1)))?;
+
+    let (status, _, _) = server
+        .get_with_bearer(routes::GRAPHQL, Some(&never_expires))
+        .await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, _) = server
+        .get_with_bearer(routes::GRAPHQL, Some(&expires_tomorrow))
+        .await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, _) = server
+        .get_with_bearer(routes::GRAPHQL, Some(&server.bearer))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn revocations_take_effect_without_a_restart() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let revoked_token = bearer_token(
+        &server.key_pair,
+        jwt_claims(Duration::from_mins(5)).with_jwt_id("revoked_token"),
+    )?;
+
+    let (status, _, _) = server
+        .get_with_bearer(routes::GRAPHQL, Some(&revoked_token))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    // Token ids are only revoked for the key that signed them
+    let mut revocations = Revocations {
+        tokens: BTreeMap::from([("other_key".to_owned(), vec!["revoked_token".to_owned()])]),
+        ..Default::default()
+    };
+    revocations.save(&server.revocations_path).await?;
+
+    let (status, _, _) = server
+        .get_with_bearer(routes::GRAPHQL, Some(&revoked_token))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    revocations.tokens =
+        BTreeMap::from([(server.key_id.clone(), vec!["revoked_token".to_owned()])]);
+    revocations.save(&server.revocations_path).await?;
+
+    let (status, _, _) = server
+        .get_with_bearer(routes::GRAPHQL, Some(&revoked_token))
+        .await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = server.get(routes::GRAPHQL, true).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    revocations.keys.push(server.key_id.clone());
+    revocations.save(&server.revocations_path).await?;
+
+    let (status, _) = server.get(routes::GRAPHQL, true).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn retired_key_pairs_are_rejected_without_a_restart() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let (status, _) = server.get(routes::GRAPHQL, true).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let public_key_pem = server
+        .key_pair
+        .public_key()
+        .to_pem()
+        .map_err(|_| eyre!("Failed to generate PEM for client public key"))?;
+
+    let revocations = Revocations {
+        public_keys: vec![public_key_pem],
+        ..Default::default()
+    };
+    revocations.save(&server.revocations_path).await?;
+
+    let (status, _) = server.get(routes::GRAPHQL, true).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rotated_keys_take_effect_without_a_restart() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let old_token = bearer_token(&server.key_pair, jwt_claims(Duration::from_mins(5)))?;
+
+    let public_key_pem = server
+        .key_pair
+        .public_key()
+        .to_pem()
+        .map_err(|_| eyre!("Failed to generate PEM for client public key"))?;
+
+    let mut config = Config::default();
+    config.authorized_keys.insert(
+        server.key_id.clone(),
+        ClientKey {
+            id: server.key_id.clone(),
+            label: "test".to_owned(),
+            public_key_pem,
+            created_at: None,
+            expires_at: None,
+            scopes: Scope::all(),
+            limits: ClientLimits::default(),
+        },
+    );
+
+    // Rotate the key the same way as `slicing-server keys rotate`
+    let server_keys = KeyManager::load_or_create(server.config_dir.path()).await?;
+    let (invite_token, retired_public_key_pem, _) =
+        config.rotate_key(&server_keys, &server.key_id, None)?;
+    fs::write(&server.config_path, toml::to_string_pretty(&config)?).await?;
+
+    let revocations = Revocations {
+        public_keys: vec![retired_public_key_pem],
+        ..Default::default()
+    };
+    revocations.save(&server.revocations_path).await?;
+
+    let invite = InviteToken::decode(&invite_token)?;
+    let new_key_pair = ES256KeyPair::from_pem(&invite.sk)
+        .map_err(|_| eyre!("Invalid invite token signing key"))?
+        .with_key_id(&invite.id);
+    let new_token = bearer_token(&new_key_pair, jwt_claims(Duration::from_mins(5)))?;
+
+    let (status, _, _) = server
+        .get_with_bearer(routes::GRAPHQL, Some(&new_token))
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, _) = server
+        .get_with_bearer(routes::GRAPHQL, Some(&old_token))
+        .await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn resolvers_require_scopes() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let job = server.create_job(TEST_ENGINE_URL).await?;
+    let job_id = job["id"].as_str().unwrap();
+
+    let job_query = || {
+        async_graphql::Request::new("query($id: ID!) { job(input: { id: $id }) { id } }")
+            .variables(Variables::from_json(json!({ "id": job_id })))
+    };
+
+    let err = server
+        .execute_as(
+            async_graphql::Request::new("{ profiles { id } }"),
+            &server.key_id,
+            vec![Scope::ReadOwnJobs],
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Forbidden: requires the submit_jobs or manage_profiles scope"));
+
+    // Profiles can only be stored by keys that are granted manage_profiles explicitly
+    let err = server
+        .execute_as(
+            async_graphql::Request::new("mutation { deleteProfile(input: { id: \"1\" }) }"),
+            &server.key_id,
+            Scope::defaults(),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Forbidden: requires the manage_profiles scope"));
+
+    // Jobs can be read by the key that created them, or by keys that can read every job
+    server
+        .execute_as(job_query(), &server.key_id, vec![Scope::ReadOwnJobs])
+        .await?;
+    server
+        .execute_as(job_query(), "other_key", vec![Scope::ReadAllJobs])
+        .await?;
+
+    let err = server
+        .execute_as(job_query(), "other_key", vec![Scope::ReadOwnJobs])
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Job not found"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn jobs_are_only_available_to_their_client_key() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    let req = create_job_request(
+        json!({ "engineURL": TEST_ENGINE_URL, "src": null, "config": null }),
+        &[("src", "model.stl", MODEL), ("config", "config.ini", "")],
+    )?;
+    let mut data = server
+        .execute_as(req, "other_key", Scope::defaults())
+        .await?;
+    let job = data["createJob"].take();
+    let job_id = job["id"].as_str().unwrap();
+    let gcode_url = job["gcodeUrl"].as_str().unwrap();
+
+    server.wait_for_job(job_id).await?;
+
+    // The test key can only download it's own jobs with a token limited to read_own_jobs
+    let mut claims = jwt_claims(Duration::from_mins(5));
+    claims.custom.scopes = Some(vec![Scope::ReadOwnJobs]);
+    let read_own_jobs = bearer_token(&server.key_pair, claims)?;
+
+    let (status, _, _) = server
+        .get_with_bearer(gcode_url, Some(&read_own_jobs))
+        .await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = server.get(gcode_url, true).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    // Signed URLs are not tied to a client key
+    let (status, _) = server
+        .get(job["signedGcodeUrl"].as_str().unwrap(), false)
+        .await?;
+    assert_eq!(status, StatusCode::OK);
+
+    // Jobs can only be modified by the key that created them or an admin
+    let cancel_job = || {
+        async_graphql::Request::new("mutation($id: ID!) { cancelJob(input: { id: $id }) { id } }")
+            .variables(Variables::from_json(json!({ "id": job_id })))
+    };
+
+    let err = server
+        .execute_as(cancel_job(), &server.key_id, Scope::defaults())
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Job not found"));
+
+    let err = server
+        .execute_as(
+            cancel_job(),
+            &server.key_id,
+            vec![Scope::SubmitJobs, Scope::ReadAllJobs],
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Forbidden"));
+
+    // The job has completed so even an admin cannot cancel it
+    let err = server
+        .execute_as(cancel_job(), &server.key_id, vec![Scope::Admin])
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("Only waiting or running jobs can be cancelled"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn expired_client_keys_are_rejected() -> Result<()> {
+    let server = TestServer::with_config_toml("expires_at = \"2020-01-01T00:00:00Z\"\n").await?;
+
+    let (status, _) = server.get(routes::GRAPHQL, true).await?;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn client_key_usage_is_recorded() -> Result<()> {
+    let server = TestServer::new().await?;
+
+    assert_eq!(server.key_usage.last_used(&server.key_id), None);
+
+    let (status, _) = server.get(routes::GRAPHQL, true).await?;
+    assert_eq!(status, StatusCode::OK);
+
+    let last_used = server.key_usage.last_used(&server.key_id);
+    assert!(last_used.is_some());
+
+    server.key_usage.flush().await?;
+
+    let usage = KeyUsage::load(&server.key_usage_path).await?;
+    assert_eq!(usage.last_used.get(&server.key_id).copied(), last_used);
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use eyre::eyre;
+use eyre::Result;
+use jwt_simple::prelude::*;
+
+/// The query parameter that signed URLs carry their token in
+pub const SIGNED_URL_TOKEN_PARAM: &'static str = "token";
+
+/// How long a signed URL remains valid after it is issued
+const SIGNED_URL_TTL_MINS: u64 = 15;
+
+#[derive(Serialize, Deserialize)]
+struct SignedUrlClaims {
+    path: String,
+}
+
+/// Issues and verifies short-lived URLs that grant access to a single path without a client JWT,
+/// eg. for printers downloading GCode.
+///
+/// The signing key is generated on startup so signed URLs do not outlive the server process (nor
+/// the in-memory jobs they point to).
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: HS256Key,
+}
+
+impl UrlSigner {
+    pub fn new() -> Self {
+        Self {
+            key: HS256Key::generate(),
+        }
+    }
+
+    pub fn sign(&self, path: &str) -> Result<String> {
+        let claims = Claims::with_custom_claims(
+            SignedUrlClaims {
+                path: path.to_owned(),
+            },
+            Duration::from_mins(SIGNED_URL_TTL_MINS),
+        );
+
+        let token = self
+            .key
+            .authenticate(claims)
+            .map_err(|_| eyre!("Unable to sign URL"))?;
+
+        Ok(format!("{path}?{SIGNED_URL_TOKEN_PARAM}={token}"))
+    }
+
+    pub fn verify(&self, path: &str, token: &str) -> Result<()> {
+        let options = VerificationOptions {
+            time_tolerance: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+
+        let claims = self
+            .key
+            .verify_token::<SignedUrlClaims>(token, Some(options))
+            .map_err(|_| eyre!("Invalid or expired signed URL"))?;
+
+        if claims.custom.path != path {
+            return Err(eyre!("Signed URL is not valid for this path"));
+        }
+
+        Ok(())
+    }
+}